@@ -0,0 +1,844 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use reqwest::Client;
+use roxmltree::Node;
+use tauri::AppHandle;
+
+use super::db::DownloadDb;
+use super::error::DownloadError;
+use super::file_store;
+use super::hls::{self, AudioRendition, InitSection, SegmentJob, SegmentProgress, StreamPlan};
+use super::http::HttpClient;
+use super::manager::commit_complete;
+use super::probe::{ProbeVariant, StreamProbe};
+use super::quality::{self, QualityTargets, VariantTraits};
+use super::tasks::TaskHandle;
+use super::throttle::Throttle;
+
+/// Upper bound on segments generated for one representation, against
+/// malformed templates.
+const MAX_SEGMENTS: u64 = 200_000;
+
+/// A representation chosen for download, with the adaptation set it belongs to.
+#[derive(Clone, Copy)]
+struct Selection<'a, 'input> {
+    set: Node<'a, 'input>,
+    rep: Node<'a, 'input>,
+}
+
+/// Download an MPEG-DASH stream given its MPD URL.
+/// Video and audio representations are fetched like HLS streams and merged
+/// into one fragmented MP4.
+#[allow(clippy::too_many_arguments)]
+pub async fn download_dash(
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    task: &TaskHandle,
+    id: &str,
+    profile_id: &str,
+    title: &str,
+    manifest_url: &str,
+    quality_pref: &str,
+    targets: &QualityTargets,
+    segment_concurrency: Option<i64>,
+    audio_languages: &[String],
+    variant_id: Option<&str>,
+    client: &HttpClient,
+    throttle: &Throttle,
+) -> Result<(), DownloadError> {
+    // ── 1. Fetch and parse the manifest ───────────────────────────────────────
+    let manifest = fetch_manifest(client, manifest_url).await?;
+    let doc = roxmltree::Document::parse(&manifest)
+        .map_err(|e| format!("Failed to parse DASH manifest: {e}"))?;
+    let mpd = checked_root(&doc)?;
+
+    let (period, period_secs) = pick_period(mpd)?;
+    let period_base = with_base(&with_base(manifest_url, mpd), period);
+
+    // ── 2. Pick representations ───────────────────────────────────────────────
+    let video = select_video(period, variant_id, targets, quality_pref)?;
+    let audio = if content_type(video.set, video.rep) == "video" {
+        select_audio(period, audio_languages)
+    } else {
+        // Audio-only manifest: the main stream already is the audio
+        Vec::new()
+    };
+    for selection in std::iter::once(&video).chain(audio.iter()) {
+        if has_child(selection.set, "ContentProtection")
+            || has_child(selection.rep, "ContentProtection")
+        {
+            return Err(
+                "DASH stream is DRM-protected (ContentProtection) and cannot be downloaded".into(),
+            );
+        }
+    }
+
+    // ── 3. Resolve segments ───────────────────────────────────────────────────
+    let mut plans = vec![StreamPlan {
+        state_key: id.to_string(),
+        jobs: Vec::new(),
+        fingerprint: String::new(),
+        part_path: file_store::part_file_path(app, profile_id, id),
+    }];
+    for i in 0..audio.len() {
+        plans.push(StreamPlan {
+            state_key: format!("{id}#audio{i}"),
+            jobs: Vec::new(),
+            fingerprint: String::new(),
+            part_path: file_store::audio_file_path(app, profile_id, id, i, "zentrio-part"),
+        });
+    }
+    let mut renditions: Vec<AudioRendition> = Vec::with_capacity(audio.len());
+    let selections = std::iter::once(&video).chain(audio.iter());
+    for (i, (plan, selection)) in plans.iter_mut().zip(selections).enumerate() {
+        let base = with_base(&with_base(&period_base, selection.set), selection.rep);
+        plan.jobs =
+            representation_jobs(client, throttle, &base, period, selection, period_secs).await?;
+        if plan.jobs.is_empty() {
+            return Err("DASH representation contained no segments".into());
+        }
+        plan.fingerprint = jobs_fingerprint(&plan.jobs);
+        if i > 0 {
+            renditions.push(AudioRendition {
+                language: selection.set.attribute("lang").map(str::to_string),
+                name: audio_name(selection),
+                default: role(selection.set) == Some("main"),
+                media_url: base,
+            });
+        }
+    }
+
+    // ── 4. Download each representation into its part file ──────────────────
+    let concurrency = hls::concurrency_level(segment_concurrency);
+    let mut progress = SegmentProgress::new(plans.iter().map(|p| p.jobs.len()).sum());
+    for plan in &plans {
+        let finished = hls::fetch_stream(
+            app,
+            db,
+            task,
+            client,
+            throttle,
+            id,
+            title,
+            plan,
+            concurrency,
+            &mut progress,
+        )
+        .await?;
+        if !finished {
+            return Ok(());
+        }
+    }
+
+    // ── 5. Merge video and audio into one MP4 ─────────────────────────────────
+    let audio_parts: Vec<(&AudioRendition, &Path)> = renditions
+        .iter()
+        .zip(plans[1..].iter().map(|p| p.part_path.as_path()))
+        .collect();
+    let output =
+        hls::finalize_output(app, task, profile_id, id, &plans[0].part_path, &audio_parts).await?;
+    let committed = commit_complete(app, db, task, id, title, &output.moves, &output.path, |d| {
+        d.clear_hls_state(id).ok();
+        if let Some(json) = &output.audio_tracks {
+            d.update_audio_tracks(id, json).ok();
+        }
+    });
+    output.settle(matches!(committed, Ok(true)));
+    committed?;
+
+    Ok(())
+}
+
+/// Lists the video representations of an MPD for `download_probe`, with
+/// size estimates and the one the quality targets pick.
+pub async fn probe_dash(
+    client: &Client,
+    manifest_url: &str,
+    targets: &QualityTargets,
+    quality_pref: &str,
+) -> Result<StreamProbe, String> {
+    let manifest = fetch_manifest(client, manifest_url)
+        .await
+        .map_err(|e| e.to_string())?;
+    let doc = roxmltree::Document::parse(&manifest)
+        .map_err(|e| format!("Failed to parse DASH manifest: {e}"))?;
+    let mpd = checked_root(&doc)?;
+    let (period, period_secs) = pick_period(mpd)?;
+
+    let selected = select_video(period, None, targets, quality_pref)?;
+    let is_video = content_type(selected.set, selected.rep) == "video";
+    // Separate audio adds to every video representation's size
+    let audio_bandwidth = if is_video {
+        select_audio(period, &[])
+            .first()
+            .map(|a| bandwidth(a.rep))
+            .unwrap_or(0)
+    } else {
+        0
+    };
+    let kind = if is_video { "video" } else { "audio" };
+    let mut variants: Vec<ProbeVariant> = representations(period, kind)
+        .iter()
+        .map(|c| {
+            let attr = |name: &str| c.rep.attribute(name).or_else(|| c.set.attribute(name));
+            let traits = representation_traits(c);
+            ProbeVariant {
+                id: c.rep.attribute("id").unwrap_or_default().to_string(),
+                width: attr("width").and_then(|w| w.parse().ok()),
+                height: attr("height").and_then(|h| h.parse().ok()),
+                codecs: attr("codecs").map(str::to_string),
+                bandwidth: bandwidth(c.rep),
+                frame_rate: attr("frameRate").and_then(parse_frame_rate),
+                estimated_size: period_secs
+                    .map(|secs| ((bandwidth(c.rep) + audio_bandwidth) as f64 / 8.0 * secs) as i64),
+                hdr: traits.hdr,
+                playable: targets.can_decode(&traits),
+            }
+        })
+        .collect();
+    variants.sort_by_key(|v| std::cmp::Reverse(v.bandwidth));
+
+    let selected_id = selected.rep.attribute("id").map(str::to_string);
+    let size = variants
+        .iter()
+        .find(|v| Some(&v.id) == selected_id.as_ref())
+        .and_then(|v| v.estimated_size);
+    Ok(StreamProbe {
+        kind: "dash".into(),
+        container: Some("fmp4".into()),
+        content_type: Some("application/dash+xml".into()),
+        size,
+        size_exact: false,
+        accept_ranges: None,
+        resumable: true,
+        duration_secs: period_secs,
+        variants,
+        selected_variant: selected_id,
+    })
+}
+
+// ─── Manifest helpers ────────────────────────────────────────────────────────
+
+async fn fetch_manifest(client: &Client, manifest_url: &str) -> Result<String, DownloadError> {
+    Ok(client
+        .get(manifest_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?)
+}
+
+/// Returns the MPD element, rejecting other documents and live manifests.
+fn checked_root<'a, 'input>(
+    doc: &'a roxmltree::Document<'input>,
+) -> Result<Node<'a, 'input>, String> {
+    let mpd = doc.root_element();
+    if mpd.tag_name().name() != "MPD" {
+        return Err("Not a DASH manifest (missing MPD element)".into());
+    }
+    if mpd.attribute("type") == Some("dynamic") {
+        return Err("Live DASH streams cannot be downloaded".into());
+    }
+    Ok(mpd)
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &'static str) -> Option<Node<'a, 'input>> {
+    children(node, name).next()
+}
+
+fn has_child(node: Node, name: &'static str) -> bool {
+    child(node, name).is_some()
+}
+
+/// Resolves the first `BaseURL` child of `node` against `base`.
+fn with_base(base: &str, node: Node) -> String {
+    child(node, "BaseURL")
+        .and_then(|b| b.text())
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .and_then(|t| hls::resolve_url(base, t))
+        .unwrap_or_else(|| base.to_string())
+}
+
+/// Picks the period to download and its duration in seconds. Manifests with
+/// several periods (e.g. inserted ads) yield the longest one.
+fn pick_period<'a, 'input>(
+    mpd: Node<'a, 'input>,
+) -> Result<(Node<'a, 'input>, Option<f64>), String> {
+    let periods: Vec<Node> = children(mpd, "Period").collect();
+    let total = mpd
+        .attribute("mediaPresentationDuration")
+        .and_then(parse_duration);
+    let start = |p: &Node| p.attribute("start").and_then(parse_duration);
+
+    let mut timed: Vec<(Node, Option<f64>)> = Vec::with_capacity(periods.len());
+    for (i, period) in periods.iter().enumerate() {
+        let duration = period
+            .attribute("duration")
+            .and_then(parse_duration)
+            .or_else(|| {
+                let begin = start(period).unwrap_or(0.0);
+                let end = periods.get(i + 1).and_then(start).or(total)?;
+                Some(end - begin)
+            });
+        timed.push((*period, duration));
+    }
+    if timed.len() > 1 {
+        log::warn!(
+            "[DASH] Manifest has {} periods; downloading the longest one",
+            timed.len()
+        );
+    }
+    timed
+        .into_iter()
+        .max_by(|a, b| a.1.unwrap_or(0.0).total_cmp(&b.1.unwrap_or(0.0)))
+        .ok_or_else(|| "DASH manifest has no periods".into())
+}
+
+/// Parses an ISO 8601 duration (`PT1H2M3.5S`, `P1DT2H`) into seconds.
+fn parse_duration(value: &str) -> Option<f64> {
+    let rest = value.trim().strip_prefix('P')?;
+    let (date, time) = rest.split_once('T').unwrap_or((rest, ""));
+    let mut seconds = 0.0;
+    for (part, units) in [
+        (
+            date,
+            &[
+                ('Y', 31_536_000.0),
+                ('M', 2_592_000.0),
+                ('W', 604_800.0),
+                ('D', 86_400.0),
+            ][..],
+        ),
+        (time, &[('H', 3600.0), ('M', 60.0), ('S', 1.0)][..]),
+    ] {
+        let mut number = String::new();
+        for c in part.chars() {
+            if c.is_ascii_digit() || c == '.' {
+                number.push(c);
+                continue;
+            }
+            let (_, scale) = units.iter().find(|(u, _)| *u == c)?;
+            seconds += number.parse::<f64>().ok()? * scale;
+            number.clear();
+        }
+    }
+    Some(seconds)
+}
+
+/// Parses a `frameRate` attribute (`25` or `30000/1001`).
+fn parse_frame_rate(value: &str) -> Option<f64> {
+    match value.split_once('/') {
+        Some((num, den)) => {
+            let den: f64 = den.trim().parse().ok()?;
+            (den > 0.0).then_some(num.trim().parse::<f64>().ok()? / den)
+        }
+        None => value.trim().parse().ok(),
+    }
+}
+
+fn mime_type<'a>(set: Node<'a, '_>, rep: Node<'a, '_>) -> &'a str {
+    rep.attribute("mimeType")
+        .or_else(|| set.attribute("mimeType"))
+        .unwrap_or("")
+}
+
+/// Returns "video", "audio", "text" etc. for a representation.
+fn content_type<'a>(set: Node<'a, '_>, rep: Node<'a, '_>) -> &'a str {
+    set.attribute("contentType")
+        .unwrap_or_else(|| mime_type(set, rep).split('/').next().unwrap_or(""))
+}
+
+fn bandwidth(rep: Node) -> u64 {
+    rep.attribute("bandwidth")
+        .and_then(|b| b.parse().ok())
+        .unwrap_or(0)
+}
+
+fn role<'a>(set: Node<'a, '_>) -> Option<&'a str> {
+    child(set, "Role").and_then(|r| r.attribute("value"))
+}
+
+/// MP4 representations of the period with the given content type.
+fn representations<'a, 'input>(period: Node<'a, 'input>, kind: &str) -> Vec<Selection<'a, 'input>> {
+    let mut found = Vec::new();
+    for set in children(period, "AdaptationSet") {
+        for rep in children(set, "Representation") {
+            if content_type(set, rep) == kind && mime_type(set, rep).ends_with("/mp4") {
+                found.push(Selection { set, rep });
+            }
+        }
+    }
+    found
+}
+
+/// Picks the representation pinned by `variant_id`, else the video
+/// representation for the quality targets, or the best audio
+/// representation for an audio-only manifest.
+fn select_video<'a, 'input>(
+    period: Node<'a, 'input>,
+    variant_id: Option<&str>,
+    targets: &QualityTargets,
+    quality_pref: &str,
+) -> Result<Selection<'a, 'input>, String> {
+    let mut candidates = representations(period, "video");
+    let audio_only = candidates.is_empty();
+    if audio_only {
+        candidates = representations(period, "audio");
+    }
+    if candidates.is_empty() {
+        let has_webm = children(period, "AdaptationSet")
+            .flat_map(|s| children(s, "Representation").map(move |r| mime_type(s, r)))
+            .any(|m| m.ends_with("/webm"));
+        return Err(if has_webm {
+            "Only MP4 DASH representations are supported (stream uses WebM)".into()
+        } else {
+            "DASH manifest has no downloadable representations".into()
+        });
+    }
+    if let Some(wanted) = variant_id {
+        if let Some(pinned) = candidates
+            .iter()
+            .find(|c| c.rep.attribute("id") == Some(wanted))
+        {
+            return Ok(*pinned);
+        }
+        log::warn!("[DASH] Representation {wanted} is not in the manifest; picking by quality");
+    }
+    if audio_only {
+        return quality::pick_by_bandwidth(&candidates, |c| bandwidth(c.rep), "best")
+            .copied()
+            .ok_or_else(|| "No suitable representation found".into());
+    }
+    quality::pick_variant_for(&candidates, representation_traits, targets, quality_pref).copied()
+}
+
+fn representation_traits(selection: &Selection) -> VariantTraits {
+    let attr = |name: &str| {
+        selection
+            .rep
+            .attribute(name)
+            .or_else(|| selection.set.attribute(name))
+    };
+    // CICP transfer characteristics 16 (PQ) and 18 (HLG) mark HDR
+    let hdr = [selection.rep, selection.set]
+        .iter()
+        .flat_map(|n| children(*n, "SupplementalProperty").chain(children(*n, "EssentialProperty")))
+        .any(|p| {
+            p.attribute("schemeIdUri") == Some("urn:mpeg:mpegB:cicp:TransferCharacteristics")
+                && matches!(p.attribute("value"), Some("16") | Some("18"))
+        });
+    let range = hdr.then_some("PQ");
+    VariantTraits::new(
+        attr("height").and_then(|h| h.parse().ok()),
+        attr("codecs"),
+        range,
+        attr("frameRate").and_then(parse_frame_rate),
+        bandwidth(selection.rep),
+    )
+}
+
+/// Picks one audio adaptation set per preferred language (or the main one),
+/// and its highest-bandwidth representation.
+fn select_audio<'a, 'input>(
+    period: Node<'a, 'input>,
+    languages: &[String],
+) -> Vec<Selection<'a, 'input>> {
+    let candidates = representations(period, "audio");
+    let best_of = |set: Node<'a, 'input>| {
+        candidates
+            .iter()
+            .filter(|c| c.set == set)
+            .max_by_key(|c| bandwidth(c.rep))
+            .map(|c| Selection { set, rep: c.rep })
+    };
+    let mut sets: Vec<Node> = Vec::new();
+    for c in &candidates {
+        if !sets.contains(&c.set) {
+            sets.push(c.set);
+        }
+    }
+
+    let primary = |tag: &str| {
+        tag.split(['-', '_'])
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase()
+    };
+    let mut selected: Vec<Node> = Vec::new();
+    for wanted in languages {
+        let pick = sets
+            .iter()
+            .find(|s| {
+                s.attribute("lang")
+                    .is_some_and(|l| l.eq_ignore_ascii_case(wanted))
+            })
+            .or_else(|| {
+                sets.iter().find(|s| {
+                    s.attribute("lang")
+                        .is_some_and(|l| primary(l) == primary(wanted))
+                })
+            });
+        if let Some(set) = pick {
+            if !selected.contains(set) {
+                selected.push(*set);
+            }
+        }
+    }
+    if selected.is_empty() {
+        selected.extend(
+            sets.iter()
+                .find(|s| role(**s) == Some("main"))
+                .or_else(|| sets.first()),
+        );
+    }
+    selected.into_iter().filter_map(best_of).collect()
+}
+
+fn audio_name(selection: &Selection) -> String {
+    child(selection.set, "Label")
+        .and_then(|l| l.text())
+        .or_else(|| selection.set.attribute("label"))
+        .or_else(|| selection.set.attribute("lang"))
+        .or_else(|| selection.rep.attribute("id"))
+        .unwrap_or("Audio")
+        .to_string()
+}
+
+// ─── Segment addressing ──────────────────────────────────────────────────────
+
+/// Builds the segment list of a representation from its SegmentTemplate,
+/// SegmentList or SegmentBase, inherited from the adaptation set and period.
+async fn representation_jobs(
+    client: &HttpClient,
+    throttle: &Throttle,
+    base: &str,
+    period: Node<'_, '_>,
+    selection: &Selection<'_, '_>,
+    period_secs: Option<f64>,
+) -> Result<Vec<SegmentJob>, DownloadError> {
+    // Nearest level first
+    let levels = [selection.rep, selection.set, period];
+    if levels.iter().any(|l| has_child(*l, "SegmentTemplate")) {
+        return Ok(template_jobs(&levels, base, period_secs)?);
+    }
+    if let Some(list) = levels.iter().find_map(|l| child(*l, "SegmentList")) {
+        return Ok(list_jobs(list, base)?);
+    }
+    let segment_base = levels.iter().find_map(|l| child(*l, "SegmentBase"));
+    base_jobs(client, throttle, base, segment_base).await
+}
+
+fn template_jobs(
+    levels: &[Node],
+    base: &str,
+    period_secs: Option<f64>,
+) -> Result<Vec<SegmentJob>, String> {
+    let attr = |name: &str| {
+        levels
+            .iter()
+            .find_map(|l| child(*l, "SegmentTemplate").and_then(|t| t.attribute(name)))
+    };
+    let number_attr = |name: &str, default: u64| {
+        attr(name)
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(default)
+    };
+    let media = attr("media").ok_or("SegmentTemplate has no media attribute")?;
+    let start_number = number_attr("startNumber", 1);
+    let timescale = number_attr("timescale", 1).max(1);
+    let time_offset = number_attr("presentationTimeOffset", 0);
+    let rep_id = levels[0].attribute("id").unwrap_or("");
+    let rep_bandwidth = bandwidth(levels[0]);
+    let period_end = period_secs.map(|s| time_offset + (s * timescale as f64) as u64);
+
+    // (number, time) of each segment
+    let mut segments: Vec<(u64, u64)> = Vec::new();
+    let timeline = levels
+        .iter()
+        .find_map(|l| child(*l, "SegmentTemplate").and_then(|t| child(t, "SegmentTimeline")));
+    if let Some(timeline) = timeline {
+        let entries: Vec<Node> = children(timeline, "S").collect();
+        let mut time = 0u64;
+        let mut number = start_number;
+        for (i, s) in entries.iter().enumerate() {
+            if let Some(t) = s.attribute("t").and_then(|t| t.parse().ok()) {
+                time = t;
+            }
+            let duration: u64 = s
+                .attribute("d")
+                .and_then(|d| d.parse().ok())
+                .filter(|d| *d > 0)
+                .ok_or("SegmentTimeline entry has no duration")?;
+            let repeat: i64 = s.attribute("r").and_then(|r| r.parse().ok()).unwrap_or(0);
+            let count = if repeat < 0 {
+                // Repeats until the next entry's start, or the end of the period
+                let end = entries
+                    .get(i + 1)
+                    .and_then(|n| n.attribute("t"))
+                    .and_then(|t| t.parse::<u64>().ok())
+                    .or(period_end)
+                    .ok_or("Open-ended SegmentTimeline in a period of unknown duration")?;
+                end.saturating_sub(time).div_ceil(duration)
+            } else {
+                repeat as u64 + 1
+            };
+            if segments.len() as u64 + count > MAX_SEGMENTS {
+                return Err("DASH representation has too many segments".into());
+            }
+            for _ in 0..count {
+                segments.push((number, time));
+                time += duration;
+                number += 1;
+            }
+        }
+    } else {
+        let duration = number_attr("duration", 0);
+        if duration == 0 {
+            return Err("SegmentTemplate has neither a SegmentTimeline nor a duration".into());
+        }
+        let secs = period_secs.ok_or("SegmentTemplate in a period of unknown duration")?;
+        let count = (secs * timescale as f64 / duration as f64).ceil() as u64;
+        if count > MAX_SEGMENTS {
+            return Err("DASH representation has too many segments".into());
+        }
+        segments.extend((0..count).map(|i| (start_number + i, time_offset + i * duration)));
+    }
+
+    let init = attr("initialization")
+        .map(|t| expand_template(t, rep_id, rep_bandwidth, start_number, 0))
+        .map(|url| {
+            hls::resolve_url(base, &url)
+                .ok_or_else(|| format!("Failed to resolve initialization URL: {url}"))
+        })
+        .transpose()?;
+
+    let mut jobs = Vec::with_capacity(segments.len());
+    for (i, (number, time)) in segments.into_iter().enumerate() {
+        let path = expand_template(media, rep_id, rep_bandwidth, number, time);
+        let url = hls::resolve_url(base, &path)
+            .ok_or_else(|| format!("Failed to resolve segment URL: {path}"))?;
+        jobs.push(plain_job(
+            url,
+            i as u64,
+            None,
+            if i == 0 { init.clone() } else { None },
+        ));
+    }
+    Ok(jobs)
+}
+
+fn list_jobs(list: Node, base: &str) -> Result<Vec<SegmentJob>, String> {
+    let resolve = |uri: Option<&str>| match uri {
+        Some(u) => hls::resolve_url(base, u).ok_or_else(|| format!("Failed to resolve URL: {u}")),
+        None => Ok(base.to_string()),
+    };
+    let init = child(list, "Initialization")
+        .map(|i| -> Result<InitSection, String> {
+            Ok(InitSection {
+                url: resolve(i.attribute("sourceURL"))?,
+                byte_range: i.attribute("range").and_then(parse_range),
+                key: None,
+            })
+        })
+        .transpose()?;
+
+    let mut jobs = Vec::new();
+    for (i, seg) in children(list, "SegmentURL").enumerate() {
+        let url = resolve(seg.attribute("media"))?;
+        let range = seg.attribute("mediaRange").and_then(parse_range);
+        let init = if i == 0 { init.clone() } else { None };
+        jobs.push(SegmentJob {
+            url,
+            sequence: i as u64,
+            byte_range: range,
+            key: None,
+            init,
+        });
+    }
+    Ok(jobs)
+}
+
+/// A single-file representation. With an `indexRange`, the file's `sidx` box
+/// splits it into byte-range segments; otherwise it is fetched as one segment.
+async fn base_jobs(
+    client: &HttpClient,
+    throttle: &Throttle,
+    base: &str,
+    segment_base: Option<Node<'_, '_>>,
+) -> Result<Vec<SegmentJob>, DownloadError> {
+    let init_range = segment_base
+        .and_then(|s| child(s, "Initialization"))
+        .and_then(|i| i.attribute("range"))
+        .and_then(parse_range);
+    let index_range = segment_base
+        .and_then(|s| s.attribute("indexRange"))
+        .and_then(parse_range);
+
+    if let Some((index_offset, index_len)) = index_range {
+        let index =
+            hls::download_segment(client, throttle, base, Some((index_offset, index_len))).await?;
+        match parse_sidx(&index, index_offset) {
+            Some(ranges) if !ranges.is_empty() => {
+                let init = InitSection {
+                    url: base.to_string(),
+                    // Everything before the index: ftyp + moov
+                    byte_range: Some(init_range.unwrap_or((0, index_offset))),
+                    key: None,
+                };
+                return Ok(ranges
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, range)| {
+                        let init = if i == 0 { Some(init.clone()) } else { None };
+                        SegmentJob {
+                            url: base.to_string(),
+                            sequence: i as u64,
+                            byte_range: Some(range),
+                            key: None,
+                            init,
+                        }
+                    })
+                    .collect());
+            }
+            _ => log::warn!("[DASH] Unusable segment index; fetching {base} as one file"),
+        }
+    }
+    Ok(vec![plain_job(base.to_string(), 0, None, None)])
+}
+
+fn plain_job(
+    url: String,
+    sequence: u64,
+    byte_range: Option<(u64, u64)>,
+    init_url: Option<String>,
+) -> SegmentJob {
+    SegmentJob {
+        url,
+        sequence,
+        byte_range,
+        key: None,
+        init: init_url.map(|url| InitSection {
+            url,
+            byte_range: None,
+            key: None,
+        }),
+    }
+}
+
+/// Parses a `first-last` byte range into `(offset, length)`.
+fn parse_range(range: &str) -> Option<(u64, u64)> {
+    let (first, last) = range.trim().split_once('-')?;
+    let (first, last): (u64, u64) = (first.parse().ok()?, last.parse().ok()?);
+    (last >= first).then_some((first, last - first + 1))
+}
+
+/// Returns the `(offset, length)` of each subsegment listed in a `sidx` box
+/// that starts at `offset` in the file. Hierarchical indexes are not supported.
+fn parse_sidx(data: &[u8], offset: u64) -> Option<Vec<(u64, u64)>> {
+    let read_u32 = |pos: usize| {
+        data.get(pos..pos + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as u64)
+    };
+    let read_u64 = |pos: usize| Some((read_u32(pos)? << 32) | read_u32(pos + 4)?);
+    if data.get(4..8)? != b"sidx" {
+        return None;
+    }
+    let size = read_u32(0)?;
+    let version = *data.get(8)?;
+    // version/flags, reference_ID, timescale
+    let mut pos = 20;
+    let first_offset = if version == 0 {
+        pos += 8;
+        read_u32(24)?
+    } else {
+        pos += 16;
+        read_u64(28)?
+    };
+    let count = data
+        .get(pos + 2..pos + 4)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))?;
+    pos += 4;
+
+    let mut next = offset + size + first_offset;
+    let mut ranges = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let reference = read_u32(pos)?;
+        if reference & 0x8000_0000 != 0 {
+            return None;
+        }
+        let length = reference & 0x7fff_ffff;
+        ranges.push((next, length));
+        next += length;
+        pos += 12;
+    }
+    Some(ranges)
+}
+
+/// Substitutes `$RepresentationID$`, `$Number$`, `$Bandwidth$` and `$Time$`
+/// (with optional `%0<width>d` formatting) in a SegmentTemplate URL.
+fn expand_template(template: &str, rep_id: &str, bandwidth: u64, number: u64, time: u64) -> String {
+    let mut out = String::with_capacity(template.len() + 16);
+    // Identifiers sit between pairs of `$`; `$$` is a literal dollar sign
+    for (i, part) in template.split('$').enumerate() {
+        if i % 2 == 0 {
+            out.push_str(part);
+            continue;
+        }
+        let (name, format) = part.split_once('%').unwrap_or((part, ""));
+        let width: usize = format
+            .trim_start_matches('0')
+            .trim_end_matches('d')
+            .parse()
+            .unwrap_or(0);
+        let value = match name {
+            "" => Some("$".to_string()),
+            "RepresentationID" => Some(rep_id.to_string()),
+            "Number" => Some(format!("{number:0width$}")),
+            "Bandwidth" => Some(format!("{bandwidth:0width$}")),
+            "Time" => Some(format!("{time:0width$}")),
+            _ => None,
+        };
+        match value {
+            Some(v) => out.push_str(&v),
+            None => {
+                out.push('$');
+                out.push_str(part);
+                out.push('$');
+            }
+        }
+    }
+    out
+}
+
+/// Identifies a representation's segment list independently of tokenised
+/// hosts and query strings, so resume survives a re-resolved manifest URL.
+fn jobs_fingerprint(jobs: &[SegmentJob]) -> String {
+    // FNV-1a — stable across builds, unlike `DefaultHasher`
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for job in jobs {
+        let path = job.url.split('?').next().unwrap_or("");
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let range_bytes = job
+            .byte_range
+            .map(|(offset, length)| [offset, length])
+            .into_iter()
+            .flatten()
+            .flat_map(u64::to_le_bytes);
+        for byte in name.bytes().chain(range_bytes) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("dash:{}:{:016x}", jobs.len(), hash)
+}
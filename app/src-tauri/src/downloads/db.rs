@@ -1,7 +1,9 @@
-use rusqlite::{params, Connection, Result};
+use rusqlite::{params, Connection, OptionalExtension, Result};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
+use super::state::{self, Event, PauseReason, StateError};
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum DownloadStatus {
@@ -11,6 +13,13 @@ pub enum DownloadStatus {
     Completed,
     Failed,
     Cancelled,
+    /// Failed transiently; re-queued after a backoff delay
+    Retrying,
+    /// The stream URL expired; waits for `download_update_url`
+    #[serde(rename = "awaiting_url")]
+    AwaitingUrl,
+    /// Completed, but the file is gone or no longer its recorded size
+    Missing,
 }
 
 impl DownloadStatus {
@@ -22,16 +31,23 @@ impl DownloadStatus {
             Self::Completed => "completed",
             Self::Failed => "failed",
             Self::Cancelled => "cancelled",
+            Self::Retrying => "retrying",
+            Self::AwaitingUrl => "awaiting_url",
+            Self::Missing => "missing",
         }
     }
-    pub fn from_str(s: &str) -> Self {
+    pub fn from_str(s: &str) -> Result<Self, StateError> {
         match s {
-            "queued" => Self::Queued,
-            "downloading" => Self::Downloading,
-            "paused" => Self::Paused,
-            "completed" => Self::Completed,
-            "failed" => Self::Failed,
-            _ => Self::Cancelled,
+            "queued" => Ok(Self::Queued),
+            "downloading" => Ok(Self::Downloading),
+            "paused" => Ok(Self::Paused),
+            "completed" => Ok(Self::Completed),
+            "failed" => Ok(Self::Failed),
+            "cancelled" => Ok(Self::Cancelled),
+            "retrying" => Ok(Self::Retrying),
+            "awaiting_url" => Ok(Self::AwaitingUrl),
+            "missing" => Ok(Self::Missing),
+            _ => Err(StateError::UnknownStatus(s.to_string())),
         }
     }
 }
@@ -59,6 +75,113 @@ impl DownloadQuality {
             _ => Self::Standard,
         }
     }
+    /// Tallest video this preference targets (None = no limit).
+    pub fn max_height(&self) -> Option<u64> {
+        match self {
+            Self::Standard => Some(720),
+            Self::Higher => Some(1080),
+            Self::Best => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadPriority {
+    High,
+    Normal,
+    Low,
+}
+
+impl DownloadPriority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::High => "high",
+            Self::Normal => "normal",
+            Self::Low => "low",
+        }
+    }
+    pub fn from_str(s: &str) -> Self {
+        match s {
+            "high" => Self::High,
+            "low" => Self::Low,
+            _ => Self::Normal,
+        }
+    }
+    /// Queue order: lower ranks start first.
+    pub fn rank(&self) -> u8 {
+        match self {
+            Self::High => 0,
+            Self::Normal => 1,
+            Self::Low => 2,
+        }
+    }
+}
+
+/// One byte range of a multi-connection direct download.
+/// `end_byte` is inclusive; `downloaded` counts bytes written from `start_byte`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeState {
+    pub index: i64,
+    pub start_byte: i64,
+    pub end_byte: i64,
+    pub downloaded: i64,
+}
+
+impl RangeState {
+    pub fn size(&self) -> i64 {
+        self.end_byte - self.start_byte + 1
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.downloaded >= self.size()
+    }
+}
+
+/// Identity of the remote file behind a direct download, recorded when the
+/// first bytes arrive. Resuming appends only while these still match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RemoteValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub total_size: Option<i64>,
+}
+
+impl RemoteValidators {
+    /// Value for an `If-Range` header: a strong ETag, else Last-Modified.
+    /// Weak ETags cannot validate byte ranges.
+    pub fn if_range(&self) -> Option<&str> {
+        self.etag
+            .as_deref()
+            .filter(|e| !e.starts_with("W/"))
+            .or(self.last_modified.as_deref())
+    }
+
+    /// Whether `other` describes the same file. Sizes must agree when both are
+    /// known, and at least one validator has to be present on both sides.
+    pub fn same_file(&self, other: &RemoteValidators) -> bool {
+        if let (Some(a), Some(b)) = (self.total_size, other.total_size) {
+            if a != b {
+                return false;
+            }
+        }
+        let strong = |e: &Option<String>| e.clone().filter(|e| !e.starts_with("W/"));
+        if let (Some(a), Some(b)) = (strong(&self.etag), strong(&other.etag)) {
+            return a == b;
+        }
+        match (&self.last_modified, &other.last_modified) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// A fully written HLS segment and where it sits in the part file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentState {
+    pub index: i64,
+    pub byte_offset: i64,
+    pub byte_length: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -95,6 +218,115 @@ pub struct DownloadRecord {
     pub subtitle_urls: Option<String>,
     /// JSON array of {lang, path} — locally downloaded subtitle file paths
     pub subtitle_paths: Option<String>,
+    /// Number of HLS segments fetched in parallel (None = engine default)
+    pub segment_concurrency: Option<i64>,
+    /// JSON array of preferred audio languages (BCP-47), in order of preference
+    pub audio_languages: Option<String>,
+    /// JSON array of {language, name, default, path} — downloaded audio renditions.
+    /// `path` is null for tracks muxed into the main file.
+    pub audio_tracks: Option<String>,
+    /// Exact variant chosen from `download_probe` (HLS variant URI or DASH
+    /// representation id). None = pick by quality preference
+    pub variant_id: Option<String>,
+    /// JSON `QualityTargets` — max height, codec preference, SDR-only
+    pub quality_targets: Option<String>,
+    /// Automatic retries since the last successful start or manual resume
+    pub retry_count: i64,
+    /// Bandwidth cap in bytes per second (None = only the global cap applies)
+    pub rate_limit: Option<i64>,
+    /// Earliest start time in ms since the epoch (None = as soon as possible)
+    pub not_before: Option<i64>,
+    pub priority: DownloadPriority,
+    /// Index in the download queue, saved so `restore` keeps the order.
+    /// -1 for a download that left the queue to start.
+    pub queue_position: i64,
+    /// JSON object of extra HTTP headers sent with every request
+    pub request_headers: Option<String>,
+    /// Why the download is paused (None unless `status` is Paused)
+    pub pause_reason: Option<PauseReason>,
+    /// Size the finished download is expected to take, from `download_probe`
+    pub expected_size: Option<i64>,
+}
+
+/// Column list shared by every query that reads a full `DownloadRecord`.
+/// Must stay in the order expected by `record_from_row`.
+const RECORD_COLUMNS: &str =
+    "id, profile_id, media_type, media_id, episode_id, title, episode_title,
+    season, episode, poster_path, status, progress, quality, file_path, file_size,
+    downloaded_bytes, added_at, completed_at, last_watched_at, watched_percent,
+    stream_url, addon_id, error_message, smart_download, auto_delete,
+    subtitle_urls, subtitle_paths, segment_concurrency, audio_languages, audio_tracks,
+    variant_id, quality_targets, retry_count, rate_limit, not_before,
+    priority, queue_position, request_headers, pause_reason, expected_size";
+
+/// `NotFound` unless an update by id touched a row.
+fn found(id: &str, updated: usize) -> Result<(), StateError> {
+    if updated == 0 {
+        return Err(StateError::NotFound(id.to_string()));
+    }
+    Ok(())
+}
+
+/// Mapped records, skipping rows that do not map — a status written by a
+/// newer version, say — so one bad row does not hide every other download.
+fn readable(rows: impl Iterator<Item = Result<DownloadRecord>>) -> Vec<DownloadRecord> {
+    rows.filter_map(|row| match row {
+        Ok(rec) => Some(rec),
+        Err(e) => {
+            log::warn!("[Downloads] Skipping unreadable download record: {e}");
+            None
+        }
+    })
+    .collect()
+}
+
+fn record_from_row(row: &rusqlite::Row) -> Result<DownloadRecord> {
+    Ok(DownloadRecord {
+        id: row.get(0)?,
+        profile_id: row.get(1)?,
+        media_type: row.get(2)?,
+        media_id: row.get(3)?,
+        episode_id: row.get(4)?,
+        title: row.get(5)?,
+        episode_title: row.get(6)?,
+        season: row.get(7)?,
+        episode: row.get(8)?,
+        poster_path: row.get(9)?,
+        status: DownloadStatus::from_str(&row.get::<_, String>(10)?).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(10, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        progress: row.get(11)?,
+        quality: DownloadQuality::from_str(&row.get::<_, String>(12)?),
+        file_path: row.get(13)?,
+        file_size: row.get(14)?,
+        downloaded_bytes: row.get(15)?,
+        added_at: row.get(16)?,
+        completed_at: row.get(17)?,
+        last_watched_at: row.get(18)?,
+        watched_percent: row.get(19)?,
+        stream_url: row.get(20)?,
+        addon_id: row.get(21)?,
+        error_message: row.get(22)?,
+        smart_download: row.get::<_, i64>(23)? != 0,
+        auto_delete: row.get::<_, i64>(24)? != 0,
+        subtitle_urls: row.get(25)?,
+        subtitle_paths: row.get(26)?,
+        segment_concurrency: row.get(27)?,
+        audio_languages: row.get(28)?,
+        audio_tracks: row.get(29)?,
+        variant_id: row.get(30)?,
+        quality_targets: row.get(31)?,
+        retry_count: row.get(32)?,
+        rate_limit: row.get(33)?,
+        not_before: row.get(34)?,
+        priority: DownloadPriority::from_str(&row.get::<_, String>(35)?),
+        queue_position: row.get(36)?,
+        request_headers: row.get(37)?,
+        pause_reason: row
+            .get::<_, Option<String>>(38)?
+            .and_then(|r| PauseReason::from_str(&r)),
+        expected_size: row.get(39)?,
+    })
 }
 
 pub struct DownloadDb {
@@ -139,7 +371,23 @@ impl DownloadDb {
                 smart_download INTEGER NOT NULL DEFAULT 0,
                 auto_delete INTEGER NOT NULL DEFAULT 0,
                 subtitle_urls TEXT,
-                subtitle_paths TEXT
+                subtitle_paths TEXT,
+                segment_concurrency INTEGER,
+                audio_languages TEXT,
+                audio_tracks TEXT,
+                variant_id TEXT,
+                quality_targets TEXT,
+                etag TEXT,
+                last_modified TEXT,
+                remote_size INTEGER,
+                retry_count INTEGER NOT NULL DEFAULT 0,
+                rate_limit INTEGER,
+                not_before INTEGER,
+                priority TEXT NOT NULL DEFAULT 'normal',
+                queue_position INTEGER NOT NULL DEFAULT 0,
+                request_headers TEXT,
+                pause_reason TEXT,
+                expected_size INTEGER
             );
             CREATE INDEX IF NOT EXISTS idx_downloads_profile ON downloads(profile_id);
             CREATE INDEX IF NOT EXISTS idx_downloads_status ON downloads(status);
@@ -149,7 +397,38 @@ impl DownloadDb {
                 profile_id TEXT PRIMARY KEY,
                 quota_bytes INTEGER NOT NULL DEFAULT 0,
                 smart_download_default INTEGER NOT NULL DEFAULT 0,
-                auto_delete_default INTEGER NOT NULL DEFAULT 0
+                auto_delete_default INTEGER NOT NULL DEFAULT 0,
+                start_after INTEGER
+            );
+
+            -- Device-wide settings as JSON values, by key
+            CREATE TABLE IF NOT EXISTS app_settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+
+            -- Per-range resume state for multi-connection direct downloads
+            CREATE TABLE IF NOT EXISTS download_ranges (
+                download_id TEXT NOT NULL,
+                range_index INTEGER NOT NULL,
+                start_byte INTEGER NOT NULL,
+                end_byte INTEGER NOT NULL,
+                downloaded INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (download_id, range_index)
+            );
+
+            -- Segment-level resume state for HLS downloads
+            CREATE TABLE IF NOT EXISTS hls_progress (
+                download_id TEXT PRIMARY KEY,
+                fingerprint TEXT NOT NULL,
+                segment_total INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS hls_segments (
+                download_id TEXT NOT NULL,
+                seg_index INTEGER NOT NULL,
+                byte_offset INTEGER NOT NULL,
+                byte_length INTEGER NOT NULL,
+                PRIMARY KEY (download_id, seg_index)
             );
         ",
         )?;
@@ -169,6 +448,66 @@ impl DownloadDb {
         let _ = self
             .conn
             .execute("ALTER TABLE downloads ADD COLUMN subtitle_paths TEXT", []);
+        let _ = self.conn.execute(
+            "ALTER TABLE downloads ADD COLUMN segment_concurrency INTEGER",
+            [],
+        );
+        let _ = self
+            .conn
+            .execute("ALTER TABLE downloads ADD COLUMN audio_languages TEXT", []);
+        let _ = self
+            .conn
+            .execute("ALTER TABLE downloads ADD COLUMN audio_tracks TEXT", []);
+        let _ = self
+            .conn
+            .execute("ALTER TABLE downloads ADD COLUMN variant_id TEXT", []);
+        let _ = self
+            .conn
+            .execute("ALTER TABLE downloads ADD COLUMN quality_targets TEXT", []);
+        let _ = self
+            .conn
+            .execute("ALTER TABLE downloads ADD COLUMN etag TEXT", []);
+        let _ = self
+            .conn
+            .execute("ALTER TABLE downloads ADD COLUMN last_modified TEXT", []);
+        let _ = self
+            .conn
+            .execute("ALTER TABLE downloads ADD COLUMN remote_size INTEGER", []);
+        let _ = self.conn.execute(
+            "ALTER TABLE downloads ADD COLUMN retry_count INTEGER NOT NULL DEFAULT 0",
+            [],
+        );
+        let _ = self
+            .conn
+            .execute("ALTER TABLE downloads ADD COLUMN rate_limit INTEGER", []);
+        let _ = self
+            .conn
+            .execute("ALTER TABLE downloads ADD COLUMN not_before INTEGER", []);
+        let _ = self.conn.execute(
+            "ALTER TABLE downloads ADD COLUMN priority TEXT NOT NULL DEFAULT 'normal'",
+            [],
+        );
+        let _ = self.conn.execute(
+            "ALTER TABLE downloads ADD COLUMN queue_position INTEGER NOT NULL DEFAULT 0",
+            [],
+        );
+        let _ = self
+            .conn
+            .execute("ALTER TABLE downloads ADD COLUMN request_headers TEXT", []);
+        let _ = self
+            .conn
+            .execute("ALTER TABLE downloads ADD COLUMN pause_reason TEXT", []);
+        let _ = self
+            .conn
+            .execute("ALTER TABLE downloads ADD COLUMN expected_size INTEGER", []);
+        let _ = self.conn.execute(
+            "ALTER TABLE hls_progress ADD COLUMN segment_total INTEGER",
+            [],
+        );
+        let _ = self.conn.execute(
+            "ALTER TABLE profile_settings ADD COLUMN start_after INTEGER",
+            [],
+        );
 
         Ok(())
     }
@@ -178,8 +517,10 @@ impl DownloadDb {
             "INSERT INTO downloads (id, profile_id, media_type, media_id, episode_id, title, episode_title,
              season, episode, poster_path, status, progress, quality, file_path, file_size, downloaded_bytes,
              added_at, completed_at, last_watched_at, watched_percent, stream_url, addon_id, error_message,
-             smart_download, auto_delete, subtitle_urls, subtitle_paths)
-             VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18,?19,?20,?21,?22,?23,?24,?25,?26,?27)",
+             smart_download, auto_delete, subtitle_urls, subtitle_paths, segment_concurrency,
+             audio_languages, audio_tracks, variant_id, quality_targets, retry_count,
+             rate_limit, not_before, priority, queue_position, request_headers, expected_size)
+             VALUES (?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11,?12,?13,?14,?15,?16,?17,?18,?19,?20,?21,?22,?23,?24,?25,?26,?27,?28,?29,?30,?31,?32,?33,?34,?35,?36,?37,?38,?39)",
             params![
                 rec.id, rec.profile_id, rec.media_type, rec.media_id, rec.episode_id,
                 rec.title, rec.episode_title, rec.season, rec.episode, rec.poster_path,
@@ -187,99 +528,37 @@ impl DownloadDb {
                 rec.file_size, rec.downloaded_bytes, rec.added_at, rec.completed_at,
                 rec.last_watched_at, rec.watched_percent, rec.stream_url, rec.addon_id,
                 rec.error_message, rec.smart_download as i64, rec.auto_delete as i64,
-                rec.subtitle_urls, rec.subtitle_paths
+                rec.subtitle_urls, rec.subtitle_paths, rec.segment_concurrency,
+                rec.audio_languages, rec.audio_tracks, rec.variant_id, rec.quality_targets,
+                rec.retry_count, rec.rate_limit, rec.not_before, rec.priority.as_str(),
+                rec.queue_position, rec.request_headers, rec.expected_size
             ],
         )?;
         Ok(())
     }
 
+    /// Every download of every profile.
+    pub fn get_all_profiles(&self) -> Result<Vec<DownloadRecord>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT {RECORD_COLUMNS} FROM downloads"))?;
+        let rows = stmt.query_map([], record_from_row)?;
+        Ok(readable(rows))
+    }
+
     pub fn get_all(&self, profile_id: &str) -> Result<Vec<DownloadRecord>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, profile_id, media_type, media_id, episode_id, title, episode_title,
-             season, episode, poster_path, status, progress, quality, file_path, file_size,
-             downloaded_bytes, added_at, completed_at, last_watched_at, watched_percent,
-             stream_url, addon_id, error_message, smart_download, auto_delete,
-             subtitle_urls, subtitle_paths
-             FROM downloads WHERE profile_id = ?1 ORDER BY added_at DESC",
-        )?;
-        let rows = stmt.query_map([profile_id], |row| {
-            Ok(DownloadRecord {
-                id: row.get(0)?,
-                profile_id: row.get(1)?,
-                media_type: row.get(2)?,
-                media_id: row.get(3)?,
-                episode_id: row.get(4)?,
-                title: row.get(5)?,
-                episode_title: row.get(6)?,
-                season: row.get(7)?,
-                episode: row.get(8)?,
-                poster_path: row.get(9)?,
-                status: DownloadStatus::from_str(&row.get::<_, String>(10)?),
-                progress: row.get(11)?,
-                quality: DownloadQuality::from_str(&row.get::<_, String>(12)?),
-                file_path: row.get(13)?,
-                file_size: row.get(14)?,
-                downloaded_bytes: row.get(15)?,
-                added_at: row.get(16)?,
-                completed_at: row.get(17)?,
-                last_watched_at: row.get(18)?,
-                watched_percent: row.get(19)?,
-                stream_url: row.get(20)?,
-                addon_id: row.get(21)?,
-                error_message: row.get(22)?,
-                smart_download: row.get::<_, i64>(23)? != 0,
-                auto_delete: row.get::<_, i64>(24)? != 0,
-                subtitle_urls: row.get(25)?,
-                subtitle_paths: row.get(26)?,
-            })
-        })?;
-        let mut records = Vec::new();
-        for row in rows {
-            records.push(row?);
-        }
-        Ok(records)
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {RECORD_COLUMNS} FROM downloads WHERE profile_id = ?1 ORDER BY added_at DESC"
+        ))?;
+        let rows = stmt.query_map([profile_id], record_from_row)?;
+        Ok(readable(rows))
     }
 
     pub fn get_by_id(&self, id: &str) -> Result<Option<DownloadRecord>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, profile_id, media_type, media_id, episode_id, title, episode_title,
-             season, episode, poster_path, status, progress, quality, file_path, file_size,
-             downloaded_bytes, added_at, completed_at, last_watched_at, watched_percent,
-             stream_url, addon_id, error_message, smart_download, auto_delete,
-             subtitle_urls, subtitle_paths
-             FROM downloads WHERE id = ?1",
-        )?;
-        let mut rows = stmt.query_map([id], |row| {
-            Ok(DownloadRecord {
-                id: row.get(0)?,
-                profile_id: row.get(1)?,
-                media_type: row.get(2)?,
-                media_id: row.get(3)?,
-                episode_id: row.get(4)?,
-                title: row.get(5)?,
-                episode_title: row.get(6)?,
-                season: row.get(7)?,
-                episode: row.get(8)?,
-                poster_path: row.get(9)?,
-                status: DownloadStatus::from_str(&row.get::<_, String>(10)?),
-                progress: row.get(11)?,
-                quality: DownloadQuality::from_str(&row.get::<_, String>(12)?),
-                file_path: row.get(13)?,
-                file_size: row.get(14)?,
-                downloaded_bytes: row.get(15)?,
-                added_at: row.get(16)?,
-                completed_at: row.get(17)?,
-                last_watched_at: row.get(18)?,
-                watched_percent: row.get(19)?,
-                stream_url: row.get(20)?,
-                addon_id: row.get(21)?,
-                error_message: row.get(22)?,
-                smart_download: row.get::<_, i64>(23)? != 0,
-                auto_delete: row.get::<_, i64>(24)? != 0,
-                subtitle_urls: row.get(25)?,
-                subtitle_paths: row.get(26)?,
-            })
-        })?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {RECORD_COLUMNS} FROM downloads WHERE id = ?1"
+        ))?;
+        let mut rows = stmt.query_map([id], record_from_row)?;
         if let Some(row) = rows.next() {
             Ok(Some(row?))
         } else {
@@ -287,42 +566,183 @@ impl DownloadDb {
         }
     }
 
-    pub fn update_progress(&self, id: &str, progress: f64, downloaded_bytes: i64) -> Result<()> {
-        self.conn.execute(
-            "UPDATE downloads SET progress = ?1, downloaded_bytes = ?2, status = 'downloading' WHERE id = ?3",
+    /// Records progress. The first report of a queued download is its Start;
+    /// reports that land after a pause or cancel leave the status alone.
+    pub fn update_progress(
+        &self,
+        id: &str,
+        progress: f64,
+        downloaded_bytes: i64,
+    ) -> Result<(), StateError> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE downloads SET progress = ?1, downloaded_bytes = ?2 WHERE id = ?3",
             params![progress, downloaded_bytes, id],
         )?;
+        if self.status(id)? == DownloadStatus::Queued {
+            self.transition(id, Event::Start)?;
+        }
+        tx.commit()?;
         Ok(())
     }
 
-    pub fn update_status(&self, id: &str, status: &DownloadStatus) -> Result<()> {
+    /// Writes progress rebuilt from the files on disk, leaving the status alone.
+    pub fn restore_progress(&self, id: &str, progress: f64, downloaded_bytes: i64) -> Result<()> {
         self.conn.execute(
-            "UPDATE downloads SET status = ?1 WHERE id = ?2",
-            params![status.as_str(), id],
+            "UPDATE downloads SET progress = ?1, downloaded_bytes = ?2 WHERE id = ?3",
+            params![progress, downloaded_bytes, id],
         )?;
         Ok(())
     }
 
-    pub fn update_complete(&self, id: &str, file_path: &str, file_size: i64) -> Result<()> {
+    /// Applies `event` to a download's status, rejecting changes the state
+    /// machine does not allow. Returns the new status.
+    pub fn transition(&self, id: &str, event: Event) -> Result<DownloadStatus, StateError> {
+        let to = state::next(&self.status(id)?, event)?;
+        let reason = match event {
+            Event::Pause(reason) => Some(reason.as_str()),
+            _ => None,
+        };
+        self.conn.execute(
+            "UPDATE downloads SET status = ?1, pause_reason = ?2 WHERE id = ?3",
+            params![to.as_str(), reason, id],
+        )?;
+        Ok(to)
+    }
+
+    pub fn status(&self, id: &str) -> Result<DownloadStatus, StateError> {
+        let status: String = self
+            .conn
+            .query_row("SELECT status FROM downloads WHERE id = ?1", [id], |r| {
+                r.get(0)
+            })
+            .optional()?
+            .ok_or_else(|| StateError::NotFound(id.to_string()))?;
+        DownloadStatus::from_str(&status)
+    }
+
+    pub fn update_complete(
+        &self,
+        id: &str,
+        file_path: &str,
+        file_size: i64,
+    ) -> Result<(), StateError> {
+        self.transition(id, Event::Complete)?;
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap_or_default()
             .as_millis() as i64;
         self.conn.execute(
-            "UPDATE downloads SET status = 'completed', progress = 100, file_path = ?1, file_size = ?2, completed_at = ?3 WHERE id = ?4",
+            "UPDATE downloads SET progress = 100, file_path = ?1, file_size = ?2, completed_at = ?3, retry_count = 0 WHERE id = ?4",
             params![file_path, file_size, now, id],
         )?;
         Ok(())
     }
 
-    pub fn update_error(&self, id: &str, error: &str) -> Result<()> {
+    pub fn update_error(&self, id: &str, error: &str) -> Result<(), StateError> {
+        self.transition(id, Event::Fail)?;
         self.conn.execute(
-            "UPDATE downloads SET status = 'failed', error_message = ?1 WHERE id = ?2",
+            "UPDATE downloads SET error_message = ?1 WHERE id = ?2",
             params![error, id],
         )?;
         Ok(())
     }
 
+    /// Marks a download as waiting for automatic retry number `retry_count`.
+    pub fn update_retrying(
+        &self,
+        id: &str,
+        retry_count: i64,
+        error: &str,
+    ) -> Result<(), StateError> {
+        self.transition(id, Event::Retry)?;
+        self.conn.execute(
+            "UPDATE downloads SET retry_count = ?1, error_message = ?2 WHERE id = ?3",
+            params![retry_count, error, id],
+        )?;
+        Ok(())
+    }
+
+    /// Parks a download whose stream URL expired until a fresh one arrives.
+    pub fn update_awaiting_url(&self, id: &str, error: &str) -> Result<(), StateError> {
+        self.transition(id, Event::AwaitUrl)?;
+        self.conn.execute(
+            "UPDATE downloads SET error_message = ?1 WHERE id = ?2",
+            params![error, id],
+        )?;
+        Ok(())
+    }
+
+    /// Downloads paused for `reason`, across all profiles, oldest first.
+    pub fn get_paused_by(&self, reason: PauseReason) -> Result<Vec<DownloadRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {RECORD_COLUMNS} FROM downloads
+             WHERE status = 'paused' AND pause_reason = ?1
+             ORDER BY queue_position ASC, added_at ASC"
+        ))?;
+        let rows = stmt.query_map([reason.as_str()], record_from_row)?;
+        Ok(readable(rows))
+    }
+
+    pub fn update_stream_url(&self, id: &str, stream_url: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE downloads SET stream_url = ?1, error_message = NULL WHERE id = ?2",
+            params![stream_url, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_rate_limit(&self, id: &str, rate_limit: Option<i64>) -> Result<(), StateError> {
+        let updated = self.conn.execute(
+            "UPDATE downloads SET rate_limit = ?1 WHERE id = ?2",
+            params![rate_limit, id],
+        )?;
+        found(id, updated)
+    }
+
+    pub fn update_not_before(&self, id: &str, not_before: Option<i64>) -> Result<(), StateError> {
+        let updated = self.conn.execute(
+            "UPDATE downloads SET not_before = ?1 WHERE id = ?2",
+            params![not_before, id],
+        )?;
+        found(id, updated)
+    }
+
+    pub fn update_priority(&self, id: &str, priority: &DownloadPriority) -> Result<(), StateError> {
+        let updated = self.conn.execute(
+            "UPDATE downloads SET priority = ?1 WHERE id = ?2",
+            params![priority.as_str(), id],
+        )?;
+        found(id, updated)
+    }
+
+    pub fn update_queue_position(&self, id: &str, position: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE downloads SET queue_position = ?1 WHERE id = ?2",
+            params![position, id],
+        )?;
+        Ok(())
+    }
+
+    /// Stores the order of the queued downloads, first to last.
+    pub fn save_queue_order(&self, ids: &[&str]) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare("UPDATE downloads SET queue_position = ?1 WHERE id = ?2")?;
+            for (position, id) in ids.iter().enumerate() {
+                stmt.execute(params![position as i64, id])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Starts the retry budget over — after a manual resume.
+    pub fn reset_retry_count(&self, id: &str) -> Result<()> {
+        self.conn
+            .execute("UPDATE downloads SET retry_count = 0 WHERE id = ?1", [id])?;
+        Ok(())
+    }
+
     pub fn update_subtitle_paths(&self, id: &str, paths_json: &str) -> Result<()> {
         self.conn.execute(
             "UPDATE downloads SET subtitle_paths = ?1 WHERE id = ?2",
@@ -331,71 +751,229 @@ impl DownloadDb {
         Ok(())
     }
 
+    pub fn update_audio_tracks(&self, id: &str, tracks_json: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE downloads SET audio_tracks = ?1 WHERE id = ?2",
+            params![tracks_json, id],
+        )?;
+        Ok(())
+    }
+
     pub fn delete(&self, id: &str) -> Result<()> {
         self.conn
             .execute("DELETE FROM downloads WHERE id = ?1", [id])?;
+        self.clear_ranges(id)?;
+        self.clear_hls_state(id)?;
         Ok(())
     }
 
-    /// Returns all downloads that were queued or in-progress at shutdown, across all profiles.
-    /// Used on startup to restore the download queue.
-    pub fn get_all_pending(&self) -> Result<Vec<DownloadRecord>> {
+    // ── Remote file validators ─────────────────────────────────────────────────
+
+    pub fn get_validators(&self, id: &str) -> Result<RemoteValidators> {
+        let result = self.conn.query_row(
+            "SELECT etag, last_modified, remote_size FROM downloads WHERE id = ?1",
+            [id],
+            |r| {
+                Ok(RemoteValidators {
+                    etag: r.get(0)?,
+                    last_modified: r.get(1)?,
+                    total_size: r.get(2)?,
+                })
+            },
+        );
+        match result {
+            Ok(v) => Ok(v),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(RemoteValidators::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn update_validators(&self, id: &str, validators: &RemoteValidators) -> Result<()> {
+        self.conn.execute(
+            "UPDATE downloads SET etag = ?1, last_modified = ?2, remote_size = ?3 WHERE id = ?4",
+            params![
+                validators.etag,
+                validators.last_modified,
+                validators.total_size,
+                id
+            ],
+        )?;
+        Ok(())
+    }
+
+    // ── Multi-connection range state ───────────────────────────────────────────
+
+    pub fn get_ranges(&self, id: &str) -> Result<Vec<RangeState>> {
         let mut stmt = self.conn.prepare(
-            "SELECT id, profile_id, media_type, media_id, episode_id, title, episode_title,
-             season, episode, poster_path, status, progress, quality, file_path, file_size,
-             downloaded_bytes, added_at, completed_at, last_watched_at, watched_percent,
-             stream_url, addon_id, error_message, smart_download, auto_delete,
-             subtitle_urls, subtitle_paths
-             FROM downloads WHERE status IN ('queued','downloading') ORDER BY added_at ASC",
+            "SELECT range_index, start_byte, end_byte, downloaded FROM download_ranges
+             WHERE download_id = ?1 ORDER BY range_index ASC",
         )?;
-        let rows = stmt.query_map([], |row| {
-            Ok(DownloadRecord {
-                id: row.get(0)?,
-                profile_id: row.get(1)?,
-                media_type: row.get(2)?,
-                media_id: row.get(3)?,
-                episode_id: row.get(4)?,
-                title: row.get(5)?,
-                episode_title: row.get(6)?,
-                season: row.get(7)?,
-                episode: row.get(8)?,
-                poster_path: row.get(9)?,
-                status: DownloadStatus::from_str(&row.get::<_, String>(10)?),
-                progress: row.get(11)?,
-                quality: DownloadQuality::from_str(&row.get::<_, String>(12)?),
-                file_path: row.get(13)?,
-                file_size: row.get(14)?,
-                downloaded_bytes: row.get(15)?,
-                added_at: row.get(16)?,
-                completed_at: row.get(17)?,
-                last_watched_at: row.get(18)?,
-                watched_percent: row.get(19)?,
-                stream_url: row.get(20)?,
-                addon_id: row.get(21)?,
-                error_message: row.get(22)?,
-                smart_download: row.get::<_, i64>(23)? != 0,
-                auto_delete: row.get::<_, i64>(24)? != 0,
-                subtitle_urls: row.get(25)?,
-                subtitle_paths: row.get(26)?,
+        let rows = stmt.query_map([id], |row| {
+            Ok(RangeState {
+                index: row.get(0)?,
+                start_byte: row.get(1)?,
+                end_byte: row.get(2)?,
+                downloaded: row.get(3)?,
             })
         })?;
-        let mut records = Vec::new();
+        let mut ranges = Vec::new();
         for row in rows {
-            records.push(row?);
+            ranges.push(row?);
         }
-        Ok(records)
+        Ok(ranges)
     }
 
-    /// Resets any 'downloading' records back to 'queued' — called on startup so
-    /// downloads interrupted by a crash or shutdown don't appear stuck mid-flight.
-    pub fn reset_interrupted(&self) -> Result<()> {
+    /// Replaces the stored range plan for a download.
+    pub fn save_ranges(&self, id: &str, ranges: &[RangeState]) -> Result<()> {
+        self.clear_ranges(id)?;
+        for r in ranges {
+            self.conn.execute(
+                "INSERT INTO download_ranges (download_id, range_index, start_byte, end_byte, downloaded)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![id, r.index, r.start_byte, r.end_byte, r.downloaded],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn update_range_progress(&self, id: &str, index: i64, downloaded: i64) -> Result<()> {
         self.conn.execute(
-            "UPDATE downloads SET status = 'queued', progress = 0, downloaded_bytes = 0 WHERE status = 'downloading'",
-            [],
+            "UPDATE download_ranges SET downloaded = ?1 WHERE download_id = ?2 AND range_index = ?3",
+            params![downloaded, id, index],
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_ranges(&self, id: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM download_ranges WHERE download_id = ?1", [id])?;
+        Ok(())
+    }
+
+    // ── HLS segment state ──────────────────────────────────────────────────────
+
+    /// Returns the playlist fingerprint and completed segments recorded for a download.
+    pub fn get_hls_state(&self, id: &str) -> Result<Option<(String, Vec<SegmentState>)>> {
+        let fingerprint: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT fingerprint FROM hls_progress WHERE download_id = ?1",
+                [id],
+                |r| r.get(0),
+            )
+            .ok();
+        let fingerprint = match fingerprint {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+
+        let mut stmt = self.conn.prepare(
+            "SELECT seg_index, byte_offset, byte_length FROM hls_segments
+             WHERE download_id = ?1 ORDER BY seg_index ASC",
+        )?;
+        let rows = stmt.query_map([id], |row| {
+            Ok(SegmentState {
+                index: row.get(0)?,
+                byte_offset: row.get(1)?,
+                byte_length: row.get(2)?,
+            })
+        })?;
+        let mut segments = Vec::new();
+        for row in rows {
+            segments.push(row?);
+        }
+        Ok(Some((fingerprint, segments)))
+    }
+
+    /// Starts fresh segment tracking for a playlist, discarding any previous state.
+    /// `segment_total` counts the segments of every stream of the download.
+    pub fn begin_hls_state(&self, id: &str, fingerprint: &str, segment_total: i64) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM hls_segments WHERE download_id = ?1", [id])?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO hls_progress (download_id, fingerprint, segment_total)
+             VALUES (?1, ?2, ?3)",
+            params![id, fingerprint, segment_total],
+        )?;
+        Ok(())
+    }
+
+    /// State keys with recorded segment tracking for a download — its own id
+    /// and `{id}#audio{n}` — with the segment total of each.
+    pub fn get_hls_streams(&self, id: &str) -> Result<Vec<(String, Option<i64>)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT download_id, segment_total FROM hls_progress
+             WHERE download_id = ?1 OR download_id LIKE ?1 || '#%'",
+        )?;
+        let rows = stmt.query_map([id], |r| Ok((r.get(0)?, r.get(1)?)))?;
+        let mut streams = Vec::new();
+        for row in rows {
+            streams.push(row?);
+        }
+        Ok(streams)
+    }
+
+    pub fn record_hls_segment(&self, id: &str, seg: &SegmentState) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO hls_segments (download_id, seg_index, byte_offset, byte_length)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![id, seg.index, seg.byte_offset, seg.byte_length],
         )?;
         Ok(())
     }
 
+    /// Clears the resume state of a download, including that of its audio
+    /// renditions (stored under `{id}#audio{n}`).
+    pub fn clear_hls_state(&self, id: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM hls_progress WHERE download_id = ?1 OR download_id LIKE ?1 || '#%'",
+            [id],
+        )?;
+        self.conn.execute(
+            "DELETE FROM hls_segments WHERE download_id = ?1 OR download_id LIKE ?1 || '#%'",
+            [id],
+        )?;
+        Ok(())
+    }
+
+    /// Returns all downloads that were queued or in-progress at shutdown, across all profiles.
+    /// Used on startup to restore the download queue, in its saved order.
+    pub fn get_all_pending(&self) -> Result<Vec<DownloadRecord>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {RECORD_COLUMNS} FROM downloads
+             WHERE status IN ('queued','downloading')
+             ORDER BY queue_position ASC, added_at ASC"
+        ))?;
+        let rows = stmt.query_map([], record_from_row)?;
+        Ok(readable(rows))
+    }
+
+    /// Resets any 'downloading' records back to 'queued' — called on startup so
+    /// downloads interrupted by a crash or shutdown don't appear stuck mid-flight.
+    /// Downloads that were waiting out a retry delay are queued straight away.
+    /// All or nothing. Progress is left for `restore` to rebuild from the part
+    /// files.
+    pub fn reset_interrupted(&self) -> Result<(), StateError> {
+        let tx = self.conn.unchecked_transaction()?;
+        let ids = {
+            let mut stmt = tx.prepare(
+                "SELECT id, status FROM downloads WHERE status IN ('downloading','retrying')",
+            )?;
+            let rows =
+                stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?;
+            rows.collect::<Result<Vec<_>>>()?
+        };
+        for (id, status) in ids {
+            let event = match DownloadStatus::from_str(&status)? {
+                DownloadStatus::Retrying => Event::Requeue,
+                _ => Event::Interrupt,
+            };
+            self.transition(&id, event)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
     pub fn get_storage_stats(&self, profile_id: &str) -> Result<(i64, i64)> {
         // Returns (total_size_bytes, count) for completed downloads
         let mut stmt = self.conn.prepare(
@@ -417,6 +995,10 @@ impl DownloadDb {
             .collect();
         self.conn
             .execute("DELETE FROM downloads WHERE profile_id=?1", [profile_id])?;
+        for id in &ids {
+            self.clear_ranges(id)?;
+            self.clear_hls_state(id)?;
+        }
         Ok(ids)
     }
 
@@ -483,6 +1065,19 @@ impl DownloadDb {
             auto_delete: rec.auto_delete,
             subtitle_urls: None,
             subtitle_paths: None,
+            segment_concurrency: rec.segment_concurrency,
+            audio_languages: rec.audio_languages.clone(),
+            audio_tracks: None,
+            variant_id: None,
+            quality_targets: rec.quality_targets.clone(),
+            retry_count: 0,
+            rate_limit: rec.rate_limit,
+            not_before: None,
+            priority: rec.priority.clone(),
+            queue_position: 0,
+            request_headers: rec.request_headers.clone(),
+            pause_reason: None,
+            expected_size: None,
         };
 
         Ok(Some(next))
@@ -531,6 +1126,49 @@ impl DownloadDb {
         Ok(())
     }
 
+    /// Time (ms since the epoch) before which none of the profile's downloads start.
+    pub fn get_start_after(&self, profile_id: &str) -> Result<Option<i64>> {
+        let result = self.conn.query_row(
+            "SELECT start_after FROM profile_settings WHERE profile_id=?1",
+            [profile_id],
+            |r| r.get(0),
+        );
+        Ok(result.unwrap_or(None))
+    }
+
+    pub fn set_start_after(&self, profile_id: &str, start_after: Option<i64>) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO profile_settings (profile_id, start_after) VALUES (?1, ?2)
+             ON CONFLICT(profile_id) DO UPDATE SET start_after=excluded.start_after",
+            params![profile_id, start_after],
+        )?;
+        Ok(())
+    }
+
+    // ── Device-wide settings ───────────────────────────────────────────────────
+
+    pub fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let result =
+            self.conn
+                .query_row("SELECT value FROM app_settings WHERE key=?1", [key], |r| {
+                    r.get(0)
+                });
+        match result {
+            Ok(v) => Ok(Some(v)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO app_settings (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value=excluded.value",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
     #[allow(dead_code)]
     pub fn update_smart_flags(&self, id: &str, smart: bool, auto_delete: bool) -> Result<()> {
         self.conn.execute(
@@ -540,3 +1178,50 @@ impl DownloadDb {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db_with(id: &str) -> DownloadDb {
+        let db = DownloadDb::open(Path::new(":memory:")).unwrap();
+        db.conn
+            .execute(
+                "INSERT INTO downloads (id, profile_id, media_type, media_id, title, stream_url, added_at)
+                 VALUES (?1, 'p', 'movie', 'm', 'Title', 'https://example.com/a.mp4', 0)",
+                [id],
+            )
+            .unwrap();
+        db
+    }
+
+    #[test]
+    fn rate_limit_of_unknown_download() {
+        let db = db_with("a");
+        assert_eq!(db.update_rate_limit("a", Some(1024)), Ok(()));
+        assert_eq!(
+            db.update_rate_limit("b", Some(1024)),
+            Err(StateError::NotFound("b".into()))
+        );
+    }
+
+    #[test]
+    fn start_after_of_unknown_download() {
+        let db = db_with("a");
+        assert_eq!(db.update_not_before("a", Some(1)), Ok(()));
+        assert_eq!(
+            db.update_not_before("b", None),
+            Err(StateError::NotFound("b".into()))
+        );
+    }
+
+    #[test]
+    fn priority_of_unknown_download() {
+        let db = db_with("a");
+        assert_eq!(db.update_priority("a", &DownloadPriority::High), Ok(()));
+        assert_eq!(
+            db.update_priority("b", &DownloadPriority::High),
+            Err(StateError::NotFound("b".into()))
+        );
+    }
+}
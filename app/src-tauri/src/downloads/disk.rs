@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Below this much free space the watchdog pauses running downloads.
+pub const LOW_SPACE: u64 = 512 * 1024 * 1024;
+/// Downloads paused for a full disk resume once this much is free again, so a
+/// few freed megabytes do not make them flap.
+pub const RESUME_SPACE: u64 = 1024 * 1024 * 1024;
+
+/// Space promised to running downloads that have not written it yet. Each
+/// reservation shrinks as the download writes, so bytes already on disk,
+/// which free space accounts for, are not counted a second time.
+#[derive(Default)]
+pub struct DiskReservations {
+    reserved: Mutex<HashMap<String, u64>>,
+}
+
+impl DiskReservations {
+    /// Reserves `bytes` for download `id` if `dir`'s disk can hold them on top
+    /// of every other reservation without dropping below `LOW_SPACE`. Disks
+    /// whose free space cannot be read are assumed to have room.
+    pub fn reserve(&self, id: &str, dir: &Path, bytes: u64) -> bool {
+        self.reserve_within(id, available_space(dir), bytes)
+    }
+
+    fn reserve_within(&self, id: &str, free: Option<u64>, bytes: u64) -> bool {
+        let mut reserved = match self.reserved.lock() {
+            Ok(r) => r,
+            Err(_) => return true,
+        };
+        let others: u64 = reserved
+            .iter()
+            .filter(|(other, _)| other.as_str() != id)
+            .map(|(_, b)| b)
+            .sum();
+        let fits = match free {
+            Some(free) => free >= others.saturating_add(bytes).saturating_add(LOW_SPACE),
+            None => true,
+        };
+        if fits {
+            reserved.insert(id.to_string(), bytes);
+        }
+        fits
+    }
+
+    /// Whether `dir`'s disk could take `bytes` more right now, after existing
+    /// reservations, while keeping `RESUME_SPACE` free.
+    pub fn has_room(&self, dir: &Path, bytes: u64) -> bool {
+        self.has_room_within(available_space(dir), bytes)
+    }
+
+    fn has_room_within(&self, free: Option<u64>, bytes: u64) -> bool {
+        let others: u64 = self.reserved.lock().map(|r| r.values().sum()).unwrap_or(0);
+        match free {
+            Some(free) => free >= others.saturating_add(bytes).saturating_add(RESUME_SPACE),
+            None => true,
+        }
+    }
+
+    /// Lowers download `id`'s reservation to the `remaining` bytes it still
+    /// has to write. Never grows it, and ignores downloads without one.
+    pub fn shrink(&self, id: &str, remaining: u64) {
+        if let Ok(mut reserved) = self.reserved.lock() {
+            if let Some(bytes) = reserved.get_mut(id) {
+                *bytes = (*bytes).min(remaining);
+            }
+        }
+    }
+
+    pub fn release(&self, id: &str) {
+        if let Ok(mut reserved) = self.reserved.lock() {
+            reserved.remove(id);
+        }
+    }
+}
+
+/// Free space on the disk holding `dir`. Downloads directories are created
+/// on first use, so the nearest existing ancestor is measured.
+pub fn available_space(dir: &Path) -> Option<u64> {
+    let existing = dir.ancestors().find(|p| p.exists())?;
+    fs2::available_space(existing).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GB: u64 = 1024 * 1024 * 1024;
+
+    #[test]
+    fn reserve_counts_other_reservations() {
+        let disk = DiskReservations::default();
+        let free = Some(10 * GB + LOW_SPACE);
+        assert!(disk.reserve_within("a", free, 6 * GB));
+        assert!(!disk.reserve_within("b", free, 5 * GB));
+        assert!(disk.reserve_within("b", free, 4 * GB));
+        // Reserving again replaces the download's own reservation
+        assert!(disk.reserve_within("a", free, 6 * GB));
+    }
+
+    #[test]
+    fn unknown_free_space_always_fits() {
+        let disk = DiskReservations::default();
+        assert!(disk.reserve_within("a", None, u64::MAX));
+        assert!(disk.has_room_within(None, u64::MAX));
+    }
+
+    #[test]
+    fn release_frees_the_reservation() {
+        let disk = DiskReservations::default();
+        let free = Some(10 * GB + LOW_SPACE);
+        assert!(disk.reserve_within("a", free, 8 * GB));
+        assert!(!disk.reserve_within("b", free, 4 * GB));
+        disk.release("a");
+        assert!(disk.reserve_within("b", free, 4 * GB));
+    }
+
+    #[test]
+    fn has_room_keeps_resume_space_free() {
+        let disk = DiskReservations::default();
+        let free = Some(4 * GB + RESUME_SPACE);
+        assert!(disk.has_room_within(free, 4 * GB));
+        assert!(disk.reserve_within("a", free, GB));
+        assert!(!disk.has_room_within(free, 4 * GB));
+        assert!(disk.has_room_within(free, 3 * GB));
+    }
+
+    #[test]
+    fn written_bytes_are_not_counted_twice() {
+        let disk = DiskReservations::default();
+        assert!(disk.reserve_within("a", Some(10 * GB + RESUME_SPACE), 8 * GB));
+        // "a" wrote 6 GB: free space dropped by as much, and so does its
+        // reservation
+        let free = Some(4 * GB + RESUME_SPACE);
+        assert!(!disk.has_room_within(free, 2 * GB));
+        disk.shrink("a", 2 * GB);
+        assert!(disk.has_room_within(free, 2 * GB));
+        // Shrinking never grows a reservation, nor creates one
+        disk.shrink("a", 8 * GB);
+        disk.shrink("b", 8 * GB);
+        assert!(disk.has_room_within(free, 2 * GB));
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+use std::io;
+
+/// Why a download attempt failed. The engines return it so that
+/// `handle_failure` can decide between retrying, waiting for a fresh URL,
+/// pausing and failing without reading error messages.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadError {
+    /// The server answered with this non-success status
+    Http(u16),
+    /// The request could not be sent, or the response was cut off: connect
+    /// failures, timeouts, dropped connections
+    Network(String),
+    /// A local file operation failed
+    Io(io::ErrorKind, String),
+    /// The disk the download writes to is full
+    DiskFull,
+    /// The remote file changed between requests; the next attempt restarts it
+    RemoteChanged,
+    /// Stopped by pause or cancel
+    Cancelled,
+    /// Anything else: unparsable playlists, unsupported formats, DRM
+    Other(String),
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(status) => match reqwest::StatusCode::from_u16(*status) {
+                Ok(status) => write!(f, "Server answered HTTP {status}"),
+                Err(_) => write!(f, "Server answered HTTP {status}"),
+            },
+            Self::Network(e) => write!(f, "Network error: {e}"),
+            Self::Io(_, e) => write!(f, "File error: {e}"),
+            Self::DiskFull => write!(f, "Not enough disk space"),
+            Self::RemoteChanged => write!(
+                f,
+                "Remote file changed during download; it will restart on resume"
+            ),
+            Self::Cancelled => write!(f, "Download stopped"),
+            Self::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+impl From<String> for DownloadError {
+    fn from(e: String) -> Self {
+        Self::Other(e)
+    }
+}
+
+impl From<&str> for DownloadError {
+    fn from(e: &str) -> Self {
+        Self::Other(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(e: reqwest::Error) -> Self {
+        if let Some(status) = e.status() {
+            return Self::Http(status.as_u16());
+        }
+        // Malformed URLs and redirect loops fail the same way every time
+        if e.is_builder() || e.is_redirect() {
+            return Self::Other(e.to_string());
+        }
+        Self::Network(e.to_string())
+    }
+}
+
+impl From<io::Error> for DownloadError {
+    fn from(e: io::Error) -> Self {
+        // ENOSPC on Unix; ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL on Windows
+        let disk_full = if cfg!(windows) {
+            matches!(e.raw_os_error(), Some(39) | Some(112))
+        } else {
+            e.raw_os_error() == Some(28)
+        };
+        if disk_full {
+            Self::DiskFull
+        } else {
+            Self::Io(e.kind(), e.to_string())
+        }
+    }
+}
@@ -37,6 +37,41 @@ pub struct SmartNextPayload {
     pub auto_delete: bool,
 }
 
+/// Emitted when a download's stream URL has expired (HTTP 403 / 410).
+/// The frontend re-resolves the stream through the addon and calls
+/// download_update_url; the download then resumes from its part file.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlExpiredPayload {
+    pub id: String,
+    pub profile_id: String,
+    pub media_type: String,
+    pub media_id: String,
+    pub episode_id: Option<String>,
+    pub title: String,
+    pub episode_title: Option<String>,
+    pub season: Option<i64>,
+    pub episode: Option<i64>,
+    pub addon_id: String,
+    pub quality: String,
+}
+
+/// One entry of `download:queue`, sent whenever the queue order changes.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueEntry {
+    pub id: String,
+    /// 0 = next to start
+    pub position: usize,
+    pub priority: String,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuePayload {
+    pub items: Vec<QueueEntry>,
+}
+
 pub fn emit_progress(app: &AppHandle, payload: ProgressPayload) {
     let _ = app.emit("download:progress", payload);
 }
@@ -48,3 +83,11 @@ pub fn emit_status(app: &AppHandle, payload: StatusPayload) {
 pub fn emit_smart_next(app: &AppHandle, payload: SmartNextPayload) {
     let _ = app.emit("download:queue_next", payload);
 }
+
+pub fn emit_queue(app: &AppHandle, payload: QueuePayload) {
+    let _ = app.emit("download:queue", payload);
+}
+
+pub fn emit_url_expired(app: &AppHandle, payload: UrlExpiredPayload) {
+    let _ = app.emit("download:url_expired", payload);
+}
@@ -13,13 +13,32 @@ pub fn downloads_dir(app: &tauri::AppHandle, profile_id: &str) -> PathBuf {
     } else {
         safe_profile_id
     };
+    downloads_root(app).join(safe_profile_id)
+}
+
+/// Returns the directory holding every profile's downloads directory.
+pub fn downloads_root(app: &tauri::AppHandle) -> PathBuf {
     let base = custom_dir(app).unwrap_or_else(|| {
         app.path()
             .app_data_dir()
             .unwrap_or_else(|_| PathBuf::from("."))
             .join("zentrio")
     });
-    base.join("downloads").join(safe_profile_id)
+    base.join("downloads")
+}
+
+/// The file name stem of every file belonging to a download: the id without
+/// characters unsafe in paths.
+pub fn safe_id(id: &str) -> String {
+    let safe_id: String = id
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+        .collect();
+    if safe_id.is_empty() {
+        "unknown".to_string()
+    } else {
+        safe_id
+    }
 }
 
 /// Returns the path to the per-app downloads SQLite database.
@@ -33,30 +52,17 @@ pub fn db_path(app: &tauri::AppHandle) -> PathBuf {
 
 /// Returns a unique file path for a download (without extension — caller appends `.mp4`).
 pub fn download_file_path(app: &tauri::AppHandle, profile_id: &str, id: &str) -> PathBuf {
-    let safe_id: String = id
-        .chars()
-        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
-        .collect();
-    let safe_id = if safe_id.is_empty() {
-        "unknown".to_string()
-    } else {
-        safe_id
-    };
-    downloads_dir(app, profile_id).join(format!("{}.mp4", safe_id))
+    downloads_dir(app, profile_id).join(format!("{}.mp4", safe_id(id)))
+}
+
+/// Returns the `.ts` path used for HLS downloads that could not be remuxed to MP4.
+pub fn ts_file_path(app: &tauri::AppHandle, profile_id: &str, id: &str) -> PathBuf {
+    download_file_path(app, profile_id, id).with_extension("ts")
 }
 
 /// Returns the `.zentrio-part` temporary path for an in-progress download.
 pub fn part_file_path(app: &tauri::AppHandle, profile_id: &str, id: &str) -> PathBuf {
-    let safe_id: String = id
-        .chars()
-        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
-        .collect();
-    let safe_id = if safe_id.is_empty() {
-        "unknown".to_string()
-    } else {
-        safe_id
-    };
-    downloads_dir(app, profile_id).join(format!("{}.zentrio-part", safe_id))
+    downloads_dir(app, profile_id).join(format!("{}.zentrio-part", safe_id(id)))
 }
 
 /// Ensures the profile download directory exists.
@@ -99,16 +105,6 @@ pub fn subtitle_file_path(
     id: &str,
     lang: &str,
 ) -> PathBuf {
-    let safe_id: String = id
-        .chars()
-        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
-        .collect();
-    let safe_id = if safe_id.is_empty() {
-        "unknown".to_string()
-    } else {
-        safe_id
-    };
-
     // Sanitize lang to avoid path traversal
     let safe_lang: String = lang
         .chars()
@@ -119,13 +115,61 @@ pub fn subtitle_file_path(
     } else {
         safe_lang
     };
-    downloads_dir(app, profile_id).join(format!("{}_{}.vtt", safe_id, safe_lang))
+    downloads_dir(app, profile_id).join(format!("{}_{}.vtt", safe_id(id), safe_lang))
 }
 
-/// Deletes the download file (and any .zentrio-part) for a given ID.
+/// Returns the path of an alternate audio rendition kept next to the video
+/// (`ext` is `zentrio-part` while it downloads).
+pub fn audio_file_path(
+    app: &tauri::AppHandle,
+    profile_id: &str,
+    id: &str,
+    index: usize,
+    ext: &str,
+) -> PathBuf {
+    downloads_dir(app, profile_id).join(format!("{}_audio{}.{}", safe_id(id), index, ext))
+}
+
+/// Deletes the download file (and any .zentrio-part), its audio renditions
+/// and its subtitle files for a given ID.
 pub fn delete_files(app: &tauri::AppHandle, profile_id: &str, id: &str) {
     let _ = std::fs::remove_file(download_file_path(app, profile_id, id));
+    let _ = std::fs::remove_file(ts_file_path(app, profile_id, id));
     let _ = std::fs::remove_file(part_file_path(app, profile_id, id));
+    delete_audio_files(app, profile_id, id);
+    delete_subtitle_tracks(app, profile_id, id);
+}
+
+/// Deletes the `.vtt` files written by `subtitle_file_path` for a download.
+fn delete_subtitle_tracks(app: &tauri::AppHandle, profile_id: &str, id: &str) {
+    let prefix = format!("{}_", safe_id(id));
+    let entries = match std::fs::read_dir(downloads_dir(app, profile_id)) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with(&prefix) && name.ends_with(".vtt") {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Deletes the separate audio rendition files (finished or partial) of a download.
+fn delete_audio_files(app: &tauri::AppHandle, profile_id: &str, id: &str) {
+    let prefix = audio_file_path(app, profile_id, id, 0, "")
+        .file_name()
+        .map(|n| n.to_string_lossy().trim_end_matches("0.").to_string())
+        .unwrap_or_default();
+    let entries = match std::fs::read_dir(downloads_dir(app, profile_id)) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
 }
 
 /// Deletes subtitle files listed in a JSON subtitle_paths string.
@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::remux::mp4_box;
+
+/// Header of a top-level box read from a file.
+struct BoxHeader {
+    kind: [u8; 4],
+    header_len: u64,
+    /// Body length, or `None` for a box that extends to the end of the file
+    body_len: Option<u64>,
+}
+
+/// A fragmented MP4 input being merged: its tracks and read position.
+struct Input {
+    reader: BufReader<File>,
+    /// Original track ID → (track ID in the output, media timescale)
+    tracks: HashMap<u32, (u32, u32)>,
+    /// Next `moof` and its decode time in seconds, read ahead for interleaving
+    next: Option<(Vec<u8>, f64)>,
+}
+
+/// Merges fragmented MP4 files (the video stream first, then audio
+/// renditions) into a single fragmented MP4 at `output`. The movie boxes are
+/// combined and fragments are interleaved by decode time.
+pub fn merge_fragmented(inputs: &[&Path], output: &Path) -> Result<(), String> {
+    let mut opened: Vec<Input> = Vec::with_capacity(inputs.len());
+    let mut ftyp: Option<Vec<u8>> = None;
+    let mut movies: Vec<Vec<u8>> = Vec::with_capacity(inputs.len());
+
+    for path in inputs {
+        let mut reader = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+        let mut moov = None;
+        // Read the initialization boxes up to the first fragment
+        while let Some(header) = read_header(&mut reader).map_err(|e| e.to_string())? {
+            if &header.kind == b"moof" {
+                reader
+                    .seek(SeekFrom::Current(-(header.header_len as i64)))
+                    .map_err(|e| e.to_string())?;
+                break;
+            }
+            match &header.kind {
+                b"ftyp" if ftyp.is_none() => {
+                    ftyp = Some(read_box(&mut reader, &header).map_err(|e| e.to_string())?);
+                }
+                b"moov" if moov.is_none() => {
+                    moov = Some(read_box(&mut reader, &header).map_err(|e| e.to_string())?);
+                }
+                _ => skip_body(&mut reader, &header).map_err(|e| e.to_string())?,
+            }
+        }
+        let moov = moov.ok_or_else(|| format!("{} has no moov box", path.display()))?;
+        if find_child(&moov[8..], b"mvex").is_none() {
+            return Err(format!("{} is not a fragmented MP4", path.display()));
+        }
+        movies.push(moov);
+        opened.push(Input {
+            reader,
+            tracks: HashMap::new(),
+            next: None,
+        });
+    }
+
+    let moov = combine_movies(&movies, &mut opened)?;
+
+    let file = File::create(output).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::with_capacity(1 << 20, file);
+    writer
+        .write_all(
+            &ftyp.unwrap_or_else(|| mp4_box(b"ftyp", b"isom\0\0\x02\0isomiso6mp41".to_vec())),
+        )
+        .map_err(|e| e.to_string())?;
+    writer.write_all(&moov).map_err(|e| e.to_string())?;
+
+    for input in opened.iter_mut() {
+        input.next = next_fragment(input)?;
+    }
+    let mut sequence: u32 = 1;
+    loop {
+        let earliest = opened
+            .iter()
+            .enumerate()
+            .filter_map(|(i, input)| input.next.as_ref().map(|(_, time)| (i, *time)))
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        let index = match earliest {
+            Some((i, _)) => i,
+            None => break,
+        };
+        let input = &mut opened[index];
+        let (mut moof, _) = input.next.take().unwrap_or_default();
+        rewrite_moof(&mut moof, &input.tracks, sequence)?;
+        sequence += 1;
+        writer.write_all(&moof).map_err(|e| e.to_string())?;
+
+        // Copy the media data that follows this fragment
+        while let Some(header) = read_header(&mut input.reader).map_err(|e| e.to_string())? {
+            match &header.kind {
+                b"moof" => {
+                    input
+                        .reader
+                        .seek(SeekFrom::Current(-(header.header_len as i64)))
+                        .map_err(|e| e.to_string())?;
+                    break;
+                }
+                b"mdat" => {
+                    copy_box(&mut input.reader, &header, &mut writer).map_err(|e| e.to_string())?
+                }
+                // styp / sidx / emsg / prft, and repeated init sections
+                _ => skip_body(&mut input.reader, &header).map_err(|e| e.to_string())?,
+            }
+        }
+        input.next = next_fragment(input)?;
+    }
+
+    writer.flush().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Builds one `moov` holding the tracks of every input, renumbering track IDs.
+/// Only the first audio track stays enabled.
+fn combine_movies(movies: &[Vec<u8>], inputs: &mut [Input]) -> Result<Vec<u8>, String> {
+    let mut traks: Vec<u8> = Vec::new();
+    let mut trexs: Vec<u8> = Vec::new();
+    let mut next_id: u32 = 1;
+    let mut has_audio = false;
+
+    for (moov, input) in movies.iter().zip(inputs.iter_mut()) {
+        for (kind, child) in children(&moov[8..]) {
+            if &kind != b"trak" {
+                continue;
+            }
+            let mut trak = child.to_vec();
+            let (old_id, timescale, is_audio) = track_info(&trak)?;
+            let tkhd = child_offset(&trak, 8, b"tkhd").ok_or("trak has no tkhd")?;
+            let id_offset = tkhd + if trak[tkhd + 8] == 1 { 28 } else { 20 };
+            trak[id_offset..id_offset + 4].copy_from_slice(&next_id.to_be_bytes());
+            if is_audio {
+                // flags: enabled only for the first audio track, always in_movie
+                trak[tkhd + 11] = if has_audio { 0x02 } else { 0x03 };
+                has_audio = true;
+            }
+            input.tracks.insert(old_id, (next_id, timescale));
+            traks.extend_from_slice(&trak);
+            next_id += 1;
+        }
+        let mvex = find_child(&moov[8..], b"mvex").ok_or("moov has no mvex")?;
+        for (kind, child) in children(&mvex[8..]) {
+            if &kind == b"trex" && child.len() >= 16 {
+                let old_id = u32::from_be_bytes([child[12], child[13], child[14], child[15]]);
+                if let Some((new_id, _)) = input.tracks.get(&old_id) {
+                    let mut trex = child.to_vec();
+                    trex[12..16].copy_from_slice(&new_id.to_be_bytes());
+                    trexs.extend_from_slice(&trex);
+                }
+            }
+        }
+    }
+
+    // Movie header and other boxes (udta, mehd) come from the first input
+    let first = &movies[0][8..];
+    let mut mvhd = find_child(first, b"mvhd")
+        .ok_or("moov has no mvhd")?
+        .to_vec();
+    let len = mvhd.len();
+    mvhd[len - 4..].copy_from_slice(&next_id.to_be_bytes());
+
+    let mut mvex = find_child(first, b"mvex")
+        .and_then(|m| find_child(&m[8..], b"mehd"))
+        .map(|mehd| mehd.to_vec())
+        .unwrap_or_default();
+    mvex.extend_from_slice(&trexs);
+
+    let mut body = mvhd;
+    body.extend_from_slice(&traks);
+    body.extend_from_slice(&mp4_box(b"mvex", mvex));
+    for (kind, child) in children(first) {
+        if !matches!(&kind, b"mvhd" | b"trak" | b"mvex") {
+            body.extend_from_slice(child);
+        }
+    }
+    Ok(mp4_box(b"moov", body))
+}
+
+/// Returns `(track_ID, media timescale, is_audio)` of a `trak` box.
+fn track_info(trak: &[u8]) -> Result<(u32, u32, bool), String> {
+    let tkhd = find_child(&trak[8..], b"tkhd").ok_or("trak has no tkhd")?;
+    let id = read_u32(tkhd, if tkhd[8] == 1 { 28 } else { 20 }).ok_or("Truncated tkhd")?;
+    let mdia = find_child(&trak[8..], b"mdia").ok_or("trak has no mdia")?;
+    let mdhd = find_child(&mdia[8..], b"mdhd").ok_or("trak has no mdhd")?;
+    let timescale = read_u32(mdhd, if mdhd[8] == 1 { 28 } else { 20 }).ok_or("Truncated mdhd")?;
+    let is_audio = find_child(&mdia[8..], b"hdlr")
+        .and_then(|h| h.get(16..20))
+        .map(|handler| handler == b"soun")
+        .unwrap_or(false);
+    Ok((id, timescale.max(1), is_audio))
+}
+
+/// Reads the next `moof` of an input and its decode time in seconds.
+fn next_fragment(input: &mut Input) -> Result<Option<(Vec<u8>, f64)>, String> {
+    loop {
+        let header = match read_header(&mut input.reader).map_err(|e| e.to_string())? {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+        if &header.kind != b"moof" {
+            skip_body(&mut input.reader, &header).map_err(|e| e.to_string())?;
+            continue;
+        }
+        let moof = read_box(&mut input.reader, &header).map_err(|e| e.to_string())?;
+        let mut time = 0.0;
+        if let Some(traf) = find_child(&moof[8..], b"traf") {
+            let track = find_child(&traf[8..], b"tfhd").and_then(|t| read_u32(t, 12));
+            let timescale = track
+                .and_then(|id| input.tracks.get(&id))
+                .map(|(_, ts)| *ts)
+                .unwrap_or(1);
+            if let Some(tfdt) = find_child(&traf[8..], b"tfdt") {
+                let decode_time = if tfdt.get(8) == Some(&1) {
+                    tfdt.get(12..20)
+                        .map(|b| u64::from_be_bytes(b.try_into().unwrap_or_default()))
+                } else {
+                    read_u32(tfdt, 12).map(u64::from)
+                };
+                time = decode_time.unwrap_or(0) as f64 / timescale as f64;
+            }
+        }
+        return Ok(Some((moof, time)));
+    }
+}
+
+/// Renumbers a `moof` in place: its sequence number and the track ID of
+/// every track fragment.
+fn rewrite_moof(
+    moof: &mut [u8],
+    tracks: &HashMap<u32, (u32, u32)>,
+    sequence: u32,
+) -> Result<(), String> {
+    let mut pos = 8;
+    while pos + 8 <= moof.len() {
+        let size = read_u32(moof, pos).unwrap_or(0) as usize;
+        if size < 8 || pos + size > moof.len() {
+            return Err("Corrupt moof box".into());
+        }
+        match &moof[pos + 4..pos + 8] {
+            b"mfhd" if size >= 16 => {
+                moof[pos + 12..pos + 16].copy_from_slice(&sequence.to_be_bytes());
+            }
+            b"traf" => {
+                let tfhd = child_offset(&moof[pos..pos + size], 8, b"tfhd")
+                    .map(|o| pos + o)
+                    .ok_or("traf has no tfhd")?;
+                let flags = read_u32(moof, tfhd + 8).unwrap_or(0) & 0x00ff_ffff;
+                if flags & 0x01 != 0 {
+                    // Offsets relative to the source file cannot survive the merge
+                    return Err(
+                        "Fragments with an explicit base-data-offset are not supported".into(),
+                    );
+                }
+                let old_id = read_u32(moof, tfhd + 12).ok_or("Truncated tfhd")?;
+                let (new_id, _) = tracks
+                    .get(&old_id)
+                    .ok_or_else(|| format!("Fragment for unknown track {old_id}"))?;
+                moof[tfhd + 12..tfhd + 16].copy_from_slice(&new_id.to_be_bytes());
+            }
+            _ => {}
+        }
+        pos += size;
+    }
+    Ok(())
+}
+
+// ─── Box helpers ─────────────────────────────────────────────────────────────
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Child boxes of a container body, as `(type, whole box)`.
+fn children(body: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= body.len() {
+        let size = read_u32(body, pos).unwrap_or(0) as usize;
+        if size < 8 || pos + size > body.len() {
+            break;
+        }
+        let mut kind = [0u8; 4];
+        kind.copy_from_slice(&body[pos + 4..pos + 8]);
+        out.push((kind, &body[pos..pos + size]));
+        pos += size;
+    }
+    out
+}
+
+fn find_child<'a>(body: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    children(body)
+        .into_iter()
+        .find(|(k, _)| k == kind)
+        .map(|(_, b)| b)
+}
+
+/// Offset of the first `kind` child within `container`, whose children start at `start`.
+fn child_offset(container: &[u8], start: usize, kind: &[u8; 4]) -> Option<usize> {
+    let mut pos = start;
+    while pos + 8 <= container.len() {
+        let size = read_u32(container, pos)? as usize;
+        if size < 8 || pos + size > container.len() {
+            return None;
+        }
+        if &container[pos + 4..pos + 8] == kind {
+            return Some(pos);
+        }
+        pos += size;
+    }
+    None
+}
+
+fn read_header(reader: &mut impl Read) -> io::Result<Option<BoxHeader>> {
+    let mut head = [0u8; 8];
+    match reader.read_exact(&mut head) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut kind = [0u8; 4];
+    kind.copy_from_slice(&head[4..8]);
+    let size = u32::from_be_bytes([head[0], head[1], head[2], head[3]]) as u64;
+    let (header_len, body_len) = match size {
+        0 => (8, None),
+        1 => {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large)?;
+            let size = u64::from_be_bytes(large);
+            (16, Some(size.saturating_sub(16)))
+        }
+        _ => (8, Some(size.saturating_sub(8))),
+    };
+    Ok(Some(BoxHeader {
+        kind,
+        header_len,
+        body_len,
+    }))
+}
+
+/// Reads a whole box (header included) into memory.
+fn read_box(reader: &mut impl Read, header: &BoxHeader) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    match header.body_len {
+        Some(len) => {
+            body.resize(len as usize, 0);
+            reader.read_exact(&mut body)?;
+        }
+        None => {
+            reader.read_to_end(&mut body)?;
+        }
+    }
+    Ok(mp4_box(&header.kind, body))
+}
+
+fn skip_body(reader: &mut (impl Read + Seek), header: &BoxHeader) -> io::Result<()> {
+    match header.body_len {
+        Some(len) => reader.seek(SeekFrom::Current(len as i64)).map(|_| ()),
+        None => reader.seek(SeekFrom::End(0)).map(|_| ()),
+    }
+}
+
+/// Copies a box to `out` without buffering its body in memory.
+fn copy_box(reader: &mut impl Read, header: &BoxHeader, out: &mut impl Write) -> io::Result<()> {
+    let body_len = match header.body_len {
+        Some(len) => len,
+        None => {
+            // Size 0 (to end of file) is only valid for the last box, so give it a real size
+            let mut body = Vec::new();
+            reader.read_to_end(&mut body)?;
+            return out.write_all(&mp4_box(&header.kind, body));
+        }
+    };
+    if header.header_len == 16 || body_len + 8 > u32::MAX as u64 {
+        out.write_all(&1u32.to_be_bytes())?;
+        out.write_all(&header.kind)?;
+        out.write_all(&(body_len + 16).to_be_bytes())?;
+    } else {
+        out.write_all(&(body_len as u32 + 8).to_be_bytes())?;
+        out.write_all(&header.kind)?;
+    }
+    let copied = io::copy(&mut reader.take(body_len), out)?;
+    if copied != body_len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Truncated mdat box",
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use std::collections::BTreeMap;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+/// Extra request headers for one download, such as an addon's
+/// `behaviorHints.proxyHeaders` (Referer, User-Agent, Cookie).
+pub type RequestHeaders = BTreeMap<String, String>;
+
+/// Headers the engines manage themselves; stored values would corrupt
+/// ranged or resumed requests.
+const RESERVED: &[&str] = &[
+    "host",
+    "content-length",
+    "connection",
+    "transfer-encoding",
+    "range",
+    "if-range",
+];
+
+/// Builds the default headers of a download's HTTP client. A `User-Agent`
+/// entry replaces the built-in one. Fails on names or values that are not
+/// valid HTTP.
+pub fn header_map(headers: &RequestHeaders) -> Result<HeaderMap, String> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = name.trim();
+        if RESERVED.contains(&name.to_ascii_lowercase().as_str()) {
+            continue;
+        }
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("Invalid request header name: {name}"))?;
+        let value = HeaderValue::from_str(value.trim())
+            .map_err(|_| format!("Invalid value for request header {name}"))?;
+        map.insert(name, value);
+    }
+    Ok(map)
+}
+
+/// Headers stored in the `request_headers` column. Unreadable or invalid
+/// entries are dropped rather than failing the download.
+pub fn from_json(json: Option<&str>) -> HeaderMap {
+    let headers: RequestHeaders = json
+        .and_then(|j| serde_json::from_str(j).ok())
+        .unwrap_or_default();
+    header_map(&headers).unwrap_or_default()
+}
@@ -1,251 +1,1157 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
-use m3u8_rs::{MasterPlaylist, MediaPlaylist, Playlist};
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use futures_util::{StreamExt, TryStreamExt};
+use m3u8_rs::{
+    AlternativeMedia, AlternativeMediaType, ByteRange, Key, KeyMethod, MasterPlaylist,
+    MediaPlaylist, Playlist, VariantStream,
+};
 use reqwest::{Client, Url};
 use tauri::AppHandle;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 
-use super::db::DownloadDb;
-use super::events::{emit_progress, emit_status, ProgressPayload, StatusPayload};
+use super::db::{DownloadDb, SegmentState};
+use super::error::DownloadError;
+use super::events::{emit_progress, ProgressPayload};
 use super::file_store;
+use super::fmp4;
+use super::http::HttpClient;
+use super::manager::commit_complete;
 use super::notifier;
+use super::quality::{self, QualityTargets, VariantTraits};
+use super::remux;
+use super::retry::{self, SEGMENT_RETRY};
+use super::sample_aes;
+use super::subtitles::{self, SubtitlePathEntry};
+use super::tasks::TaskHandle;
+use super::throttle::Throttle;
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+/// Segments fetched in parallel when the download does not specify a level.
+const DEFAULT_SEGMENT_CONCURRENCY: usize = 4;
+/// Upper bound on parallel segment fetches for a single download.
+const MAX_SEGMENT_CONCURRENCY: usize = 16;
+
+/// A media segment resolved against its playlist URL.
+#[derive(Debug, Clone)]
+pub struct SegmentJob {
+    pub url: String,
+    /// Media sequence number — the IV when `EXT-X-KEY` does not give one
+    pub sequence: u64,
+    /// `(offset, length)` within `url` for `EXT-X-BYTERANGE` segments
+    pub byte_range: Option<(u64, u64)>,
+    pub key: Option<SegmentKey>,
+    /// Initialization section to write before this segment — set on the first
+    /// segment and wherever `EXT-X-MAP` changes
+    pub init: Option<InitSection>,
+}
+
+/// An `EXT-X-MAP` initialization section (fMP4 / CMAF streams).
+#[derive(Debug, Clone, PartialEq)]
+pub struct InitSection {
+    pub url: String,
+    pub byte_range: Option<(u64, u64)>,
+    pub key: Option<SegmentKey>,
+}
+
+/// Encryption that an `EXT-X-KEY` tag applies to a segment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentKey {
+    pub method: KeyMethod,
+    pub uri: String,
+    pub iv: Option<[u8; 16]>,
+}
+
+/// Keys fetched so far for one download, by key URI.
+type KeyCache = Arc<tokio::sync::Mutex<HashMap<String, [u8; 16]>>>;
+
+/// An `EXT-X-MEDIA TYPE=AUDIO` rendition (or DASH audio adaptation set)
+/// chosen for download.
+pub struct AudioRendition {
+    pub language: Option<String>,
+    pub name: String,
+    pub default: bool,
+    pub media_url: String,
+}
+
+/// An `EXT-X-MEDIA TYPE=SUBTITLES` rendition (segmented WebVTT).
+struct SubtitleRendition {
+    language: String,
+    media_url: String,
+}
+
+/// One media playlist to download into its own part file.
+pub struct StreamPlan {
+    /// Key of the stream's resume state in the DB
+    pub state_key: String,
+    pub jobs: Vec<SegmentJob>,
+    pub fingerprint: String,
+    pub part_path: PathBuf,
+}
+
+/// Progress shared by the video stream and its audio renditions.
+pub struct SegmentProgress {
+    total: usize,
+    done: usize,
+    bytes_total: i64,
+    last_progress: f64,
+    last_notif_pct: u8,
+    last_notif_time: Instant,
+    speed_start: Instant,
+    /// Counted as soon as a fetch finishes, so speed reflects segments still
+    /// waiting for their turn to be written.
+    bytes_fetched: Arc<AtomicI64>,
+}
+
+impl SegmentProgress {
+    pub fn new(total: usize) -> Self {
+        SegmentProgress {
+            total,
+            done: 0,
+            bytes_total: 0,
+            last_progress: -5.0,
+            last_notif_pct: 0,
+            last_notif_time: Instant::now(),
+            speed_start: Instant::now(),
+            bytes_fetched: Arc::new(AtomicI64::new(0)),
+        }
+    }
+
+    /// Records a written segment, persisting and emitting progress every 1%.
+    fn advance(
+        &mut self,
+        app: &AppHandle,
+        db: &Arc<Mutex<DownloadDb>>,
+        id: &str,
+        title: &str,
+        bytes: i64,
+    ) {
+        self.bytes_total += bytes;
+        self.done += 1;
+        let progress = self.done as f64 / self.total as f64 * 100.0;
+        if (progress - self.last_progress) < 1.0 {
+            return;
+        }
+        self.last_progress = progress;
+        if let Ok(d) = db.lock() {
+            d.update_progress(id, progress, self.bytes_total).ok();
+        }
+
+        let speed = self.bytes_fetched.load(Ordering::Relaxed) as f64
+            / self.speed_start.elapsed().as_secs_f64().max(0.001);
+        emit_progress(
+            app,
+            ProgressPayload {
+                id: id.to_string(),
+                progress,
+                downloaded_bytes: self.bytes_total,
+                speed,
+            },
+        );
+
+        let pct = progress as u8;
+        if pct / 10 > self.last_notif_pct / 10 || self.last_notif_time.elapsed().as_secs() >= 30 {
+            self.last_notif_pct = pct;
+            self.last_notif_time = Instant::now();
+            notifier::notify_progress(app, id, title, pct, speed / 1024.0);
+        }
+    }
+}
 
 /// Download an HLS stream given its master or media playlist URL.
 /// Outputs a concatenated MP4-compatible file at `final_path`.
 #[allow(clippy::too_many_arguments)]
 pub async fn download_hls(
-    app: AppHandle,
-    db: Arc<Mutex<DownloadDb>>,
-    paused: Arc<Mutex<Vec<String>>>,
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    task: &TaskHandle,
     id: &str,
     profile_id: &str,
     title: &str,
     playlist_url: &str,
-    quality_pref: &str, // "standard" | "higher" | "best"
-) -> Result<(), String> {
-    let client = Client::builder()
-        .user_agent("Zentrio/1.0")
-        .connect_timeout(std::time::Duration::from_secs(15))
-        .read_timeout(std::time::Duration::from_secs(120))
-        .build()
-        .map_err(|e| e.to_string())?;
-
+    quality_pref: &str,
+    targets: &QualityTargets,
+    segment_concurrency: Option<i64>,
+    audio_languages: &[String],
+    variant_id: Option<&str>,
+    client: &HttpClient,
+    throttle: &Throttle,
+) -> Result<(), DownloadError> {
     // ── 1. Fetch the playlist ──────────────────────────────────────────────────
     let playlist_bytes = client
         .get(playlist_url)
         .send()
-        .await
-        .map_err(|e| format!("Failed to fetch playlist: {e}"))?
-        .error_for_status()
-        .map_err(|e| format!("Playlist request failed: {e}"))?
+        .await?
+        .error_for_status()?
         .bytes()
-        .await
-        .map_err(|e| format!("Failed to read playlist body: {e}"))?;
+        .await?;
 
-    let segment_urls = match m3u8_rs::parse_playlist_res(&playlist_bytes) {
-        Ok(Playlist::MasterPlaylist(master)) => {
-            // Pick the best variant stream based on quality preference
-            let variant_url = pick_variant(&master, playlist_url, quality_pref)?;
-            fetch_media_segments(&client, &variant_url).await?
-        }
-        Ok(Playlist::MediaPlaylist(media)) => {
-            // Already a media playlist — resolve relative URIs against the base URL
-            resolve_segments(&media, playlist_url)
+    let (media, media_url, renditions, subtitle_renditions) =
+        match m3u8_rs::parse_playlist_res(&playlist_bytes) {
+            Ok(Playlist::MasterPlaylist(master)) => {
+                // Pick the best variant stream based on quality preference
+                let variant = pick_variant(&master, variant_id, targets, quality_pref)?;
+                let variant_url = resolve_url(playlist_url, &variant.uri)
+                    .ok_or_else(|| format!("Failed to resolve variant URL: {}", variant.uri))?;
+                let renditions =
+                    select_audio_renditions(&master, variant, playlist_url, audio_languages)?;
+                let subtitle_renditions =
+                    select_subtitle_renditions(&master, variant, playlist_url);
+                let media = fetch_media_playlist(client, &variant_url).await?;
+                (media, variant_url, renditions, subtitle_renditions)
+            }
+            Ok(Playlist::MediaPlaylist(media)) => {
+                (media, playlist_url.to_string(), Vec::new(), Vec::new())
+            }
+            Err(e) => {
+                return Err(format!("Failed to parse HLS playlist: {e:?}").into());
+            }
+        };
+    // Resolve relative URIs against the media playlist URL
+    let segment_jobs = resolve_segments(&media, &media_url)?;
+    if segment_jobs.is_empty() {
+        return Err("HLS playlist contained no segments".into());
+    }
+    let mut plans = vec![StreamPlan {
+        state_key: id.to_string(),
+        jobs: segment_jobs,
+        fingerprint: playlist_fingerprint(&media),
+        part_path: file_store::part_file_path(app, profile_id, id),
+    }];
+
+    for (i, rendition) in renditions.iter().enumerate() {
+        let audio_media = fetch_media_playlist(client, &rendition.media_url).await?;
+        let jobs = resolve_segments(&audio_media, &rendition.media_url)?;
+        if jobs.is_empty() {
+            return Err(format!(
+                "Audio rendition \"{}\" contained no segments",
+                rendition.name
+            )
+            .into());
         }
-        Err(e) => {
-            return Err(format!("Failed to parse HLS playlist: {e:?}"));
+        plans.push(StreamPlan {
+            state_key: format!("{id}#audio{i}"),
+            jobs,
+            fingerprint: playlist_fingerprint(&audio_media),
+            part_path: file_store::audio_file_path(app, profile_id, id, i, "zentrio-part"),
+        });
+    }
+
+    // ── 2. Download each stream's segments into its part file ─────────────────
+    let concurrency = concurrency_level(segment_concurrency);
+    let mut progress = SegmentProgress::new(plans.iter().map(|p| p.jobs.len()).sum());
+    for plan in &plans {
+        let finished = fetch_stream(
+            app,
+            db,
+            task,
+            client,
+            throttle,
+            id,
+            title,
+            plan,
+            concurrency,
+            &mut progress,
+        )
+        .await?;
+        if !finished {
+            return Ok(());
         }
-    };
+    }
 
-    if segment_urls.is_empty() {
-        return Err("HLS playlist contained no segments".into());
+    // ── 3. Stitch subtitle renditions into one WebVTT file each ──────────────
+    if !subtitle_renditions.is_empty() {
+        let reference_pts = remux::start_timestamp(&plans[0].part_path);
+        let added = download_subtitle_renditions(
+            app,
+            client,
+            throttle,
+            profile_id,
+            id,
+            &subtitle_renditions,
+            reference_pts,
+            concurrency,
+        )
+        .await;
+        if let Ok(d) = db.lock() {
+            let existing = d
+                .get_by_id(id)
+                .ok()
+                .flatten()
+                .and_then(|r| r.subtitle_paths);
+            if let Some(json) = subtitles::merge_subtitle_paths(existing.as_deref(), &added) {
+                d.update_subtitle_paths(id, &json).ok();
+            }
+        }
     }
 
-    // ── 2. Download all segments sequentially ─────────────────────────────────
-    let final_path = file_store::download_file_path(&app, profile_id, id);
-    let part_path = file_store::part_file_path(&app, profile_id, id);
+    // ── 4. Remux MPEG-TS into MP4 ────────────────────────────────────────────
+    let audio_parts: Vec<(&AudioRendition, &Path)> = renditions
+        .iter()
+        .zip(plans[1..].iter().map(|p| p.part_path.as_path()))
+        .collect();
+    let output =
+        finalize_output(app, task, profile_id, id, &plans[0].part_path, &audio_parts).await?;
+    let committed = commit_complete(app, db, task, id, title, &output.moves, &output.path, |d| {
+        d.clear_hls_state(id).ok();
+        if let Some(json) = &output.audio_tracks {
+            d.update_audio_tracks(id, json).ok();
+        }
+    });
+    output.settle(matches!(committed, Ok(true)));
+    committed?;
+
+    Ok(())
+}
+
+/// Downloads the segments of one stream into its part file, resuming after
+/// the last segment a previous attempt recorded. Segments are fetched in
+/// parallel and written in playlist order. Returns `false` if stopped.
+#[allow(clippy::too_many_arguments)]
+pub async fn fetch_stream(
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    task: &TaskHandle,
+    client: &HttpClient,
+    throttle: &Throttle,
+    id: &str,
+    title: &str,
+    plan: &StreamPlan,
+    concurrency: usize,
+    progress: &mut SegmentProgress,
+) -> Result<bool, DownloadError> {
+    let key = plan.state_key.as_str();
+    let part_path = &plan.part_path;
+
+    // Continue after the last segment written by a previous attempt, provided
+    // the playlist still describes the same media.
+    let resume = db
+        .lock()
+        .map_err(|_| "DB lock poisoned".to_string())?
+        .get_hls_state(key)
+        .map_err(|e| e.to_string())?
+        .and_then(|(saved, segments)| {
+            if saved != plan.fingerprint {
+                log::warn!("[HLS] Playlist changed since last attempt for {key}; restarting");
+                return None;
+            }
+            resume_point(&segments, file_store::file_size(part_path))
+        });
+
+    let (first_segment, resume_offset) = match resume {
+        Some((next_index, offset)) if part_path.exists() => (next_index, offset),
+        _ => {
+            let _ = tokio::fs::remove_file(part_path).await;
+            db.lock()
+                .map_err(|_| "DB lock poisoned".to_string())?
+                .begin_hls_state(key, &plan.fingerprint, progress.total as i64)
+                .map_err(|e| e.to_string())?;
+            (0, 0)
+        }
+    };
 
-    // HLS segment-level resume is not implemented, so always restart the temp file cleanly.
-    let _ = tokio::fs::remove_file(&part_path).await;
     let mut output = tokio::fs::OpenOptions::new()
         .create(true)
         .write(true)
-        .truncate(true)
-        .open(&part_path)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let total = segment_urls.len();
-    let mut done = 0usize;
-    let mut last_progress = -5.0_f64;
-    let mut last_notif_pct: u8 = 0;
-    let mut last_notif_time = Instant::now();
-    let speed_start = Instant::now();
-    let mut bytes_total: i64 = 0;
-
-    for url in &segment_urls {
-        // Pause / cancel check — manager.pause() already updated DB and emitted event
-        if paused
-            .lock()
-            .map(|p| p.contains(&id.to_string()))
-            .unwrap_or(false)
-        {
+        .truncate(false)
+        .open(part_path)
+        .await?;
+    // Drop any partially written segment past the last recorded one
+    output.set_len(resume_offset as u64).await?;
+    output
+        .seek(std::io::SeekFrom::Start(resume_offset as u64))
+        .await?;
+
+    if first_segment > 0 {
+        log::info!(
+            "[HLS] Resuming {key} at segment {first_segment}/{} (byte {resume_offset})",
+            plan.jobs.len()
+        );
+    }
+    progress.done += first_segment;
+    progress.bytes_total += resume_offset;
+    let mut index = first_segment;
+    let mut offset = resume_offset;
+
+    // `buffered` keeps up to `concurrency` fetches in flight and yields their
+    // results in playlist order, so segments may arrive out of order but are
+    // always appended in sequence.
+    let keys: KeyCache = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+    let bytes_fetched = Arc::clone(&progress.bytes_fetched);
+    let mut segments = futures_util::stream::iter(plan.jobs.iter().skip(first_segment).cloned())
+        .map(|job| {
+            let client = client.clone();
+            let throttle = throttle.clone();
+            let keys = Arc::clone(&keys);
+            let bytes_fetched = Arc::clone(&bytes_fetched);
+            async move {
+                let mut bytes = Vec::new();
+                if let Some(init) = &job.init {
+                    let data =
+                        download_segment(&client, &throttle, &init.url, init.byte_range).await?;
+                    bytes_fetched.fetch_add(data.len() as i64, Ordering::Relaxed);
+                    bytes = decrypt_segment(&client, &keys, init.key.as_ref(), job.sequence, data)
+                        .await?;
+                }
+                let data = download_segment(&client, &throttle, &job.url, job.byte_range).await?;
+                bytes_fetched.fetch_add(data.len() as i64, Ordering::Relaxed);
+                bytes.extend(
+                    decrypt_segment(&client, &keys, job.key.as_ref(), job.sequence, data).await?,
+                );
+                Ok::<_, DownloadError>(bytes)
+            }
+        })
+        .buffered(concurrency);
+
+    loop {
+        // Stopped by pause / cancel / the download window — the manager has
+        // already recorded the status. Returning drops the stream, which
+        // aborts any fetches still in flight.
+        if task.is_stopped() {
             output.flush().await.ok();
-            return Ok(());
+            return Ok(false);
         }
 
-        let seg_bytes = download_segment(&client, url).await.map_err(|e| {
-            let msg = format!("Segment fetch failed: {e}");
-            db.lock().unwrap().update_error(id, &msg).ok();
-            emit_status(
-                &app,
-                StatusPayload {
-                    id: id.to_string(),
-                    status: "failed".into(),
-                    file_path: None,
-                    error: Some(msg.clone()),
+        let seg_bytes = match segments.next().await {
+            Some(result) => result?,
+            None => break,
+        };
+
+        output.write_all(&seg_bytes).await?;
+        output.flush().await?;
+
+        // Record the segment only once its bytes have reached the file
+        if let Ok(d) = db.lock() {
+            d.record_hls_segment(
+                key,
+                &SegmentState {
+                    index: index as i64,
+                    byte_offset: offset,
+                    byte_length: seg_bytes.len() as i64,
                 },
-            );
-            notifier::notify_failed(&app, title);
-            msg
-        })?;
+            )
+            .ok();
+        }
+
+        index += 1;
+        offset += seg_bytes.len() as i64;
+        progress.advance(app, db, id, title, seg_bytes.len() as i64);
+    }
+    drop(segments);
+
+    output.flush().await?;
+    Ok(true)
+}
+
+// ─── Helpers ─────────────────────────────────────────────────────────────────
+
+/// Number of segments to fetch in parallel for a download's configured level.
+pub fn concurrency_level(segment_concurrency: Option<i64>) -> usize {
+    segment_concurrency
+        .map(|c| c.clamp(1, MAX_SEGMENT_CONCURRENCY as i64) as usize)
+        .unwrap_or(DEFAULT_SEGMENT_CONCURRENCY)
+}
 
-        bytes_total += seg_bytes.len() as i64;
-        output
-            .write_all(&seg_bytes)
+/// The output of a finished download, ready for `commit_complete`.
+pub struct FinalOutput {
+    /// The file the download completes with
+    pub path: PathBuf,
+    /// Part files to move to their final paths on commit
+    pub moves: Vec<(PathBuf, PathBuf)>,
+    /// JSON describing the audio renditions, for `audio_tracks`
+    pub audio_tracks: Option<String>,
+    /// Part files the muxed output replaces
+    replaced: Vec<PathBuf>,
+    /// Written by the muxer ahead of the commit
+    written: Option<PathBuf>,
+}
+
+impl FinalOutput {
+    /// Removes the part files a committed output replaced, or else the muxed
+    /// output, keeping the part files for a resume.
+    pub fn settle(&self, committed: bool) {
+        let stale = if committed {
+            &self.replaced[..]
+        } else {
+            self.written.as_slice()
+        };
+        for path in stale {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Prepares the finished part files for their final paths. MPEG-TS output
+/// is remuxed into a faststart MP4 together with the audio renditions, or
+/// kept as `.ts` if that fails (e.g. E-AC-3 with dependent substreams).
+/// fMP4 output is already an MP4: it is merged with fMP4 audio renditions,
+/// or renamed as-is. Renditions that could not be muxed are kept as separate
+/// files.
+///
+/// Part files stay in place until the output is committed and settled.
+pub async fn finalize_output(
+    app: &AppHandle,
+    task: &TaskHandle,
+    profile_id: &str,
+    id: &str,
+    part_path: &Path,
+    audio: &[(&AudioRendition, &Path)],
+) -> Result<FinalOutput, DownloadError> {
+    let mp4_path = file_store::download_file_path(app, profile_id, id);
+    let mut moves = Vec::new();
+    let mut muxed = false;
+    let (path, written) = if !remux::is_transport_stream_file(part_path) {
+        if !audio.is_empty() {
+            let mut inputs = vec![part_path.to_path_buf()];
+            inputs.extend(audio.iter().map(|(_, path)| path.to_path_buf()));
+            let (output, stop) = (mp4_path.clone(), task.clone());
+            let merged = tokio::task::spawn_blocking(move || {
+                let inputs: Vec<&Path> = inputs.iter().map(PathBuf::as_path).collect();
+                discard_if_stopped(&stop, &output, fmp4::merge_fragmented(&inputs, &output))
+            })
             .await
             .map_err(|e| e.to_string())?;
+            if task.is_stopped() {
+                return Err(DownloadError::Cancelled);
+            }
+            match merged {
+                Ok(()) => muxed = true,
+                Err(e) => {
+                    log::warn!("[HLS] Could not merge audio renditions into {id}, keeping them separate: {e}");
+                    let _ = tokio::fs::remove_file(&mp4_path).await;
+                }
+            }
+        }
+        if muxed {
+            (mp4_path.clone(), Some(mp4_path))
+        } else {
+            moves.push((part_path.to_path_buf(), mp4_path.clone()));
+            (mp4_path, None)
+        }
+    } else {
+        let extra: Vec<remux::AudioInput> = audio
+            .iter()
+            .map(|(rendition, path)| remux::AudioInput {
+                path: path.to_path_buf(),
+                language: rendition.language.clone(),
+                name: rendition.name.clone(),
+            })
+            .collect();
+        let mut remuxed = remux_blocking(task, part_path, extra.clone(), &mp4_path).await;
+        if let Err(e) = &remuxed {
+            if !extra.is_empty() && !task.is_stopped() {
+                log::warn!(
+                    "[HLS] Could not mux audio renditions into {id}, keeping them separate: {e}"
+                );
+                remuxed = remux_blocking(task, part_path, Vec::new(), &mp4_path).await;
+            }
+        } else {
+            muxed = true;
+        }
+        if task.is_stopped() {
+            return Err(DownloadError::Cancelled);
+        }
 
-        done += 1;
-        let progress = done as f64 / total as f64 * 100.0;
+        match remuxed {
+            Ok(()) => (mp4_path.clone(), Some(mp4_path)),
+            Err(e) => {
+                log::warn!("[HLS] Could not remux {id} to MP4, keeping MPEG-TS: {e}");
+                let _ = tokio::fs::remove_file(&mp4_path).await;
+                let ts_path = file_store::ts_file_path(app, profile_id, id);
+                moves.push((part_path.to_path_buf(), ts_path.clone()));
+                (ts_path, None)
+            }
+        }
+    };
 
-        if (progress - last_progress) >= 1.0 {
-            last_progress = progress;
-            db.lock()
-                .unwrap()
-                .update_progress(id, progress, bytes_total)
-                .ok();
-
-            let speed = bytes_total as f64 / speed_start.elapsed().as_secs_f64().max(0.001);
-            emit_progress(
-                &app,
-                ProgressPayload {
-                    id: id.to_string(),
-                    progress,
-                    downloaded_bytes: bytes_total,
-                    speed,
-                },
-            );
+    // The muxed output replaces the video part file, and the audio part
+    // files too if they went into it
+    let mut replaced = Vec::new();
+    if written.is_some() {
+        replaced.push(part_path.to_path_buf());
+    }
+    let mut audio_tracks = None;
+    if !audio.is_empty() {
+        let mut tracks = Vec::with_capacity(audio.len());
+        for (i, (rendition, path)) in audio.iter().enumerate() {
+            let kept = if muxed {
+                replaced.push(path.to_path_buf());
+                None
+            } else {
+                let ext = remux::audio_file_extension(path);
+                let dest = file_store::audio_file_path(app, profile_id, id, i, ext);
+                moves.push((path.to_path_buf(), dest.clone()));
+                Some(dest.to_string_lossy().to_string())
+            };
+            tracks.push(serde_json::json!({
+                "language": rendition.language,
+                "name": rendition.name,
+                "default": rendition.default,
+                "path": kept,
+            }));
+        }
+        audio_tracks = serde_json::to_string(&tracks).ok();
+    }
+    Ok(FinalOutput {
+        path,
+        moves,
+        audio_tracks,
+        replaced,
+        written,
+    })
+}
 
-            let pct = progress as u8;
-            if pct / 10 > last_notif_pct / 10 || last_notif_time.elapsed().as_secs() >= 30 {
-                last_notif_pct = pct;
-                last_notif_time = Instant::now();
-                notifier::notify_progress(&app, id, title, pct, speed / 1024.0);
+/// Fetches each subtitle rendition and stitches its WebVTT segments into one
+/// file. Returns the files written; renditions that fail are logged and
+/// skipped, as subtitles are not essential to the download.
+#[allow(clippy::too_many_arguments)]
+async fn download_subtitle_renditions(
+    app: &AppHandle,
+    client: &HttpClient,
+    throttle: &Throttle,
+    profile_id: &str,
+    id: &str,
+    renditions: &[SubtitleRendition],
+    reference_pts: Option<i64>,
+    concurrency: usize,
+) -> Vec<SubtitlePathEntry> {
+    let mut written = Vec::new();
+    for (i, rendition) in renditions.iter().enumerate() {
+        // Prefixed so they never collide with standalone subtitles of the same language
+        let path = file_store::subtitle_file_path(
+            app,
+            profile_id,
+            id,
+            &format!("hls{i}-{}", rendition.language),
+        );
+        if !path.exists() {
+            let vtt =
+                match fetch_webvtt_segments(client, throttle, &rendition.media_url, concurrency)
+                    .await
+                {
+                    Ok(segments) => subtitles::stitch_webvtt(&segments, reference_pts),
+                    Err(e) => {
+                        log::warn!(
+                            "[HLS] Failed to download subtitles lang={}: {e}",
+                            rendition.language
+                        );
+                        continue;
+                    }
+                };
+            if let Err(e) = tokio::fs::write(&path, vtt).await {
+                log::warn!(
+                    "[HLS] Failed to write subtitles lang={}: {e}",
+                    rendition.language
+                );
+                continue;
             }
         }
+        written.push(SubtitlePathEntry {
+            lang: rendition.language.clone(),
+            path: path.to_string_lossy().to_string(),
+        });
     }
+    written
+}
 
-    output.flush().await.map_err(|e| e.to_string())?;
-    drop(output);
-
-    tokio::fs::rename(&part_path, &final_path)
+/// Downloads the WebVTT segments of a subtitle playlist, in playlist order.
+async fn fetch_webvtt_segments(
+    client: &HttpClient,
+    throttle: &Throttle,
+    media_url: &str,
+    concurrency: usize,
+) -> Result<Vec<String>, DownloadError> {
+    let media = fetch_media_playlist(client, media_url).await?;
+    let jobs = resolve_segments(&media, media_url)?;
+    let keys: KeyCache = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+    futures_util::stream::iter(jobs)
+        .map(|job| {
+            let client = client.clone();
+            let throttle = throttle.clone();
+            let keys = Arc::clone(&keys);
+            async move {
+                let data = download_segment(&client, &throttle, &job.url, job.byte_range).await?;
+                let data =
+                    decrypt_segment(&client, &keys, job.key.as_ref(), job.sequence, data).await?;
+                Ok::<_, DownloadError>(String::from_utf8_lossy(&data).into_owned())
+            }
+        })
+        .buffered(concurrency)
+        .try_collect()
         .await
-        .map_err(|e| e.to_string())?;
-
-    let size = file_store::file_size(&final_path);
-    db.lock()
-        .unwrap()
-        .update_complete(id, &final_path.to_string_lossy(), size)
-        .ok();
-
-    emit_status(
-        &app,
-        StatusPayload {
-            id: id.to_string(),
-            status: "completed".into(),
-            file_path: Some(final_path.to_string_lossy().to_string()),
-            error: None,
-        },
-    );
-    notifier::notify_complete(&app, title);
+}
 
-    Ok(())
+/// Runs the remuxer on a blocking thread.
+async fn remux_blocking(
+    task: &TaskHandle,
+    input: &Path,
+    extra_audio: Vec<remux::AudioInput>,
+    output: &Path,
+) -> Result<(), String> {
+    let (input, output, task) = (input.to_path_buf(), output.to_path_buf(), task.clone());
+    tokio::task::spawn_blocking(move || {
+        let remuxed = remux::remux_ts_to_mp4(&input, &extra_audio, &output);
+        discard_if_stopped(&task, &output, remuxed)
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
 
-// ─── Helpers ─────────────────────────────────────────────────────────────────
+/// Removes a muxer's `output` if the task stopped while it ran. The engine is
+/// dropped on a stop but the blocking muxer runs to the end, and would
+/// otherwise leave a file behind a cancel or delete.
+fn discard_if_stopped(
+    task: &TaskHandle,
+    output: &Path,
+    result: Result<(), String>,
+) -> Result<(), String> {
+    if task.is_stopped() {
+        let _ = std::fs::remove_file(output);
+        return Err("Download stopped".into());
+    }
+    result
+}
 
-fn pick_variant(
-    master: &MasterPlaylist,
-    base_url: &str,
+/// Picks the variant pinned by `variant_id` (its URI as listed in the master
+/// playlist), or the best match for the quality targets.
+/// I-frame-only variants are never picked.
+pub fn pick_variant<'a>(
+    master: &'a MasterPlaylist,
+    variant_id: Option<&str>,
+    targets: &QualityTargets,
     quality_pref: &str,
-) -> Result<String, String> {
-    if master.variants.is_empty() {
+) -> Result<&'a VariantStream, String> {
+    let variants: Vec<&VariantStream> = master.variants.iter().filter(|v| !v.is_i_frame).collect();
+    if variants.is_empty() {
         return Err("Master playlist has no variants".into());
     }
+    if let Some(wanted) = variant_id {
+        if let Some(variant) = variants.iter().find(|v| v.uri == wanted) {
+            return Ok(variant);
+        }
+        log::warn!("[HLS] Variant {wanted} is not in the master playlist; picking by quality");
+    }
+    quality::pick_variant_for(&variants, |v| variant_traits(v), targets, quality_pref).copied()
+}
 
-    // Sort by bandwidth descending
-    let mut variants: Vec<_> = master.variants.iter().collect();
-    variants.sort_by_key(|v| std::cmp::Reverse(v.bandwidth));
+pub fn variant_traits(variant: &VariantStream) -> VariantTraits {
+    let video_range = variant
+        .other_attributes
+        .as_ref()
+        .and_then(|attrs| attrs.get("VIDEO-RANGE"))
+        .map(|v| v.as_str());
+    VariantTraits::new(
+        variant.resolution.map(|r| r.height),
+        variant.codecs.as_deref(),
+        video_range,
+        variant.frame_rate,
+        variant.bandwidth,
+    )
+}
 
-    // Map quality preference to a maximum bandwidth cap (rough heuristic)
-    let selected = match quality_pref {
-        "standard" => {
-            // Pick the lowest quality that is ≥ 720p or the first available
-            variants
-                .iter()
-                .rev()
-                .find(|v| v.bandwidth >= 1_000_000)
-                .or_else(|| variants.last())
-        }
-        "higher" => {
-            // Pick the best quality ≤ 8 Mbps (≈1080p)
-            variants
-                .iter()
-                .find(|v| v.bandwidth <= 8_000_000)
-                .or_else(|| variants.first())
+/// Picks the `EXT-X-MEDIA TYPE=AUDIO` renditions of the variant's audio group
+/// to download: one per preferred language, in preference order, or the
+/// group's DEFAULT rendition when no preference matches. Renditions without a
+/// URI are carried in the variant's own segments and need no separate download.
+fn select_audio_renditions(
+    master: &MasterPlaylist,
+    variant: &VariantStream,
+    base_url: &str,
+    languages: &[String],
+) -> Result<Vec<AudioRendition>, String> {
+    let group = match &variant.audio {
+        Some(g) => g,
+        None => return Ok(Vec::new()),
+    };
+    let candidates: Vec<&AlternativeMedia> = master
+        .alternatives
+        .iter()
+        .filter(|m| m.media_type == AlternativeMediaType::Audio && &m.group_id == group)
+        .collect();
+
+    let primary = |tag: &str| {
+        tag.split(['-', '_'])
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase()
+    };
+    let mut selected: Vec<&AlternativeMedia> = Vec::new();
+    for wanted in languages {
+        let matches = |m: &&&AlternativeMedia| {
+            m.language
+                .as_deref()
+                .is_some_and(|l| l.eq_ignore_ascii_case(wanted) || primary(l) == primary(wanted))
+        };
+        // Prefer an exact tag, then the DEFAULT rendition among same-language ones
+        let pick = candidates
+            .iter()
+            .find(|m| {
+                m.language
+                    .as_deref()
+                    .is_some_and(|l| l.eq_ignore_ascii_case(wanted))
+            })
+            .or_else(|| candidates.iter().filter(matches).find(|m| m.default))
+            .or_else(|| candidates.iter().find(matches));
+        if let Some(m) = pick {
+            if !selected.iter().any(|s| std::ptr::eq(*s, *m)) {
+                selected.push(m);
+            }
         }
-        _ => {
-            // "best" — pick the highest bandwidth
-            variants.first()
+    }
+    if selected.is_empty() {
+        if !languages.is_empty() {
+            log::warn!(
+                "[HLS] No audio rendition matches {}; using the default",
+                languages.join(", ")
+            );
         }
-    };
+        selected.extend(
+            candidates
+                .iter()
+                .find(|m| m.default)
+                .or_else(|| candidates.first()),
+        );
+    }
 
-    let variant = selected.ok_or("No suitable variant found")?;
-    let uri = &variant.uri;
+    selected
+        .into_iter()
+        .filter_map(|m| m.uri.as_deref().map(|uri| (m, uri)))
+        .map(|(m, uri)| {
+            Ok(AudioRendition {
+                language: m.language.clone(),
+                name: m.name.clone(),
+                default: m.default,
+                media_url: resolve_url(base_url, uri)
+                    .ok_or_else(|| format!("Failed to resolve audio rendition URL: {uri}"))?,
+            })
+        })
+        .collect()
+}
 
-    resolve_url(base_url, uri).ok_or_else(|| format!("Failed to resolve variant URL: {}", uri))
+/// Returns the `EXT-X-MEDIA TYPE=SUBTITLES` renditions of the variant's
+/// subtitle group, one per language / name.
+fn select_subtitle_renditions(
+    master: &MasterPlaylist,
+    variant: &VariantStream,
+    base_url: &str,
+) -> Vec<SubtitleRendition> {
+    let group = match &variant.subtitles {
+        Some(g) => g,
+        None => return Vec::new(),
+    };
+    master
+        .alternatives
+        .iter()
+        .filter(|m| m.media_type == AlternativeMediaType::Subtitles && &m.group_id == group)
+        .filter_map(|m| {
+            let media_url = resolve_url(base_url, m.uri.as_deref()?)?;
+            let mut language = m.language.clone().unwrap_or_else(|| m.name.clone());
+            if m.forced {
+                language.push_str("-forced");
+            }
+            Some(SubtitleRendition {
+                language,
+                media_url,
+            })
+        })
+        .collect()
 }
 
-async fn fetch_media_segments(client: &Client, media_url: &str) -> Result<Vec<String>, String> {
+pub async fn fetch_media_playlist(
+    client: &Client,
+    media_url: &str,
+) -> Result<MediaPlaylist, DownloadError> {
     let bytes = client
         .get(media_url)
         .send()
-        .await
-        .map_err(|e| e.to_string())?
-        .error_for_status()
-        .map_err(|e| e.to_string())?
+        .await?
+        .error_for_status()?
         .bytes()
-        .await
-        .map_err(|e| e.to_string())?;
+        .await?;
 
     match m3u8_rs::parse_playlist_res(&bytes) {
-        Ok(Playlist::MediaPlaylist(media)) => Ok(resolve_segments(&media, media_url)),
+        Ok(Playlist::MediaPlaylist(media)) => Ok(media),
         Ok(Playlist::MasterPlaylist(_)) => Err("Unexpected nested master playlist".into()),
-        Err(e) => Err(format!("Failed to parse media playlist: {e:?}")),
+        Err(e) => Err(format!("Failed to parse media playlist: {e:?}").into()),
     }
 }
 
-fn resolve_segments(media: &MediaPlaylist, base_url: &str) -> Vec<String> {
-    media
-        .segments
-        .iter()
-        .filter_map(|seg| resolve_url(base_url, &seg.uri))
-        .collect()
+/// Resolves every segment URI and attaches the `EXT-X-KEY`, `EXT-X-MAP` and
+/// `EXT-X-BYTERANGE` in effect for it.
+/// Fails for key methods or key formats the engine cannot decrypt.
+fn resolve_segments(media: &MediaPlaylist, base_url: &str) -> Result<Vec<SegmentJob>, String> {
+    let mut jobs = Vec::with_capacity(media.segments.len());
+    // m3u8-rs only attaches a key / map to the segment right after the tag, so carry them forward
+    let mut current_key: Option<SegmentKey> = None;
+    let mut current_init: Option<InitSection> = None;
+    let mut written_init: Option<InitSection> = None;
+    // End of the last sub-range per URI, where a BYTERANGE without offset continues
+    let mut range_ends: HashMap<String, u64> = HashMap::new();
+
+    for (i, seg) in media.segments.iter().enumerate() {
+        if let Some(key) = &seg.key {
+            current_key = segment_key(key, base_url)?;
+        }
+        if let Some(map) = &seg.map {
+            let url = resolve_url(base_url, &map.uri)
+                .ok_or_else(|| format!("Failed to resolve init section URL: {}", map.uri))?;
+            let key = match &current_key {
+                Some(k) if k.method == KeyMethod::AES128 && k.iv.is_none() => {
+                    return Err("Encrypted EXT-X-MAP section has no explicit IV".into());
+                }
+                Some(k) if k.method == KeyMethod::AES128 => Some(k.clone()),
+                _ => None,
+            };
+            current_init = Some(InitSection {
+                url,
+                byte_range: map
+                    .byte_range
+                    .as_ref()
+                    .map(|r| (r.offset.unwrap_or(0), r.length)),
+                key,
+            });
+        }
+        if current_init.is_some()
+            && matches!(&current_key, Some(k) if k.method == KeyMethod::SampleAES)
+        {
+            return Err("SAMPLE-AES is not supported for fragmented MP4 (CMAF) HLS streams".into());
+        }
+
+        let url = match resolve_url(base_url, &seg.uri) {
+            Some(u) => u,
+            None => continue,
+        };
+        let byte_range = seg
+            .byte_range
+            .as_ref()
+            .map(|r| sub_range(r, &url, &mut range_ends));
+
+        let init = if current_init != written_init {
+            written_init = current_init.clone();
+            current_init.clone()
+        } else {
+            None
+        };
+
+        jobs.push(SegmentJob {
+            url,
+            sequence: media.media_sequence + i as u64,
+            byte_range,
+            key: current_key.clone(),
+            init,
+        });
+    }
+    Ok(jobs)
+}
+
+/// Returns `(offset, length)` for an `EXT-X-BYTERANGE`. Without an explicit
+/// offset the sub-range starts where the previous one of the same URI ended.
+fn sub_range(range: &ByteRange, url: &str, range_ends: &mut HashMap<String, u64>) -> (u64, u64) {
+    let offset = range
+        .offset
+        .unwrap_or_else(|| range_ends.get(url).copied().unwrap_or(0));
+    range_ends.insert(url.to_string(), offset + range.length);
+    (offset, range.length)
+}
+
+fn segment_key(key: &Key, base_url: &str) -> Result<Option<SegmentKey>, String> {
+    match &key.method {
+        KeyMethod::None => Ok(None),
+        KeyMethod::AES128 | KeyMethod::SampleAES => {
+            if let Some(format) = key.keyformat.as_deref() {
+                if format != "identity" {
+                    return Err(format!(
+                        "Unsupported HLS key format \"{format}\" (DRM-protected stream)"
+                    ));
+                }
+            }
+            let uri = key
+                .uri
+                .as_deref()
+                .ok_or("HLS EXT-X-KEY tag is missing its URI")?;
+            let uri = resolve_url(base_url, uri)
+                .ok_or_else(|| format!("Failed to resolve key URL: {uri}"))?;
+            let iv = key.iv.as_deref().map(parse_iv).transpose()?;
+            Ok(Some(SegmentKey {
+                method: key.method.clone(),
+                uri,
+                iv,
+            }))
+        }
+        KeyMethod::Other(method) => Err(format!("Unsupported HLS encryption method: {method}")),
+    }
+}
+
+/// Parses an `EXT-X-KEY` IV attribute (`0x` followed by up to 32 hex digits).
+fn parse_iv(raw: &str) -> Result<[u8; 16], String> {
+    let hex = raw
+        .strip_prefix("0x")
+        .or_else(|| raw.strip_prefix("0X"))
+        .unwrap_or(raw);
+    if hex.is_empty() || hex.len() > 32 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid HLS key IV: {raw}"));
+    }
+    let padded = format!("{hex:0>32}");
+    let mut iv = [0u8; 16];
+    for (i, byte) in iv.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&padded[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("Invalid HLS key IV: {raw}"))?;
+    }
+    Ok(iv)
 }
 
-fn resolve_url(base_url: &str, uri: &str) -> Option<String> {
+/// The IV used when a key has no explicit one: the segment's media sequence
+/// number as a 128-bit big-endian integer.
+fn sequence_iv(sequence: u64) -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    iv[8..].copy_from_slice(&sequence.to_be_bytes());
+    iv
+}
+
+async fn fetch_key(client: &Client, keys: &KeyCache, uri: &str) -> Result<[u8; 16], DownloadError> {
+    // Held across the request so concurrent segments share one key fetch
+    let mut cache = keys.lock().await;
+    if let Some(key) = cache.get(uri) {
+        return Ok(*key);
+    }
+    let bytes = client
+        .get(uri)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let key: [u8; 16] = bytes.as_ref().try_into().map_err(|_| {
+        format!(
+            "Decryption key is {} bytes, expected 16 for AES-128",
+            bytes.len()
+        )
+    })?;
+    cache.insert(uri.to_string(), key);
+    Ok(key)
+}
+
+/// Decrypts a downloaded segment according to its `EXT-X-KEY`, if any.
+async fn decrypt_segment(
+    client: &Client,
+    keys: &KeyCache,
+    key: Option<&SegmentKey>,
+    sequence: u64,
+    data: Vec<u8>,
+) -> Result<Vec<u8>, DownloadError> {
+    let key = match key {
+        Some(k) => k,
+        None => return Ok(data),
+    };
+    let key_bytes = fetch_key(client, keys, &key.uri).await?;
+    let iv = key.iv.unwrap_or_else(|| sequence_iv(sequence));
+
+    match key.method {
+        KeyMethod::AES128 => Aes128CbcDec::new(&key_bytes.into(), &iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(&data)
+            .map_err(|_| "AES-128 decryption failed (wrong key or corrupt segment)".to_string()),
+        KeyMethod::SampleAES => sample_aes::decrypt_ts(&data, &key_bytes, &iv),
+        _ => Err(format!("Unsupported HLS encryption method: {}", key.method)),
+    }
+    .map_err(DownloadError::from)
+}
+
+/// Identifies a media playlist independently of the (often tokenised) host and
+/// query string, so a re-resolved URL for the same media still matches.
+fn playlist_fingerprint(media: &MediaPlaylist) -> String {
+    // FNV-1a — stable across builds, unlike `DefaultHasher`
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for seg in &media.segments {
+        let path = seg.uri.split('?').next().unwrap_or("");
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let duration_ms = (seg.duration * 1000.0) as u64;
+        // Sub-ranges of one shared file only differ by their byte range
+        let range_bytes = seg
+            .byte_range
+            .as_ref()
+            .map(|r| [r.offset.unwrap_or(u64::MAX), r.length])
+            .into_iter()
+            .flatten()
+            .flat_map(u64::to_le_bytes);
+        for byte in name
+            .bytes()
+            .chain(duration_ms.to_le_bytes())
+            .chain(range_bytes)
+        {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!(
+        "{}:{}:{:016x}",
+        media.segments.len(),
+        media.media_sequence,
+        hash
+    )
+}
+
+/// Segment progress of an interrupted download, rebuilt from its recorded
+/// segments: `(segments done, segment total, bytes)`, counting only segments
+/// the part files still hold. The total is 0 for state recorded before totals
+/// were kept. None if no segment state was recorded.
+pub fn saved_progress(
+    app: &AppHandle,
+    db: &DownloadDb,
+    profile_id: &str,
+    id: &str,
+) -> Option<(i64, i64, i64)> {
+    let streams = db.get_hls_streams(id).ok()?;
+    if streams.is_empty() {
+        return None;
+    }
+    let (mut done, mut total, mut bytes) = (0, 0, 0);
+    for (key, segment_total) in streams {
+        total = total.max(segment_total.unwrap_or(0));
+        let part_path = match key.strip_prefix(id) {
+            Some("") => file_store::part_file_path(app, profile_id, id),
+            Some(rest) => match rest.strip_prefix("#audio").and_then(|i| i.parse().ok()) {
+                Some(i) => file_store::audio_file_path(app, profile_id, id, i, "zentrio-part"),
+                None => continue,
+            },
+            None => continue,
+        };
+        let segments = match db.get_hls_state(&key) {
+            Ok(Some((_, segments))) if part_path.exists() => segments,
+            _ => continue,
+        };
+        if let Some((next, offset)) = resume_point(&segments, file_store::file_size(&part_path)) {
+            done += next as i64;
+            bytes += offset;
+        }
+    }
+    Some((done, total, bytes))
+}
+
+/// Returns `(next_segment_index, byte_offset)` for the first segment missing
+/// from the contiguous run of recorded segments, or `None` if nothing usable
+/// was recorded or the part file is shorter than the records claim.
+fn resume_point(segments: &[SegmentState], part_size: i64) -> Option<(usize, i64)> {
+    let mut next = 0usize;
+    let mut offset = 0i64;
+    for seg in segments {
+        if seg.index != next as i64 || seg.byte_offset != offset {
+            break;
+        }
+        next += 1;
+        offset += seg.byte_length;
+    }
+    if next == 0 || offset > part_size {
+        return None;
+    }
+    Some((next, offset))
+}
+
+pub fn resolve_url(base_url: &str, uri: &str) -> Option<String> {
     if let Ok(abs) = Url::parse(uri) {
         return Some(abs.to_string());
     }
@@ -254,29 +1160,67 @@ fn resolve_url(base_url: &str, uri: &str) -> Option<String> {
     base.join(uri).ok().map(|u| u.to_string())
 }
 
-async fn download_segment(client: &Client, url: &str) -> Result<Vec<u8>, String> {
-    let mut attempts = 0u8;
+/// Fetches a segment, or only its `(offset, length)` sub-range when given.
+/// Transient failures are retried in place with `SEGMENT_RETRY` backoff.
+pub async fn download_segment(
+    client: &HttpClient,
+    throttle: &Throttle,
+    url: &str,
+    byte_range: Option<(u64, u64)>,
+) -> Result<Vec<u8>, DownloadError> {
+    let mut retries = 0;
     loop {
-        match client.get(url).send().await {
-            Ok(resp) => {
-                if !resp.status().is_success() {
-                    return Err(format!(
-                        "Segment request failed with HTTP {}",
-                        resp.status()
-                    ));
-                }
-                return resp
-                    .bytes()
-                    .await
-                    .map(|b| b.to_vec())
-                    .map_err(|e| e.to_string());
-            }
-            Err(e) if attempts < 3 => {
-                attempts += 1;
-                tokio::time::sleep(std::time::Duration::from_millis(500 * attempts as u64)).await;
-                log::warn!("[HLS] Retrying segment ({attempts}/3): {url} — {e}");
+        match fetch_segment(client, throttle, url, byte_range).await {
+            Err(e) if retries < SEGMENT_RETRY.max_retries && retry::is_transient(&e) => {
+                retries += 1;
+                log::warn!(
+                    "[HLS] Retrying segment ({retries}/{}): {url} — {e}",
+                    SEGMENT_RETRY.max_retries
+                );
+                tokio::time::sleep(SEGMENT_RETRY.delay(retries)).await;
             }
-            Err(e) => return Err(e.to_string()),
+            result => return result,
         }
     }
 }
+
+async fn fetch_segment(
+    client: &HttpClient,
+    throttle: &Throttle,
+    url: &str,
+    byte_range: Option<(u64, u64)>,
+) -> Result<Vec<u8>, DownloadError> {
+    let _permit = client.host_permit(url).await;
+    let mut request = client.get(url);
+    if let Some((offset, length)) = byte_range {
+        request = request.header(
+            "Range",
+            format!("bytes={}-{}", offset, offset + length.max(1) - 1),
+        );
+    }
+    let resp = request.send().await?;
+    if !resp.status().is_success() {
+        return Err(DownloadError::Http(resp.status().as_u16()));
+    }
+    // A server that ignores Range would send the whole shared file for every
+    // sub-range of it. Refuse before reading the body, unless the range is
+    // the whole file anyway.
+    if let Some((offset, length)) = byte_range {
+        let whole_file = offset == 0 && resp.content_length() == Some(length);
+        if resp.status() != reqwest::StatusCode::PARTIAL_CONTENT && !whole_file {
+            return Err(format!(
+                "Server does not support byte-range segments (HTTP {})",
+                resp.status()
+            )
+            .into());
+        }
+    }
+    let mut bytes = Vec::new();
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        throttle.consume(chunk.len()).await;
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+use reqwest::{Client, Url};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::proxy::ProxySettings;
+
+/// Transfers to one host at a time, across all downloads. Segment workers
+/// beyond this wait for a slot instead of opening more connections.
+const MAX_CONNECTIONS_PER_HOST: usize = 8;
+/// Distinct request-header sets kept; the cache starts over beyond this.
+const MAX_CACHED_CLIENTS: usize = 16;
+/// Idle connections stay open this long for the next segment or download.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+const HTTP2_KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// A pooled client plus the per-host connection limits it shares with every
+/// other download. Derefs to `reqwest::Client` for one-off requests.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    hosts: Arc<HostLimits>,
+}
+
+impl Deref for HttpClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl HttpClient {
+    /// Waits for a transfer slot on `url`'s host. Hold the permit until the
+    /// response body has been read.
+    pub async fn host_permit(&self, url: &str) -> Option<OwnedSemaphorePermit> {
+        let host = Url::parse(url).ok()?.host_str()?.to_ascii_lowercase();
+        let semaphore = {
+            let mut hosts = self.hosts.semaphores.lock().ok()?;
+            Arc::clone(
+                hosts
+                    .entry(host)
+                    .or_insert_with(|| Arc::new(Semaphore::new(MAX_CONNECTIONS_PER_HOST))),
+            )
+        };
+        semaphore.acquire_owned().await.ok()
+    }
+}
+
+#[derive(Default)]
+struct HostLimits {
+    semaphores: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+/// A set of request headers, sorted by name.
+type HeaderKey = Vec<(String, Vec<u8>)>;
+
+/// HTTP clients shared by every download engine, so connections, TLS
+/// sessions and HTTP/2 streams are reused across segments, retries and
+/// downloads. One client per distinct set of request headers; most
+/// downloads have none and all share the same one.
+pub struct ClientPool {
+    proxy: Mutex<ProxySettings>,
+    clients: Mutex<HashMap<HeaderKey, HttpClient>>,
+    hosts: Arc<HostLimits>,
+}
+
+impl ClientPool {
+    pub fn new(proxy: ProxySettings) -> Self {
+        ClientPool {
+            proxy: Mutex::new(proxy),
+            clients: Mutex::new(HashMap::new()),
+            hosts: Arc::new(HostLimits::default()),
+        }
+    }
+
+    /// The shared client that sends `headers` with every request.
+    pub fn client(&self, headers: &HeaderMap) -> Result<HttpClient, String> {
+        let mut key: HeaderKey = headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+            .collect();
+        key.sort();
+
+        let mut clients = self
+            .clients
+            .lock()
+            .map_err(|_| "Client pool lock poisoned".to_string())?;
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+
+        let proxy = self
+            .proxy
+            .lock()
+            .map_err(|_| "Client pool lock poisoned".to_string())?
+            .clone();
+        let client = proxy
+            .apply(
+                Client::builder()
+                    .user_agent("Zentrio/1.0")
+                    .default_headers(headers.clone())
+                    .connect_timeout(Duration::from_secs(15))
+                    .read_timeout(Duration::from_secs(120))
+                    .pool_idle_timeout(IDLE_TIMEOUT)
+                    .pool_max_idle_per_host(MAX_CONNECTIONS_PER_HOST)
+                    .tcp_keepalive(TCP_KEEPALIVE)
+                    .http2_keep_alive_interval(HTTP2_KEEPALIVE)
+                    .http2_keep_alive_while_idle(true),
+            )?
+            .build()
+            .map_err(|e| e.to_string())?;
+        let client = HttpClient {
+            client,
+            hosts: Arc::clone(&self.hosts),
+        };
+
+        if clients.len() >= MAX_CACHED_CLIENTS {
+            clients.clear();
+        }
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    /// Switches proxies. Downloads already running keep their client;
+    /// everything fetched from now on goes through the new settings.
+    pub fn set_proxy(&self, proxy: ProxySettings) {
+        if let Ok(mut current) = self.proxy.lock() {
+            *current = proxy;
+        }
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.clear();
+        }
+    }
+}
@@ -1,22 +1,42 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use futures_util::StreamExt;
+use reqwest::header::HeaderMap;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use tauri::AppHandle;
 use tokio::io::AsyncWriteExt;
 use uuid::Uuid;
 
-use super::db::{DownloadDb, DownloadQuality, DownloadRecord, DownloadStatus};
+use super::dash;
+use super::db::{
+    DownloadDb, DownloadPriority, DownloadQuality, DownloadRecord, DownloadStatus, RemoteValidators,
+};
+use super::disk::{self, DiskReservations};
+use super::error::DownloadError;
 use super::events::{
-    emit_progress, emit_smart_next, emit_status, ProgressPayload, SmartNextPayload, StatusPayload,
+    emit_progress, emit_queue, emit_smart_next, emit_status, emit_url_expired, ProgressPayload,
+    QueueEntry, QueuePayload, SmartNextPayload, StatusPayload, UrlExpiredPayload,
 };
 use super::file_store;
+use super::headers::{self, RequestHeaders};
 use super::hls;
+use super::http::{ClientPool, HttpClient};
 use super::notifier;
+use super::proxy::ProxySettings;
+use super::quality::{codec_family, DeviceProfile, QualityTargets};
+use super::reconcile::{self, ReconcileReport};
+use super::retry::{self, DOWNLOAD_RETRY};
+use super::schedule::{self, DownloadSchedule};
+use super::segmented;
+use super::state::{self, Event, PauseReason};
 use super::subtitles::SubtitleEntry;
+use super::tasks::{StopReason, TaskHandle, TaskRegistry};
+use super::throttle::{RateLimiter, Throttle};
 
 /// Payload sent from the frontend to start a new download.
 #[derive(Debug, Deserialize, Serialize)]
@@ -40,8 +60,53 @@ pub struct StartDownloadPayload {
     pub auto_delete: Option<bool>,
     /// Subtitle tracks from the stream response — downloaded alongside the video
     pub subtitle_urls: Option<Vec<SubtitleEntry>>,
+    /// Number of HLS segments to fetch in parallel (None = engine default)
+    pub segment_concurrency: Option<u8>,
+    /// Preferred HLS audio languages (BCP-47), most preferred first.
+    /// None = the stream's default audio rendition
+    pub audio_languages: Option<Vec<String>>,
+    /// Exact variant from `download_probe` (None = pick by `quality`)
+    pub variant_id: Option<String>,
+    /// Override the tallest video `quality` allows
+    pub max_height: Option<u64>,
+    /// Codec families in order of preference ("hevc", "h264", …)
+    pub preferred_codecs: Option<Vec<String>>,
+    /// Avoid HDR variants when an SDR one exists
+    pub sdr_only: Option<bool>,
+    /// Bandwidth cap for this download in bytes per second (None = global cap only)
+    pub rate_limit: Option<u64>,
+    /// Do not start before this time, in ms since the epoch
+    pub not_before: Option<i64>,
+    /// "high" | "normal" | "low" (None = normal)
+    pub priority: Option<String>,
+    /// Extra HTTP headers for every request of this download, e.g. the
+    /// addon's `behaviorHints.proxyHeaders`
+    pub request_headers: Option<RequestHeaders>,
+    /// `size` from `download_probe`, checked against the free disk space
+    /// before the download starts
+    pub expected_size: Option<i64>,
 }
 
+/// `app_settings` key of the device capability profile.
+const DEVICE_PROFILE_KEY: &str = "device_profile";
+/// `app_settings` key of the device-wide bandwidth cap, in bytes per second.
+const GLOBAL_RATE_LIMIT_KEY: &str = "global_rate_limit";
+/// `app_settings` key of the daily download window.
+const SCHEDULE_KEY: &str = "download_schedule";
+/// `app_settings` key of the download proxy settings.
+const PROXY_KEY: &str = "download_proxy";
+/// `app_settings` key of the number of downloads that run at once.
+const MAX_CONCURRENT_KEY: &str = "max_concurrent";
+const DEFAULT_MAX_CONCURRENT: usize = 2;
+/// Upper bound on downloads running at once.
+const MAX_CONCURRENT_LIMIT: usize = 8;
+/// How often the scheduler re-checks the download window, start-after times,
+/// free disk space, storage quotas and lost connections.
+const SCHEDULER_TICK: Duration = Duration::from_secs(10);
+/// How long a server of a download paused for a lost connection gets to
+/// answer before it counts as still unreachable.
+const NETWORK_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Lightweight queue item held in memory.
 #[derive(Debug, Clone)]
 struct QueueItem {
@@ -54,38 +119,97 @@ struct QueueItem {
     auto_delete: bool,
     /// JSON string of subtitle URLs (serialized for cheap cloning)
     subtitle_urls_json: Option<String>,
+    segment_concurrency: Option<i64>,
+    audio_languages: Vec<String>,
+    variant_id: Option<String>,
+    quality_targets: QualityTargets,
+    not_before: Option<i64>,
+    priority: DownloadPriority,
+    request_headers: HeaderMap,
+}
+
+impl QueueItem {
+    fn from_record(rec: &DownloadRecord) -> Self {
+        QueueItem {
+            id: rec.id.clone(),
+            profile_id: rec.profile_id.clone(),
+            title: rec.title.clone(),
+            stream_url: rec.stream_url.clone(),
+            quality: rec.quality.as_str().to_string(),
+            smart_download: rec.smart_download,
+            auto_delete: rec.auto_delete,
+            subtitle_urls_json: rec.subtitle_urls.clone(),
+            segment_concurrency: rec.segment_concurrency,
+            audio_languages: parse_languages(rec.audio_languages.as_deref()),
+            variant_id: rec.variant_id.clone(),
+            quality_targets: parse_targets(rec.quality_targets.as_deref(), &rec.quality),
+            not_before: rec.not_before,
+            priority: rec.priority.clone(),
+            request_headers: headers::from_json(rec.request_headers.as_deref()),
+        }
+    }
 }
 
 /// Shared state managed across Tauri commands.
 pub struct DownloadManager {
     db: Arc<Mutex<DownloadDb>>,
     queue: Arc<Mutex<VecDeque<QueueItem>>>,
-    active: Arc<Mutex<Vec<String>>>,
-    paused: Arc<Mutex<Vec<String>>>,
-    max_concurrent: usize,
+    tasks: Arc<TaskRegistry>,
+    max_concurrent: Arc<AtomicUsize>,
+    limiter: Arc<RateLimiter>,
+    clients: Arc<ClientPool>,
+    disk: Arc<DiskReservations>,
 }
 
 impl DownloadManager {
     pub fn new(db: DownloadDb) -> Self {
+        let global_limit = load_global_rate_limit(&db);
+        let max_concurrent = load_max_concurrent(&db);
+        let proxy = load_proxy(&db);
         Self {
             db: Arc::new(Mutex::new(db)),
             queue: Arc::new(Mutex::new(VecDeque::new())),
-            active: Arc::new(Mutex::new(Vec::new())),
-            paused: Arc::new(Mutex::new(Vec::new())),
-            max_concurrent: 2,
+            tasks: Arc::new(TaskRegistry::default()),
+            max_concurrent: Arc::new(AtomicUsize::new(max_concurrent)),
+            limiter: Arc::new(RateLimiter::new(global_limit)),
+            clients: Arc::new(ClientPool::new(proxy)),
+            disk: Arc::new(DiskReservations::default()),
         }
     }
 
     /// Re-queues any downloads that were interrupted by a crash or clean shutdown.
     /// Call once after construction, before the app is fully running.
+    /// Also starts the scheduler that enforces the download window.
     pub fn restore(&self, app: AppHandle) {
+        self.spawn_scheduler(app.clone());
+
+        // Only report at startup; deleting files is left to `download_reconcile`,
+        // which the user confirms
+        match self.reconcile(app.clone(), false) {
+            Ok(report) => {
+                if !report.orphaned_files.is_empty()
+                    || !report.missing.is_empty()
+                    || !report.restored.is_empty()
+                {
+                    log::info!(
+                        "[Downloads] Storage check: {} orphaned file(s) ({} bytes), {} missing, {} back on disk",
+                        report.orphaned_files.len(),
+                        report.orphaned_bytes,
+                        report.missing.len(),
+                        report.restored.len()
+                    );
+                }
+            }
+            Err(e) => log::warn!("[Downloads] Failed to reconcile storage: {e}"),
+        }
+
         let db = match self.db.lock() {
             Ok(d) => d,
             Err(_) => return,
         };
 
-        // Reset any stuck 'downloading' rows back to 'queued' with zeroed progress
-        // so they restart cleanly rather than resuming from a potentially corrupt offset.
+        // Reset any stuck 'downloading' rows back to 'queued'; their engines
+        // pick up from the part files when they start again.
         if let Err(e) = db.reset_interrupted() {
             log::warn!("[Downloads] Failed to reset interrupted downloads: {e}");
         }
@@ -97,6 +221,13 @@ impl DownloadManager {
                 return;
             }
         };
+        // Show what a restart will keep, rather than the last progress report
+        for rec in &pending {
+            let (progress, downloaded) = restored_progress(&app, &db, rec);
+            if let Err(e) = db.restore_progress(&rec.id, progress, downloaded) {
+                log::warn!("[Downloads] Failed to restore progress of {}: {e}", rec.id);
+            }
+        }
         drop(db);
 
         if pending.is_empty() {
@@ -112,19 +243,11 @@ impl DownloadManager {
             Ok(q) => q,
             Err(_) => return,
         };
-        for rec in pending {
-            queue.push_back(QueueItem {
-                id: rec.id,
-                profile_id: rec.profile_id,
-                title: rec.title,
-                stream_url: rec.stream_url,
-                quality: rec.quality.as_str().to_string(),
-                smart_download: rec.smart_download,
-                auto_delete: rec.auto_delete,
-                subtitle_urls_json: rec.subtitle_urls,
-            });
+        for rec in &pending {
+            queue.push_back(QueueItem::from_record(rec));
         }
         drop(queue);
+        queue_changed(&app, &self.db, &self.queue);
 
         self.try_start_next(app);
     }
@@ -175,6 +298,42 @@ impl DownloadManager {
             .filter(|v| !v.is_empty())
             .and_then(|v| serde_json::to_string(v).ok());
 
+        let variant_id = payload
+            .variant_id
+            .as_deref()
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string);
+
+        let request_headers = payload
+            .request_headers
+            .clone()
+            .filter(|h| !h.is_empty())
+            .unwrap_or_default();
+        let header_map = headers::header_map(&request_headers)?;
+
+        let priority = payload
+            .priority
+            .as_deref()
+            .map(DownloadPriority::from_str)
+            .unwrap_or(DownloadPriority::Normal);
+
+        let quality = DownloadQuality::from_str(&payload.quality);
+        let quality_targets = QualityTargets::new(
+            &quality,
+            payload.max_height,
+            payload.preferred_codecs.clone(),
+            payload.sdr_only,
+        );
+
+        let audio_languages: Vec<String> = payload
+            .audio_languages
+            .iter()
+            .flatten()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect();
+
         let record = DownloadRecord {
             id: id.clone(),
             profile_id: payload.profile_id.clone(),
@@ -188,7 +347,7 @@ impl DownloadManager {
             poster_path: payload.poster_path.clone(),
             status: DownloadStatus::Queued,
             progress: 0.0,
-            quality: DownloadQuality::from_str(&payload.quality),
+            quality,
             file_path: file_path.clone(),
             file_size: 0,
             downloaded_bytes: 0,
@@ -203,10 +362,27 @@ impl DownloadManager {
             auto_delete,
             subtitle_urls: subtitle_urls_json.clone(),
             subtitle_paths: None,
+            segment_concurrency: payload.segment_concurrency.map(i64::from),
+            audio_languages: (!audio_languages.is_empty())
+                .then(|| serde_json::to_string(&audio_languages).ok())
+                .flatten(),
+            audio_tracks: None,
+            variant_id: variant_id.clone(),
+            quality_targets: serde_json::to_string(&quality_targets).ok(),
+            retry_count: 0,
+            rate_limit: payload.rate_limit.filter(|r| *r > 0).map(|r| r as i64),
+            not_before: payload.not_before,
+            priority: priority.clone(),
+            queue_position: 0,
+            request_headers: (!request_headers.is_empty())
+                .then(|| serde_json::to_string(&request_headers).ok())
+                .flatten(),
+            pause_reason: None,
+            expected_size: payload.expected_size.filter(|s| *s > 0),
         };
 
         db.insert(&record).map_err(|e| e.to_string())?;
-        drop(db); // Release before touching queue/active
+        drop(db); // Release before touching the queue
 
         let item = QueueItem {
             id: id.clone(),
@@ -217,38 +393,85 @@ impl DownloadManager {
             smart_download,
             auto_delete,
             subtitle_urls_json,
+            segment_concurrency: payload.segment_concurrency.map(i64::from),
+            audio_languages,
+            variant_id,
+            quality_targets,
+            not_before: payload.not_before,
+            priority,
+            request_headers: header_map,
         };
 
-        self.queue
+        let mut queue = self
+            .queue
             .lock()
-            .map_err(|_| "Queue lock poisoned".to_string())?
-            .push_back(item);
+            .map_err(|_| "Queue lock poisoned".to_string())?;
+        insert_by_priority(&mut queue, item, false);
+        drop(queue);
+        queue_changed(&app, &self.db, &self.queue);
 
         self.try_start_next(app);
         Ok(id)
     }
 
+    fn spawn_scheduler(&self, app: AppHandle) {
+        let db = Arc::clone(&self.db);
+        let queue = Arc::clone(&self.queue);
+        let tasks = Arc::clone(&self.tasks);
+        let limiter = Arc::clone(&self.limiter);
+        let max_concurrent = Arc::clone(&self.max_concurrent);
+        let clients = Arc::clone(&self.clients);
+        let disk = Arc::clone(&self.disk);
+        tauri::async_runtime::spawn(async move {
+            let mut tick = tokio::time::interval(SCHEDULER_TICK);
+            loop {
+                tick.tick().await;
+                disk_watchdog(&app, &db, &queue, &tasks, &disk);
+                quota_watchdog(&app, &db, &queue);
+                network_watchdog(&app, &db, &queue, &clients).await;
+                scheduler_tick(
+                    &app,
+                    &db,
+                    &queue,
+                    &tasks,
+                    &max_concurrent,
+                    &limiter,
+                    &clients,
+                    &disk,
+                );
+            }
+        });
+    }
+
     fn try_start_next(&self, app: AppHandle) {
         dispatch_pending(
             app,
             Arc::clone(&self.db),
             Arc::clone(&self.queue),
-            Arc::clone(&self.active),
-            Arc::clone(&self.paused),
-            self.max_concurrent,
+            Arc::clone(&self.tasks),
+            Arc::clone(&self.max_concurrent),
+            Arc::clone(&self.limiter),
+            Arc::clone(&self.clients),
+            Arc::clone(&self.disk),
         );
     }
 
+    /// Stops a download, keeping its part file, until `resume`.
     pub fn pause(&self, app: AppHandle, id: &str) -> Result<(), String> {
-        self.paused
-            .lock()
-            .map_err(|_| "Lock poisoned".to_string())?
-            .push(id.to_string());
-        self.db
-            .lock()
-            .map_err(|_| "DB lock poisoned".to_string())?
-            .update_status(id, &DownloadStatus::Paused)
+        // Validate before touching the task, so a rejected change leaves the
+        // download running. Record and stop under one DB lock: an engine
+        // finishing meanwhile either completes first or sees the stop in
+        // `commit_complete`.
+        let db = self.db.lock().map_err(|_| "DB lock poisoned".to_string())?;
+        db.transition(id, Event::Pause(PauseReason::User))
             .map_err(|e| e.to_string())?;
+        self.tasks.stop(id, StopReason::Pause);
+        drop(db);
+        self.queue
+            .lock()
+            .map_err(|_| "Queue lock poisoned".to_string())?
+            .retain(|q| q.id != id);
+        queue_changed(&app, &self.db, &self.queue);
         emit_status(
             &app,
             StatusPayload {
@@ -262,11 +485,6 @@ impl DownloadManager {
     }
 
     pub fn resume(&self, app: AppHandle, id: &str) -> Result<(), String> {
-        self.paused
-            .lock()
-            .map_err(|_| "Lock poisoned".to_string())?
-            .retain(|p| p != id);
-
         let rec = self
             .db
             .lock()
@@ -278,41 +496,35 @@ impl DownloadManager {
         self.db
             .lock()
             .map_err(|_| "DB lock poisoned".to_string())?
-            .update_status(id, &DownloadStatus::Queued)
+            .transition(id, Event::Resume)
             .map_err(|e| e.to_string())?;
+        if let Ok(d) = self.db.lock() {
+            d.reset_retry_count(id).ok();
+        }
 
-        let item = QueueItem {
-            id: rec.id.clone(),
-            profile_id: rec.profile_id.clone(),
-            title: rec.title.clone(),
-            stream_url: rec.stream_url.clone(),
-            quality: rec.quality.as_str().to_string(),
-            smart_download: rec.smart_download,
-            auto_delete: rec.auto_delete,
-            subtitle_urls_json: rec.subtitle_urls.clone(),
-        };
-        self.queue
+        let mut queue = self
+            .queue
             .lock()
-            .map_err(|_| "Queue lock poisoned".to_string())?
-            .push_front(item);
+            .map_err(|_| "Queue lock poisoned".to_string())?;
+        insert_by_priority(&mut queue, QueueItem::from_record(&rec), true);
+        drop(queue);
+        queue_changed(&app, &self.db, &self.queue);
         self.try_start_next(app);
         Ok(())
     }
 
     pub fn cancel(&self, app: AppHandle, id: &str) -> Result<(), String> {
-        self.paused
-            .lock()
-            .map_err(|_| "Lock poisoned".to_string())?
-            .push(id.to_string()); // treat as paused so worker exits
+        // Under one DB lock, as in `pause`
+        let db = self.db.lock().map_err(|_| "DB lock poisoned".to_string())?;
+        db.transition(id, Event::Cancel)
+            .map_err(|e| e.to_string())?;
+        self.tasks.stop(id, StopReason::Cancel);
+        drop(db);
         self.queue
             .lock()
             .map_err(|_| "Queue lock poisoned".to_string())?
             .retain(|q| q.id != id);
-        self.db
-            .lock()
-            .map_err(|_| "DB lock poisoned".to_string())?
-            .update_status(id, &DownloadStatus::Cancelled)
-            .map_err(|e| e.to_string())?;
+        queue_changed(&app, &self.db, &self.queue);
         emit_status(
             &app,
             StatusPayload {
@@ -325,6 +537,38 @@ impl DownloadManager {
         Ok(())
     }
 
+    /// Give a download whose stream URL expired a fresh one and queue it again.
+    /// Any other status is rejected.
+    pub fn update_url(&self, app: AppHandle, id: &str, stream_url: &str) -> Result<(), String> {
+        let stream_url = stream_url.trim();
+        if !stream_url.starts_with("http://") && !stream_url.starts_with("https://") {
+            return Err("Stream URL must be http(s)".into());
+        }
+
+        let db = self.db.lock().map_err(|_| "DB lock poisoned".to_string())?;
+        db.transition(id, Event::NewUrl)
+            .map_err(|e| e.to_string())?;
+        db.update_stream_url(id, stream_url)
+            .map_err(|e| e.to_string())?;
+        db.reset_retry_count(id).ok();
+        let rec = db
+            .get_by_id(id)
+            .map_err(|e| e.to_string())?
+            .ok_or("Download not found")?;
+        drop(db);
+
+        let mut queue = self
+            .queue
+            .lock()
+            .map_err(|_| "Queue lock poisoned".to_string())?;
+        queue.retain(|q| q.id != id);
+        insert_by_priority(&mut queue, QueueItem::from_record(&rec), true);
+        drop(queue);
+        queue_changed(&app, &self.db, &self.queue);
+        self.try_start_next(app);
+        Ok(())
+    }
+
     pub fn delete(&self, app: AppHandle, id: &str) -> Result<(), String> {
         let rec = self
             .db
@@ -333,6 +577,8 @@ impl DownloadManager {
             .get_by_id(id)
             .map_err(|e| e.to_string())?;
 
+        self.tasks.stop(id, StopReason::Cancel);
+        self.limiter.remove(id);
         if let Some(rec) = rec {
             file_store::delete_files(&app, &rec.profile_id, id);
             file_store::delete_subtitle_files(rec.subtitle_paths.as_deref());
@@ -341,14 +587,7 @@ impl DownloadManager {
             .lock()
             .map_err(|_| "Queue lock poisoned".to_string())?
             .retain(|q| q.id != id);
-        self.paused
-            .lock()
-            .map_err(|_| "Lock poisoned".to_string())?
-            .retain(|p| p != id);
-        self.active
-            .lock()
-            .map_err(|_| "Lock poisoned".to_string())?
-            .retain(|a| a != id);
+        queue_changed(&app, &self.db, &self.queue);
         self.db
             .lock()
             .map_err(|_| "DB lock poisoned".to_string())?
@@ -410,6 +649,262 @@ impl DownloadManager {
             .map_err(|e| e.to_string())
     }
 
+    pub fn get_device_profile(&self) -> Result<DeviceProfile, String> {
+        let db = self.db.lock().map_err(|_| "DB lock poisoned".to_string())?;
+        Ok(load_device_profile(&db))
+    }
+
+    /// Stores what this device can play. Codec names are normalised to
+    /// families ("avc1" → "h264"); unknown ones are rejected.
+    pub fn set_device_profile(&self, mut profile: DeviceProfile) -> Result<(), String> {
+        let mut codecs = Vec::with_capacity(profile.supported_codecs.len());
+        for codec in &profile.supported_codecs {
+            let family =
+                codec_family(codec).ok_or_else(|| format!("Unknown video codec: {codec}"))?;
+            if !codecs.contains(&family) {
+                codecs.push(family);
+            }
+        }
+        profile.supported_codecs = codecs;
+        let json = serde_json::to_string(&profile).map_err(|e| e.to_string())?;
+        self.db
+            .lock()
+            .map_err(|_| "DB lock poisoned".to_string())?
+            .set_setting(DEVICE_PROFILE_KEY, &json)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Device-wide bandwidth cap in bytes per second (0 = unlimited).
+    pub fn get_global_rate_limit(&self) -> u64 {
+        self.limiter.global_limit()
+    }
+
+    /// Changes the device-wide bandwidth cap, including for running downloads.
+    pub fn set_global_rate_limit(&self, bytes_per_sec: u64) -> Result<(), String> {
+        self.db
+            .lock()
+            .map_err(|_| "DB lock poisoned".to_string())?
+            .set_setting(GLOBAL_RATE_LIMIT_KEY, &bytes_per_sec.to_string())
+            .map_err(|e| e.to_string())?;
+        self.limiter.set_global_limit(bytes_per_sec);
+        Ok(())
+    }
+
+    /// Caps one download's bandwidth (0 = only the global cap applies),
+    /// including while it runs.
+    pub fn set_rate_limit(&self, id: &str, bytes_per_sec: u64) -> Result<(), String> {
+        let limit = (bytes_per_sec > 0).then_some(bytes_per_sec as i64);
+        self.db
+            .lock()
+            .map_err(|_| "DB lock poisoned".to_string())?
+            .update_rate_limit(id, limit)
+            .map_err(|e| e.to_string())?;
+        // Queued downloads pick the cap up from their record when they start
+        if self.tasks.is_running(id) {
+            self.limiter.set_download_limit(id, bytes_per_sec);
+        }
+        Ok(())
+    }
+
+    pub fn get_schedule(&self) -> Result<DownloadSchedule, String> {
+        let db = self.db.lock().map_err(|_| "DB lock poisoned".to_string())?;
+        Ok(load_schedule(&db))
+    }
+
+    /// Stores the daily download window and applies it straight away: running
+    /// downloads pause if it is closed, held ones continue if it is open.
+    pub fn set_schedule(&self, app: AppHandle, schedule: DownloadSchedule) -> Result<(), String> {
+        schedule.validate()?;
+        let json = serde_json::to_string(&schedule).map_err(|e| e.to_string())?;
+        self.db
+            .lock()
+            .map_err(|_| "DB lock poisoned".to_string())?
+            .set_setting(SCHEDULE_KEY, &json)
+            .map_err(|e| e.to_string())?;
+        scheduler_tick(
+            &app,
+            &self.db,
+            &self.queue,
+            &self.tasks,
+            &self.max_concurrent,
+            &self.limiter,
+            &self.clients,
+            &self.disk,
+        );
+        Ok(())
+    }
+
+    pub fn get_proxy(&self) -> Result<ProxySettings, String> {
+        let db = self.db.lock().map_err(|_| "DB lock poisoned".to_string())?;
+        Ok(load_proxy(&db))
+    }
+
+    /// Stores the download proxy settings; downloads started from now on use them.
+    pub fn set_proxy(&self, proxy: ProxySettings) -> Result<(), String> {
+        proxy.validate()?;
+        let json = serde_json::to_string(&proxy).map_err(|e| e.to_string())?;
+        self.db
+            .lock()
+            .map_err(|_| "DB lock poisoned".to_string())?
+            .set_setting(PROXY_KEY, &json)
+            .map_err(|e| e.to_string())?;
+        self.clients.set_proxy(proxy);
+        Ok(())
+    }
+
+    /// The shared client for one-off requests such as `download_probe`.
+    pub fn client(&self, headers: &HeaderMap) -> Result<HttpClient, String> {
+        self.clients.client(headers)
+    }
+
+    /// Holds a queued download until `not_before` (ms since the epoch; None = no hold).
+    pub fn set_start_after(
+        &self,
+        app: AppHandle,
+        id: &str,
+        not_before: Option<i64>,
+    ) -> Result<(), String> {
+        self.db
+            .lock()
+            .map_err(|_| "DB lock poisoned".to_string())?
+            .update_not_before(id, not_before)
+            .map_err(|e| e.to_string())?;
+        if let Some(item) = self
+            .queue
+            .lock()
+            .map_err(|_| "Queue lock poisoned".to_string())?
+            .iter_mut()
+            .find(|q| q.id == id)
+        {
+            item.not_before = not_before;
+        }
+        self.try_start_next(app);
+        Ok(())
+    }
+
+    pub fn get_profile_start_after(&self, profile_id: &str) -> Result<Option<i64>, String> {
+        self.db
+            .lock()
+            .map_err(|_| "DB lock poisoned".to_string())?
+            .get_start_after(profile_id)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Holds every queued download of a profile until `start_after`.
+    pub fn set_profile_start_after(
+        &self,
+        app: AppHandle,
+        profile_id: &str,
+        start_after: Option<i64>,
+    ) -> Result<(), String> {
+        self.db
+            .lock()
+            .map_err(|_| "DB lock poisoned".to_string())?
+            .set_start_after(profile_id, start_after)
+            .map_err(|e| e.to_string())?;
+        self.try_start_next(app);
+        Ok(())
+    }
+
+    /// Moves a queued download to `position` (0 = next to start), clamped to
+    /// the end of the queue. Priorities only apply when items are added, so a
+    /// manual move may place a low-priority download ahead of a high one.
+    pub fn move_in_queue(&self, app: AppHandle, id: &str, position: usize) -> Result<(), String> {
+        let mut queue = self
+            .queue
+            .lock()
+            .map_err(|_| "Queue lock poisoned".to_string())?;
+        let from = queue
+            .iter()
+            .position(|q| q.id == id)
+            .ok_or("Download is not queued")?;
+        if let Some(item) = queue.remove(from) {
+            let to = position.min(queue.len());
+            queue.insert(to, item);
+        }
+        drop(queue);
+        queue_changed(&app, &self.db, &self.queue);
+        self.try_start_next(app);
+        Ok(())
+    }
+
+    /// Changes a download's priority; a queued one is re-placed accordingly.
+    pub fn set_priority(&self, app: AppHandle, id: &str, priority: &str) -> Result<(), String> {
+        let priority = match priority {
+            "high" | "normal" | "low" => DownloadPriority::from_str(priority),
+            other => return Err(format!("Unknown priority: {other}")),
+        };
+        self.db
+            .lock()
+            .map_err(|_| "DB lock poisoned".to_string())?
+            .update_priority(id, &priority)
+            .map_err(|e| e.to_string())?;
+
+        let mut queue = self
+            .queue
+            .lock()
+            .map_err(|_| "Queue lock poisoned".to_string())?;
+        let from = match queue.iter().position(|q| q.id == id) {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        if let Some(mut item) = queue.remove(from) {
+            item.priority = priority;
+            insert_by_priority(&mut queue, item, false);
+        }
+        drop(queue);
+        queue_changed(&app, &self.db, &self.queue);
+        self.try_start_next(app);
+        Ok(())
+    }
+
+    pub fn get_max_concurrent(&self) -> usize {
+        self.max_concurrent.load(Ordering::Relaxed)
+    }
+
+    /// Changes how many downloads run at once. Raising it starts queued ones
+    /// straight away; lowering it lets running downloads finish.
+    pub fn set_max_concurrent(&self, app: AppHandle, max_concurrent: usize) -> Result<(), String> {
+        if !(1..=MAX_CONCURRENT_LIMIT).contains(&max_concurrent) {
+            return Err(format!(
+                "Concurrent downloads must be between 1 and {MAX_CONCURRENT_LIMIT}"
+            ));
+        }
+        self.db
+            .lock()
+            .map_err(|_| "DB lock poisoned".to_string())?
+            .set_setting(MAX_CONCURRENT_KEY, &max_concurrent.to_string())
+            .map_err(|e| e.to_string())?;
+        self.max_concurrent.store(max_concurrent, Ordering::Relaxed);
+        self.try_start_next(app);
+        Ok(())
+    }
+
+    /// Checks the downloads directory against the records and, with `clean`,
+    /// deletes orphaned files and updates missing downloads.
+    pub fn reconcile(&self, app: AppHandle, clean: bool) -> Result<ReconcileReport, String> {
+        let db = self.db.lock().map_err(|_| "DB lock poisoned".to_string())?;
+        let report = reconcile::reconcile(&app, &db, clean)?;
+        drop(db);
+
+        if report.cleaned {
+            let missing = report.missing.iter().map(|m| (&m.id, "missing"));
+            let restored = report.restored.iter().map(|id| (id, "completed"));
+            for (id, status) in missing.chain(restored) {
+                emit_status(
+                    &app,
+                    StatusPayload {
+                        id: id.clone(),
+                        status: status.into(),
+                        file_path: None,
+                        error: None,
+                    },
+                );
+            }
+        }
+        Ok(report)
+    }
+
     pub fn delete_all_for_profile(&self, app: AppHandle, profile_id: &str) -> Result<(), String> {
         let ids = self
             .db
@@ -417,53 +912,229 @@ impl DownloadManager {
             .map_err(|_| "DB lock poisoned".to_string())?
             .delete_all_for_profile(profile_id)
             .map_err(|e| e.to_string())?;
+        self.queue
+            .lock()
+            .map_err(|_| "Queue lock poisoned".to_string())?
+            .retain(|q| !ids.contains(&q.id));
+        queue_changed(&app, &self.db, &self.queue);
         for id in ids {
+            self.tasks.stop(&id, StopReason::Cancel);
+            self.limiter.remove(&id);
             file_store::delete_files(&app, profile_id, &id);
         }
         Ok(())
     }
 }
 
+/// Stored quality targets, or the defaults of `quality` for rows written
+/// before targets were recorded.
+fn parse_targets(json: Option<&str>, quality: &DownloadQuality) -> QualityTargets {
+    json.and_then(|j| serde_json::from_str(j).ok())
+        .unwrap_or_else(|| QualityTargets::new(quality, None, None, None))
+}
+
+/// The stored device profile, or the permissive default.
+pub fn load_device_profile(db: &DownloadDb) -> DeviceProfile {
+    db.get_setting(DEVICE_PROFILE_KEY)
+        .ok()
+        .flatten()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn load_schedule(db: &DownloadDb) -> DownloadSchedule {
+    db.get_setting(SCHEDULE_KEY)
+        .ok()
+        .flatten()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn load_global_rate_limit(db: &DownloadDb) -> u64 {
+    db.get_setting(GLOBAL_RATE_LIMIT_KEY)
+        .ok()
+        .flatten()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn load_proxy(db: &DownloadDb) -> ProxySettings {
+    db.get_setting(PROXY_KEY)
+        .ok()
+        .flatten()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn load_max_concurrent(db: &DownloadDb) -> usize {
+    db.get_setting(MAX_CONCURRENT_KEY)
+        .ok()
+        .flatten()
+        .and_then(|v| v.parse().ok())
+        .filter(|n| (1..=MAX_CONCURRENT_LIMIT).contains(n))
+        .unwrap_or(DEFAULT_MAX_CONCURRENT)
+}
+
+/// Inserts `item` after every queued item of higher priority. With `ahead` it
+/// also goes before items of equal priority (a resumed download), otherwise
+/// after them.
+fn insert_by_priority(queue: &mut VecDeque<QueueItem>, item: QueueItem, ahead: bool) {
+    let rank = item.priority.rank();
+    let at = queue
+        .iter()
+        .position(|q| {
+            if ahead {
+                q.priority.rank() >= rank
+            } else {
+                q.priority.rank() > rank
+            }
+        })
+        .unwrap_or(queue.len());
+    queue.insert(at, item);
+}
+
+/// Persists the queue order and tells the frontend about it.
+fn queue_changed(
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    queue: &Arc<Mutex<VecDeque<QueueItem>>>,
+) {
+    let items: Vec<QueueEntry> = match queue.lock() {
+        Ok(q) => q
+            .iter()
+            .enumerate()
+            .map(|(position, item)| QueueEntry {
+                id: item.id.clone(),
+                position,
+                priority: item.priority.as_str().to_string(),
+            })
+            .collect(),
+        Err(_) => return,
+    };
+    if let Ok(d) = db.lock() {
+        let ids: Vec<&str> = items.iter().map(|e| e.id.as_str()).collect();
+        if let Err(e) = d.save_queue_order(&ids) {
+            log::warn!("[Downloads] Failed to save queue order: {e}");
+        }
+    }
+    emit_queue(app, QueuePayload { items });
+}
+
+/// Parses the JSON array stored in `audio_languages`.
+fn parse_languages(json: Option<&str>) -> Vec<String> {
+    json.and_then(|j| serde_json::from_str(j).ok())
+        .unwrap_or_default()
+}
+
 // ─── Queue dispatcher ─────────────────────────────────────────────────────────
 
-/// Starts queued downloads up to `max_concurrent`.
+/// Starts queued downloads up to `max_concurrent`, while the download window
+/// is open and in queue order, skipping items held by a start-after time.
+/// Downloads the disk has no room for are paused instead.
 /// Safe to call from within async tasks — spawns new tasks and returns immediately.
+#[allow(clippy::too_many_arguments)]
 fn dispatch_pending(
     app: AppHandle,
     db: Arc<Mutex<DownloadDb>>,
     queue: Arc<Mutex<VecDeque<QueueItem>>>,
-    active: Arc<Mutex<Vec<String>>>,
-    paused: Arc<Mutex<Vec<String>>>,
-    max_concurrent: usize,
+    tasks: Arc<TaskRegistry>,
+    max_concurrent: Arc<AtomicUsize>,
+    limiter: Arc<RateLimiter>,
+    clients: Arc<ClientPool>,
+    disk: Arc<DiskReservations>,
 ) {
+    let schedule = match db.lock() {
+        Ok(d) => load_schedule(&d),
+        Err(_) => return,
+    };
+    if !schedule.is_open() {
+        return;
+    }
+
     loop {
-        let active_count = match active.lock() {
-            Ok(a) => a.len(),
-            Err(_) => return,
-        };
-        if active_count >= max_concurrent {
+        let running = tasks.running();
+        if running.len() >= max_concurrent.load(Ordering::Relaxed) {
             return;
         }
 
-        let item = match queue.lock() {
-            Ok(mut q) => q.pop_front(),
+        // Items still winding down from a stop are not restarted yet
+        let waiting: Vec<(String, String, Option<i64>)> = match queue.lock() {
+            Ok(q) => q
+                .iter()
+                .filter(|i| !running.contains(&i.id))
+                .map(|i| (i.id.clone(), i.profile_id.clone(), i.not_before))
+                .collect(),
             Err(_) => return,
         };
+        let now = schedule::now_ms();
+        let ready = waiting
+            .into_iter()
+            .find(|(_, profile_id, not_before)| start_after(&db, profile_id, *not_before) <= now);
+        let item = match ready {
+            Some((id, ..)) => match queue.lock() {
+                Ok(mut q) => q.iter().position(|i| i.id == id).and_then(|p| q.remove(p)),
+                Err(_) => return,
+            },
+            None => return,
+        };
         let item = match item {
             Some(i) => i,
             None => return,
         };
 
-        match active.lock() {
-            Ok(mut a) => a.push(item.id.clone()),
+        let (rate_limit, remaining) = match db.lock() {
+            Ok(d) => match d.get_by_id(&item.id).ok().flatten() {
+                Some(rec) => (rec.rate_limit.unwrap_or(0), remaining_bytes(&d, &rec)),
+                None => (0, None),
+            },
             Err(_) => return,
         };
 
-        let db2 = Arc::clone(&db);
-        let queue2 = Arc::clone(&queue);
-        let active2 = Arc::clone(&active);
-        let paused2 = Arc::clone(&paused);
-        let app2 = app.clone();
+        // The profile's quota filled up since this was queued
+        if db.lock().is_ok_and(|d| over_quota(&d, &item.profile_id)) {
+            log::warn!(
+                "[Downloads] Storage quota of profile {} is used up. Pausing {}.",
+                item.profile_id,
+                item.id
+            );
+            pause_for(&app, &db, &item.id, PauseReason::Quota);
+            queue_changed(&app, &db, &queue);
+            continue;
+        }
+
+        // Hold the space the download still needs, or wait for the watchdog
+        // to see it freed. Without a known size only the low-space floor counts.
+        refresh_reservations(&db, &tasks, &disk);
+        let dir = file_store::downloads_dir(&app, &item.profile_id);
+        if !disk.reserve(&item.id, &dir, remaining.unwrap_or(0)) {
+            log::warn!(
+                "[Downloads] Not enough disk space for {}. Pausing until space is freed.",
+                item.id
+            );
+            pause_for(&app, &db, &item.id, PauseReason::DiskFull);
+            queue_changed(&app, &db, &queue);
+            continue;
+        }
+
+        let task = tasks.start(&item.id);
+        if let Ok(d) = db.lock() {
+            d.update_queue_position(&item.id, -1).ok();
+        }
+        queue_changed(&app, &db, &queue);
+
+        // The stored per-download cap applies from the first byte
+        limiter.set_download_limit(&item.id, rate_limit.max(0) as u64);
+        let throttle = limiter.for_download(&item.id);
+
+        let db2 = Arc::clone(&db);
+        let queue2 = Arc::clone(&queue);
+        let tasks2 = Arc::clone(&tasks);
+        let limiter2 = Arc::clone(&limiter);
+        let max_concurrent2 = Arc::clone(&max_concurrent);
+        let clients2 = Arc::clone(&clients);
+        let disk2 = Arc::clone(&disk);
+        let client = clients.client(&item.request_headers);
+        let app2 = app.clone();
         let id = item.id.clone();
         let smart = item.smart_download;
         let auto_del = item.auto_delete;
@@ -471,57 +1142,547 @@ fn dispatch_pending(
         let profile_id = item.profile_id.clone();
 
         tauri::async_runtime::spawn(async move {
-            let result = run_download(
-                app2.clone(),
-                db2.clone(),
-                paused2.clone(),
-                &item.id,
-                &item.profile_id,
-                &item.title,
-                &item.stream_url,
-                &item.quality,
-            )
-            .await;
-
-            if let Ok(mut a) = active2.lock() {
-                a.retain(|a| a != &id);
+            let result = match &client {
+                Ok(client) => {
+                    tokio::select! {
+                        result = run_download(
+                            app2.clone(),
+                            db2.clone(),
+                            &task,
+                            &item.id,
+                            &item.profile_id,
+                            &item.title,
+                            &item.stream_url,
+                            &item.quality,
+                            &item.quality_targets,
+                            item.segment_concurrency,
+                            &item.audio_languages,
+                            item.variant_id.as_deref(),
+                            client,
+                            &throttle,
+                        ) => result,
+                        // Dropping the engine aborts any request still in flight
+                        _ = task.stopped() => Err(DownloadError::Cancelled),
+                    }
+                }
+                Err(e) => Err(DownloadError::Other(e.clone())),
+            };
+
+            tasks2.finish(&id, &task);
+            disk2.release(&id);
+            // A newer task under the same id keeps its cap
+            if !tasks2.is_running(&id) {
+                limiter2.remove(&id);
             }
 
-            if result.is_ok() {
-                // Download subtitles if provided and not already downloaded
-                if let Some(urls_json) = subtitle_urls_json.as_deref() {
-                    // Only download if subtitle_paths not yet set
-                    let already_done = db2
-                        .lock()
-                        .ok()
-                        .and_then(|d| d.get_by_id(&id).ok().flatten())
-                        .and_then(|r| r.subtitle_paths)
-                        .map(|p| !p.is_empty())
-                        .unwrap_or(false);
-
-                    if !already_done {
-                        if let Some(paths_json) =
-                            super::subtitles::download_subtitles(&app2, &profile_id, &id, urls_json)
-                                .await
+            match task.reason() {
+                // Pause and cancel have already recorded their status
+                Some(StopReason::Pause) | Some(StopReason::Cancel) => {}
+                None => match result {
+                    Ok(()) => {
+                        // Download subtitles if provided. Files already on disk are reused, and
+                        // the result is merged with any tracks the HLS engine has registered.
+                        if let (Some(urls_json), Ok(client)) =
+                            (subtitle_urls_json.as_deref(), &client)
                         {
-                            if let Ok(d) = db2.lock() {
-                                d.update_subtitle_paths(&id, &paths_json).ok();
+                            if let Some(paths_json) = super::subtitles::download_subtitles(
+                                &app2,
+                                &profile_id,
+                                &id,
+                                urls_json,
+                                client,
+                                &throttle,
+                            )
+                            .await
+                            {
+                                let added: Vec<super::subtitles::SubtitlePathEntry> =
+                                    serde_json::from_str(&paths_json).unwrap_or_default();
+                                if let Ok(d) = db2.lock() {
+                                    let existing = d
+                                        .get_by_id(&id)
+                                        .ok()
+                                        .flatten()
+                                        .and_then(|r| r.subtitle_paths);
+                                    if let Some(merged) = super::subtitles::merge_subtitle_paths(
+                                        existing.as_deref(),
+                                        &added,
+                                    ) {
+                                        d.update_subtitle_paths(&id, &merged).ok();
+                                    }
+                                }
                             }
                         }
-                    }
-                }
 
-                if smart {
-                    smart_download_hook(app2.clone(), db2.clone(), &id, auto_del).await;
-                }
+                        if smart {
+                            smart_download_hook(app2.clone(), db2.clone(), &id, auto_del).await;
+                        }
+                    }
+                    Err(error) => handle_failure(
+                        &app2,
+                        &db2,
+                        &queue2,
+                        &tasks2,
+                        &max_concurrent2,
+                        &limiter2,
+                        &clients2,
+                        &disk2,
+                        item,
+                        error,
+                    ),
+                },
             }
 
             // Continue draining the queue
-            dispatch_pending(app2, db2, queue2, active2, paused2, max_concurrent);
+            dispatch_pending(
+                app2,
+                db2,
+                queue2,
+                tasks2,
+                max_concurrent2,
+                limiter2,
+                clients2,
+                disk2,
+            );
+        });
+    }
+}
+
+/// Earliest time (ms since the epoch) a queued download may start: its own
+/// `not_before` or its profile's start-after time, whichever is later.
+fn start_after(db: &Arc<Mutex<DownloadDb>>, profile_id: &str, not_before: Option<i64>) -> i64 {
+    let profile = db
+        .lock()
+        .ok()
+        .and_then(|d| d.get_start_after(profile_id).ok().flatten());
+    not_before.max(profile).unwrap_or(0)
+}
+
+/// Applies the download window: when it is closed, running downloads are
+/// paused for the schedule; when it opens, they are queued again ahead of the
+/// rest and anything due is started.
+#[allow(clippy::too_many_arguments)]
+fn scheduler_tick(
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    queue: &Arc<Mutex<VecDeque<QueueItem>>>,
+    tasks: &Arc<TaskRegistry>,
+    max_concurrent: &Arc<AtomicUsize>,
+    limiter: &Arc<RateLimiter>,
+    clients: &Arc<ClientPool>,
+    disk: &Arc<DiskReservations>,
+) {
+    let schedule = match db.lock() {
+        Ok(d) => load_schedule(&d),
+        Err(_) => return,
+    };
+
+    if !schedule.is_open() {
+        for id in tasks.active() {
+            log::info!("[Downloads] Download window closed. Pausing {id}.");
+            tasks.stop(&id, StopReason::Pause);
+            pause_for(app, db, &id, PauseReason::Schedule);
+        }
+        return;
+    }
+
+    requeue_paused(app, db, queue, PauseReason::Schedule, |_| true);
+    dispatch_pending(
+        app.clone(),
+        Arc::clone(db),
+        Arc::clone(queue),
+        Arc::clone(tasks),
+        Arc::clone(max_concurrent),
+        Arc::clone(limiter),
+        Arc::clone(clients),
+        Arc::clone(disk),
+    );
+}
+
+/// Pauses running downloads while their disk is almost full, and queues the
+/// ones it paused again once enough space is free. The scheduler tick that
+/// follows starts them.
+fn disk_watchdog(
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    queue: &Arc<Mutex<VecDeque<QueueItem>>>,
+    tasks: &Arc<TaskRegistry>,
+    disk: &Arc<DiskReservations>,
+) {
+    refresh_reservations(db, tasks, disk);
+    for id in tasks.active() {
+        let profile_id = match db.lock().ok().and_then(|d| d.get_by_id(&id).ok().flatten()) {
+            Some(rec) => rec.profile_id,
+            None => continue,
+        };
+        let free = disk::available_space(&file_store::downloads_dir(app, &profile_id));
+        if free.is_some_and(|free| free < disk::LOW_SPACE) {
+            log::warn!("[Downloads] Disk almost full. Pausing {id}.");
+            tasks.stop(&id, StopReason::Pause);
+            pause_for(app, db, &id, PauseReason::DiskFull);
+        }
+    }
+
+    requeue_paused(app, db, queue, PauseReason::DiskFull, |rec| {
+        let remaining = db
+            .lock()
+            .ok()
+            .and_then(|d| remaining_bytes(&d, rec))
+            .unwrap_or(0);
+        disk.has_room(&file_store::downloads_dir(app, &rec.profile_id), remaining)
+    });
+}
+
+/// Whether a profile's completed downloads fill its storage quota, if it has one.
+fn over_quota(db: &DownloadDb, profile_id: &str) -> bool {
+    let quota = db.get_quota(profile_id).unwrap_or(0);
+    quota > 0
+        && db
+            .get_storage_stats(profile_id)
+            .is_ok_and(|(used, _)| used >= quota)
+}
+
+/// Queues downloads paused for the storage quota again once their profile is
+/// back under it: files were deleted or the quota was raised.
+fn quota_watchdog(
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    queue: &Arc<Mutex<VecDeque<QueueItem>>>,
+) {
+    requeue_paused(app, db, queue, PauseReason::Quota, |rec| {
+        db.lock().is_ok_and(|d| !over_quota(&d, &rec.profile_id))
+    });
+}
+
+/// Queues downloads paused for a lost connection again once their server
+/// answers a HEAD request, with any status. One probe per host and tick.
+async fn network_watchdog(
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    queue: &Arc<Mutex<VecDeque<QueueItem>>>,
+    clients: &Arc<ClientPool>,
+) {
+    let held = db
+        .lock()
+        .ok()
+        .and_then(|d| d.get_paused_by(PauseReason::Network).ok())
+        .unwrap_or_default();
+    let mut reachable: HashMap<String, bool> = HashMap::new();
+    for rec in &held {
+        let host = host_of(&rec.stream_url);
+        if reachable.contains_key(&host) {
+            continue;
+        }
+        let answered = match clients.client(&QueueItem::from_record(rec).request_headers) {
+            Ok(client) => client
+                .head(&rec.stream_url)
+                .timeout(NETWORK_PROBE_TIMEOUT)
+                .send()
+                .await
+                .is_ok(),
+            Err(_) => false,
+        };
+        reachable.insert(host, answered);
+    }
+    requeue_paused(app, db, queue, PauseReason::Network, |rec| {
+        reachable
+            .get(&host_of(&rec.stream_url))
+            .copied()
+            .unwrap_or(false)
+    });
+}
+
+fn host_of(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_ascii_lowercase))
+        .unwrap_or_default()
+}
+
+/// Shrinks the reservations of running downloads to what their recorded
+/// progress says they still have to write.
+fn refresh_reservations(
+    db: &Arc<Mutex<DownloadDb>>,
+    tasks: &Arc<TaskRegistry>,
+    disk: &Arc<DiskReservations>,
+) {
+    let d = match db.lock() {
+        Ok(d) => d,
+        Err(_) => return,
+    };
+    for id in tasks.running() {
+        if let Some(remaining) = d
+            .get_by_id(&id)
+            .ok()
+            .flatten()
+            .and_then(|rec| remaining_bytes(&d, &rec))
+        {
+            disk.shrink(&id, remaining);
+        }
+    }
+}
+
+/// Records a pause the user did not ask for and tells the frontend. A running
+/// download must be stopped first.
+fn pause_for(app: &AppHandle, db: &Arc<Mutex<DownloadDb>>, id: &str, reason: PauseReason) {
+    let paused = db
+        .lock()
+        .map_err(|_| "DB lock poisoned".to_string())
+        .and_then(|d| {
+            d.transition(id, Event::Pause(reason))
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = paused {
+        log::warn!("[Downloads] Failed to pause {id}: {e}");
+        return;
+    }
+    emit_status(
+        app,
+        StatusPayload {
+            id: id.to_string(),
+            status: "paused".into(),
+            file_path: None,
+            error: None,
+        },
+    );
+}
+
+/// Queues downloads paused for `reason` again, for those `ready` accepts.
+/// They go ahead of their priority class, in the order they were paused.
+fn requeue_paused(
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    queue: &Arc<Mutex<VecDeque<QueueItem>>>,
+    reason: PauseReason,
+    ready: impl Fn(&DownloadRecord) -> bool,
+) {
+    let held = db
+        .lock()
+        .ok()
+        .and_then(|d| d.get_paused_by(reason).ok())
+        .unwrap_or_default();
+    let mut requeued = false;
+    // Oldest first, each inserted ahead of its priority class: reverse so
+    // they keep their order
+    for rec in held.iter().rev() {
+        if !ready(rec) {
+            continue;
+        }
+        // A fresh start, like a resume from the UI
+        let resumed = db.lock().ok().and_then(|d| {
+            d.transition(&rec.id, Event::Resume).ok()?;
+            d.reset_retry_count(&rec.id).ok()
         });
+        if resumed.is_none() {
+            continue;
+        }
+        if let Ok(mut q) = queue.lock() {
+            if !q.iter().any(|i| i.id == rec.id) {
+                insert_by_priority(&mut q, QueueItem::from_record(rec), true);
+            }
+        }
+        emit_status(
+            app,
+            StatusPayload {
+                id: rec.id.clone(),
+                status: "queued".into(),
+                file_path: None,
+                error: None,
+            },
+        );
+        requeued = true;
+    }
+    if requeued {
+        queue_changed(app, db, queue);
     }
 }
 
+/// Bytes a download still has to write, when its size is known: the probed
+/// size, or the remote size recorded by an earlier attempt.
+fn remaining_bytes(db: &DownloadDb, rec: &DownloadRecord) -> Option<u64> {
+    let total = rec
+        .expected_size
+        .or_else(|| db.get_validators(&rec.id).ok()?.total_size)?;
+    Some((total - rec.downloaded_bytes).max(0) as u64)
+}
+
+/// Reports a failed download attempt. A full disk pauses it until the disk
+/// watchdog sees space again, an expired stream URL waits for the
+/// frontend to supply a new one, transient errors are re-queued after a
+/// `DOWNLOAD_RETRY` backoff delay, a connection still lost past the retry
+/// limit pauses it until the server answers, and anything else — or any
+/// other retry past the limit — marks the download failed.
+#[allow(clippy::too_many_arguments)]
+fn handle_failure(
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    queue: &Arc<Mutex<VecDeque<QueueItem>>>,
+    tasks: &Arc<TaskRegistry>,
+    max_concurrent: &Arc<AtomicUsize>,
+    limiter: &Arc<RateLimiter>,
+    clients: &Arc<ClientPool>,
+    disk: &Arc<DiskReservations>,
+    item: QueueItem,
+    error: DownloadError,
+) {
+    let rec = match db
+        .lock()
+        .ok()
+        .and_then(|d| d.get_by_id(&item.id).ok().flatten())
+    {
+        Some(rec) => rec,
+        None => return, // deleted meanwhile
+    };
+
+    if error == DownloadError::Cancelled {
+        return;
+    }
+    if error == DownloadError::DiskFull {
+        log::warn!(
+            "[Downloads] Disk full while downloading {} ({}). Pausing until space is freed.",
+            item.id,
+            error
+        );
+        pause_for(app, db, &item.id, PauseReason::DiskFull);
+        return;
+    }
+
+    let message = error.to_string();
+    if retry::is_url_expired(&error) {
+        log::warn!(
+            "[Downloads] Stream URL of {} expired ({}). Waiting for a fresh URL.",
+            item.id,
+            error
+        );
+        if let Ok(d) = db.lock() {
+            d.update_awaiting_url(&item.id, &message).ok();
+        }
+        emit_status(
+            app,
+            StatusPayload {
+                id: item.id.clone(),
+                status: "awaiting_url".into(),
+                file_path: None,
+                error: Some(message),
+            },
+        );
+        emit_url_expired(
+            app,
+            UrlExpiredPayload {
+                id: rec.id,
+                profile_id: rec.profile_id,
+                media_type: rec.media_type,
+                media_id: rec.media_id,
+                episode_id: rec.episode_id,
+                title: rec.title,
+                episode_title: rec.episode_title,
+                season: rec.season,
+                episode: rec.episode,
+                addon_id: rec.addon_id,
+                quality: rec.quality.as_str().to_string(),
+            },
+        );
+        return;
+    }
+
+    let retries = rec.retry_count;
+    if matches!(error, DownloadError::Network(_)) && retries >= DOWNLOAD_RETRY.max_retries as i64 {
+        log::warn!(
+            "[Downloads] {} lost its connection ({}). Pausing until the server answers.",
+            item.id,
+            error
+        );
+        pause_for(app, db, &item.id, PauseReason::Network);
+        return;
+    }
+    if !retry::is_transient(&error) || retries >= DOWNLOAD_RETRY.max_retries as i64 {
+        if let Ok(d) = db.lock() {
+            d.update_error(&item.id, &message).ok();
+        }
+        emit_status(
+            app,
+            StatusPayload {
+                id: item.id.clone(),
+                status: "failed".into(),
+                file_path: None,
+                error: Some(message),
+            },
+        );
+        notifier::notify_failed(app, &item.title);
+        return;
+    }
+
+    let retry = retries + 1;
+    let delay = DOWNLOAD_RETRY.delay(retry as u32);
+    log::warn!(
+        "[Downloads] {} failed ({}). Retry {}/{} in {}s",
+        item.id,
+        error,
+        retry,
+        DOWNLOAD_RETRY.max_retries,
+        delay.as_secs()
+    );
+    if let Ok(d) = db.lock() {
+        d.update_retrying(&item.id, retry, &message).ok();
+    }
+    emit_status(
+        app,
+        StatusPayload {
+            id: item.id.clone(),
+            status: "retrying".into(),
+            file_path: None,
+            error: Some(message),
+        },
+    );
+
+    let app = app.clone();
+    let db = Arc::clone(db);
+    let queue = Arc::clone(queue);
+    let tasks = Arc::clone(tasks);
+    let max_concurrent = Arc::clone(max_concurrent);
+    let limiter = Arc::clone(limiter);
+    let clients = Arc::clone(clients);
+    let disk = Arc::clone(disk);
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(delay).await;
+
+        // Paused, cancelled or deleted during the delay
+        let waiting = db
+            .lock()
+            .ok()
+            .and_then(|d| d.get_by_id(&item.id).ok().flatten())
+            .is_some_and(|r| r.status == DownloadStatus::Retrying);
+        if !waiting {
+            return;
+        }
+        if let Ok(d) = db.lock() {
+            d.transition(&item.id, Event::Requeue).ok();
+        }
+        emit_status(
+            &app,
+            StatusPayload {
+                id: item.id.clone(),
+                status: "queued".into(),
+                file_path: None,
+                error: None,
+            },
+        );
+        if let Ok(mut q) = queue.lock() {
+            insert_by_priority(&mut q, item, false);
+        }
+        queue_changed(&app, &db, &queue);
+        dispatch_pending(
+            app,
+            db,
+            queue,
+            tasks,
+            max_concurrent,
+            limiter,
+            clients,
+            disk,
+        );
+    });
+}
+
 // ─── Smart Downloads hook ─────────────────────────────────────────────────────
 
 /// Post-completion hook for Smart Downloads.
@@ -584,15 +1745,26 @@ async fn smart_download_hook(
     );
 }
 
-// ─── HLS detection ────────────────────────────────────────────────────────────
+// ─── Stream detection ─────────────────────────────────────────────────────────
+
+/// Container formats that need a dedicated download engine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamKind {
+    Hls,
+    Dash,
+    File,
+}
 
-/// Returns true if the URL or its Content-Type indicates an HLS stream.
-async fn is_hls_stream(client: &Client, url: &str) -> bool {
+/// Detects HLS and DASH streams from the URL or their Content-Type.
+pub async fn detect_stream_kind(client: &Client, url: &str) -> StreamKind {
     let lower = url.to_lowercase();
     if lower.contains(".m3u8") || lower.contains("playlist.m3u") {
-        return true;
+        return StreamKind::Hls;
     }
-    // HEAD request fallback for HLS streams without .m3u8 in the URL
+    if lower.split('?').next().unwrap_or("").ends_with(".mpd") {
+        return StreamKind::Dash;
+    }
+    // HEAD request fallback for manifests without an extension in the URL
     match client
         .head(url)
         .timeout(std::time::Duration::from_secs(5))
@@ -606,117 +1778,298 @@ async fn is_hls_stream(client: &Client, url: &str) -> bool {
                 .and_then(|v| v.to_str().ok())
                 .unwrap_or("")
                 .to_lowercase();
-            ct.contains("mpegurl")
+            if ct.contains("mpegurl") {
+                StreamKind::Hls
+            } else if ct.contains("dash+xml") {
+                StreamKind::Dash
+            } else {
+                StreamKind::File
+            }
         }
-        Err(_) => false,
+        Err(_) => StreamKind::File,
     }
 }
 
 // ─── Download worker ──────────────────────────────────────────────────────────
 
+/// Progress of an interrupted download as its engine will resume it, rebuilt
+/// from the part files and resume state on disk: `(percent, bytes)`.
+fn restored_progress(app: &AppHandle, db: &DownloadDb, rec: &DownloadRecord) -> (f64, i64) {
+    let percent = |done: i64, total: i64| {
+        if total > 0 {
+            (done as f64 / total as f64 * 100.0).min(100.0)
+        } else {
+            0.0
+        }
+    };
+
+    if let Some((done, total, bytes)) = hls::saved_progress(app, db, &rec.profile_id, &rec.id) {
+        // Segment state recorded before totals were kept
+        if total == 0 {
+            return (rec.progress, bytes);
+        }
+        return (percent(done, total), bytes);
+    }
+
+    let part_path = file_store::part_file_path(app, &rec.profile_id, &rec.id);
+    if let Some((bytes, total)) = segmented::saved_progress(db, &part_path, &rec.id) {
+        return (percent(bytes, total), bytes);
+    }
+
+    // A single-stream part file is kept only if If-Range can vouch for it,
+    // and only while it still fits inside the recorded remote file
+    let stored = db.get_validators(&rec.id).unwrap_or_default();
+    let size = if part_path.exists() {
+        file_store::file_size(&part_path)
+    } else {
+        0
+    };
+    if size == 0 || stored.if_range().is_none() || stored.total_size.is_some_and(|t| size > t) {
+        return (0.0, 0);
+    }
+    (percent(size, stored.total_size.unwrap_or(0)), size)
+}
+
+/// Whether a `206` resume response continues the stored file at `start_byte`:
+/// its Content-Range must start there, and the total length and ETag (servers
+/// that ignore If-Range still send it) must be unchanged.
+fn resume_matches(
+    response: &reqwest::Response,
+    start_byte: i64,
+    stored: &RemoteValidators,
+) -> bool {
+    let (first, _, total) = match response
+        .headers()
+        .get("content-range")
+        .and_then(|v| v.to_str().ok())
+        .and_then(segmented::parse_content_range)
+    {
+        Some(range) => range,
+        None => return false,
+    };
+    if first != start_byte {
+        return false;
+    }
+    if let (Some(a), Some(b)) = (total, stored.total_size) {
+        if a != b {
+            return false;
+        }
+    }
+    let served = segmented::validators_from_headers(response.headers(), total);
+    match (&served.etag, &stored.etag) {
+        (Some(a), Some(b)) => a == b,
+        _ => true,
+    }
+}
+
+/// Moves a finished download's files into place and records it completed,
+/// then tells the frontend. `pause` and `cancel` record their status and stop
+/// the task under the same DB lock, so either the download completes first
+/// and they are rejected, or it sees the stop here and moves nothing, leaving
+/// the part files for a resume. `record` writes engine-specific state in the
+/// same step. Returns whether the download completed.
+#[allow(clippy::too_many_arguments)]
+pub fn commit_complete(
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    task: &TaskHandle,
+    id: &str,
+    title: &str,
+    moves: &[(PathBuf, PathBuf)],
+    final_path: &Path,
+    record: impl FnOnce(&DownloadDb),
+) -> Result<bool, DownloadError> {
+    let d = db
+        .lock()
+        .map_err(|_| DownloadError::Other("DB lock poisoned".into()))?;
+    if task.is_stopped() {
+        return Ok(false);
+    }
+    // Deleted meanwhile, or moved on by something that did not stop the task
+    if let Err(e) = d.status(id).and_then(|s| state::next(&s, Event::Complete)) {
+        log::warn!("[Downloads] Not completing {id}: {e}");
+        return Ok(false);
+    }
+    for (from, to) in moves {
+        std::fs::rename(from, to)?;
+    }
+    let size = file_store::file_size(final_path);
+    d.update_complete(id, &final_path.to_string_lossy(), size)
+        .map_err(|e| DownloadError::Other(e.to_string()))?;
+    record(&d);
+    drop(d);
+
+    emit_status(
+        app,
+        StatusPayload {
+            id: id.to_string(),
+            status: "completed".into(),
+            file_path: Some(final_path.to_string_lossy().to_string()),
+            error: None,
+        },
+    );
+    notifier::notify_complete(app, title);
+    Ok(true)
+}
+
 /// The async task that actually downloads a file.
 /// Detects stream format and routes to the appropriate engine.
 #[allow(clippy::too_many_arguments)]
 async fn run_download(
     app: AppHandle,
     db: Arc<Mutex<DownloadDb>>,
-    paused: Arc<Mutex<Vec<String>>>,
+    task: &TaskHandle,
     id: &str,
     profile_id: &str,
     title: &str,
     stream_url: &str,
     quality: &str,
-) -> Result<(), String> {
-    let client = Client::builder()
-        .user_agent("Zentrio/1.0")
-        .connect_timeout(std::time::Duration::from_secs(15))
-        .read_timeout(std::time::Duration::from_secs(120))
-        .build()
-        .map_err(|e| e.to_string())?;
-
-    if is_hls_stream(&client, stream_url).await {
-        return hls::download_hls(app, db, paused, id, profile_id, title, stream_url, quality)
+    quality_targets: &QualityTargets,
+    segment_concurrency: Option<i64>,
+    audio_languages: &[String],
+    variant_id: Option<&str>,
+    client: &HttpClient,
+    throttle: &Throttle,
+) -> Result<(), DownloadError> {
+    let device = db
+        .lock()
+        .map(|d| load_device_profile(&d))
+        .unwrap_or_default();
+    let targets = quality_targets.clone().with_device(&device);
+
+    match detect_stream_kind(client, stream_url).await {
+        StreamKind::Hls => {
+            return hls::download_hls(
+                &app,
+                &db,
+                task,
+                id,
+                profile_id,
+                title,
+                stream_url,
+                quality,
+                &targets,
+                segment_concurrency,
+                audio_languages,
+                variant_id,
+                client,
+                throttle,
+            )
+            .await;
+        }
+        StreamKind::Dash => {
+            return dash::download_dash(
+                &app,
+                &db,
+                task,
+                id,
+                profile_id,
+                title,
+                stream_url,
+                quality,
+                &targets,
+                segment_concurrency,
+                audio_languages,
+                variant_id,
+                client,
+                throttle,
+            )
+            .await;
+        }
+        StreamKind::File => {}
+    }
+
+    // Split large files across several connections when the server supports Range
+    if let Some(remote) = segmented::probe_range_support(client, stream_url).await {
+        let total_size = remote.total_size.unwrap_or(0);
+        if total_size >= segmented::MIN_SEGMENTED_SIZE {
+            return segmented::download_segmented(
+                app, db, task, client, throttle, id, profile_id, title, stream_url, total_size,
+                &remote,
+            )
             .await;
+        }
     }
 
     let part_path = file_store::part_file_path(&app, profile_id, id);
     let final_path = file_store::download_file_path(&app, profile_id, id);
 
-    // Resume support: continue from where we left off
-    let start_byte = if part_path.exists() {
+    // A range plan left over from a segmented attempt means the part file is
+    // preallocated with holes — it cannot be resumed as a single stream.
+    let had_ranges = db
+        .lock()
+        .ok()
+        .and_then(|d| d.get_ranges(id).ok())
+        .map(|r| !r.is_empty())
+        .unwrap_or(false);
+    if had_ranges {
+        log::warn!(
+            "[Downloads] Server no longer supports Range for {}. Restarting as a single stream.",
+            id
+        );
+        let _ = tokio::fs::remove_file(&part_path).await;
+        if let Ok(d) = db.lock() {
+            d.clear_ranges(id).ok();
+        }
+    }
+
+    let stored = db
+        .lock()
+        .ok()
+        .and_then(|d| d.get_validators(id).ok())
+        .unwrap_or_default();
+
+    // Resume support: continue from where we left off, but only when the
+    // server can confirm through If-Range that the file is unchanged
+    let mut start_byte = if part_path.exists() {
         file_store::file_size(&part_path)
     } else {
         0
     };
+    if start_byte > 0 && stored.if_range().is_none() {
+        log::warn!(
+            "[Downloads] No ETag or Last-Modified recorded for {}. Restarting from byte 0.",
+            id
+        );
+        let _ = tokio::fs::remove_file(&part_path).await;
+        start_byte = 0;
+    }
 
+    let _permit = client.host_permit(stream_url).await;
     let mut req = client.get(stream_url);
     if start_byte > 0 {
         req = req.header("Range", format!("bytes={}-", start_byte));
+        if let Some(validator) = stored.if_range() {
+            req = req.header("If-Range", validator);
+        }
     }
 
-    let mut response = req.send().await.map_err(|e| {
-        let msg = e.to_string();
-        if let Ok(d) = db.lock() {
-            d.update_error(id, &msg).ok();
-        }
-        emit_status(
-            &app,
-            StatusPayload {
-                id: id.to_string(),
-                status: "failed".into(),
-                file_path: None,
-                error: Some(msg.clone()),
-            },
-        );
-        notifier::notify_failed(&app, title);
-        msg
-    })?;
+    let mut response = req.send().await?;
 
-    // If resume was requested but server ignored Range and returned 200,
-    // restart from byte 0 to avoid appending duplicate bytes.
+    // A 200 answer to an If-Range resume carries the whole file — it changed,
+    // or the server ignores Range — so start over with it. A 206 must continue
+    // exactly where the part file ends, or it is discarded and refetched.
     let mut effective_start_byte = start_byte;
     if start_byte > 0 && response.status() == reqwest::StatusCode::OK {
         log::warn!(
-            "[Downloads] Server ignored Range resume for {}. Restarting from byte 0.",
+            "[Downloads] Remote file for {} changed or Range was ignored. Restarting from byte 0.",
             id
         );
         effective_start_byte = 0;
-        let _ = tokio::fs::remove_file(&part_path).await;
-        response = client.get(stream_url).send().await.map_err(|e| {
-            let msg = e.to_string();
-            if let Ok(d) = db.lock() {
-                d.update_error(id, &msg).ok();
-            }
-            emit_status(
-                &app,
-                StatusPayload {
-                    id: id.to_string(),
-                    status: "failed".into(),
-                    file_path: None,
-                    error: Some(msg.clone()),
-                },
-            );
-            notifier::notify_failed(&app, title);
-            msg
-        })?;
+    } else if start_byte > 0
+        && response.status() == reqwest::StatusCode::PARTIAL_CONTENT
+        && !resume_matches(&response, start_byte, &stored)
+    {
+        log::warn!(
+            "[Downloads] Resume of {} did not match the stored file. Restarting from byte 0.",
+            id
+        );
+        effective_start_byte = 0;
+        response = client.get(stream_url).send().await?;
     }
 
     if !response.status().is_success() {
-        let msg = format!("Download request failed with HTTP {}", response.status());
-        if let Ok(d) = db.lock() {
-            d.update_error(id, &msg).ok();
-        }
-        emit_status(
-            &app,
-            StatusPayload {
-                id: id.to_string(),
-                status: "failed".into(),
-                file_path: None,
-                error: Some(msg.clone()),
-            },
-        );
-        notifier::notify_failed(&app, title);
-        return Err(msg);
+        return Err(DownloadError::Http(response.status().as_u16()));
     }
 
     let total_size = response
@@ -727,6 +2080,17 @@ async fn run_download(
         .map(|s| s + effective_start_byte)
         .unwrap_or(0);
 
+    // Remember which file these bytes belong to, for the next resume
+    if effective_start_byte == 0 {
+        let validators = segmented::validators_from_headers(
+            response.headers(),
+            (total_size > 0).then_some(total_size),
+        );
+        if let Ok(d) = db.lock() {
+            d.update_validators(id, &validators).ok();
+        }
+    }
+
     let mut file_options = tokio::fs::OpenOptions::new();
     file_options.create(true).write(true);
     if effective_start_byte > 0 {
@@ -734,10 +2098,7 @@ async fn run_download(
     } else {
         file_options.truncate(true);
     }
-    let mut file = file_options
-        .open(&part_path)
-        .await
-        .map_err(|e| e.to_string())?;
+    let mut file = file_options.open(&part_path).await?;
 
     let mut downloaded = effective_start_byte;
     let mut last_progress = -10.0_f64;
@@ -749,35 +2110,16 @@ async fn run_download(
     let mut stream = response.bytes_stream();
 
     while let Some(chunk) = stream.next().await {
-        // Pause / cancel check
-        if paused
-            .lock()
-            .map(|p| p.contains(&id.to_string()))
-            .unwrap_or(false)
-        {
+        // Stopped between chunks — keep what was written for resume
+        if task.is_stopped() {
             file.flush().await.ok();
             return Ok(());
         }
 
-        let chunk = chunk.map_err(|e| {
-            let msg = e.to_string();
-            if let Ok(d) = db.lock() {
-                d.update_error(id, &msg).ok();
-            }
-            emit_status(
-                &app,
-                StatusPayload {
-                    id: id.to_string(),
-                    status: "failed".into(),
-                    file_path: None,
-                    error: Some(msg.clone()),
-                },
-            );
-            notifier::notify_failed(&app, title);
-            msg
-        })?;
+        let chunk = chunk?;
 
-        file.write_all(&chunk).await.map_err(|e| e.to_string())?;
+        file.write_all(&chunk).await?;
+        throttle.consume(chunk.len()).await;
         downloaded += chunk.len() as i64;
         bytes_since_window += chunk.len() as i64;
 
@@ -819,30 +2161,18 @@ async fn run_download(
         }
     }
 
-    file.flush().await.map_err(|e| e.to_string())?;
+    file.flush().await?;
     drop(file);
 
-    tokio::fs::rename(&part_path, &final_path)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let size = file_store::file_size(&final_path);
-    if let Ok(d) = db.lock() {
-        d.update_complete(id, &final_path.to_string_lossy(), size)
-            .ok();
-    }
-
-    emit_status(
+    commit_complete(
         &app,
-        StatusPayload {
-            id: id.to_string(),
-            status: "completed".into(),
-            file_path: Some(final_path.to_string_lossy().to_string()),
-            error: None,
-        },
-    );
-
-    notifier::notify_complete(&app, title);
-
+        &db,
+        task,
+        id,
+        title,
+        &[(part_path, final_path.clone())],
+        &final_path,
+        |_| {},
+    )?;
     Ok(())
 }
@@ -1,7 +1,26 @@
+pub mod dash;
 pub mod db;
+pub mod disk;
+pub mod error;
 pub mod events;
 pub mod file_store;
+pub mod fmp4;
+pub mod headers;
 pub mod hls;
+pub mod http;
 pub mod manager;
 pub mod notifier;
+pub mod probe;
+pub mod proxy;
+pub mod quality;
+pub mod reconcile;
+pub mod remux;
+pub mod retry;
+pub mod sample_aes;
+pub mod schedule;
+pub mod segmented;
+pub mod state;
 pub mod subtitles;
+pub mod tasks;
+pub mod throttle;
+pub mod ts;
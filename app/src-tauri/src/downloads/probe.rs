@@ -0,0 +1,252 @@
+use m3u8_rs::{MediaPlaylist, Playlist};
+use reqwest::Client;
+use serde::Serialize;
+
+use super::dash;
+use super::hls;
+use super::http::HttpClient;
+use super::manager::{detect_stream_kind, StreamKind};
+use super::quality::QualityTargets;
+use super::segmented;
+
+/// One selectable variant: an HLS variant stream or a DASH representation.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeVariant {
+    /// Passed back as `variantId` in `download_start`
+    pub id: String,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    pub codecs: Option<String>,
+    /// Bits per second
+    pub bandwidth: u64,
+    pub frame_rate: Option<f64>,
+    /// Bandwidth × duration, when the duration is known
+    pub estimated_size: Option<i64>,
+    pub hdr: bool,
+    /// The device profile lists every codec of this variant
+    pub playable: bool,
+}
+
+/// What `download_probe` learned about a stream URL before enqueueing it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamProbe {
+    /// "hls" | "dash" | "file"
+    pub kind: String,
+    /// "ts", "fmp4", "mp4", "mkv", "webm" … when known
+    pub container: Option<String>,
+    pub content_type: Option<String>,
+    /// Size in bytes: exact for files, estimated for the selected variant
+    pub size: Option<i64>,
+    pub size_exact: bool,
+    /// Whether the server honours byte ranges. None for HLS / DASH, which
+    /// resume per segment.
+    pub accept_ranges: Option<bool>,
+    /// An interrupted download continues instead of starting over
+    pub resumable: bool,
+    pub duration_secs: Option<f64>,
+    /// Highest bandwidth first
+    pub variants: Vec<ProbeVariant>,
+    /// The variant `download_start` picks for the probed quality targets
+    pub selected_variant: Option<String>,
+}
+
+/// Inspects a stream URL: its container, size, range support and variants.
+pub async fn probe_stream(
+    url: &str,
+    client: &HttpClient,
+    targets: &QualityTargets,
+    quality_pref: &str,
+) -> Result<StreamProbe, String> {
+    match detect_stream_kind(client, url).await {
+        StreamKind::Hls => probe_hls(client, url, targets, quality_pref).await,
+        StreamKind::Dash => dash::probe_dash(client, url, targets, quality_pref).await,
+        StreamKind::File => Ok(probe_file(client, url).await),
+    }
+}
+
+async fn probe_hls(
+    client: &Client,
+    playlist_url: &str,
+    targets: &QualityTargets,
+    quality_pref: &str,
+) -> Result<StreamProbe, String> {
+    let bytes = client
+        .get(playlist_url)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to fetch playlist: {e}"))?
+        .error_for_status()
+        .map_err(|e| format!("Playlist request failed: {e}"))?
+        .bytes()
+        .await
+        .map_err(|e| format!("Failed to read playlist body: {e}"))?;
+
+    let master = match m3u8_rs::parse_playlist_res(&bytes) {
+        Ok(Playlist::MasterPlaylist(master)) => master,
+        Ok(Playlist::MediaPlaylist(media)) => {
+            // A bare media playlist has a single rendition and no bandwidth to estimate from
+            return Ok(StreamProbe {
+                kind: "hls".into(),
+                container: Some(hls_container(&media).into()),
+                content_type: Some("application/vnd.apple.mpegurl".into()),
+                size: None,
+                size_exact: false,
+                accept_ranges: None,
+                resumable: true,
+                duration_secs: Some(playlist_duration(&media)),
+                variants: Vec::new(),
+                selected_variant: None,
+            });
+        }
+        Err(e) => return Err(format!("Failed to parse HLS playlist: {e:?}")),
+    };
+
+    // Every variant covers the same timeline, so one media playlist gives the duration
+    let selected = hls::pick_variant(&master, None, targets, quality_pref)?;
+    let media_url = hls::resolve_url(playlist_url, &selected.uri)
+        .ok_or_else(|| format!("Failed to resolve variant URL: {}", selected.uri))?;
+    let media = hls::fetch_media_playlist(client, &media_url)
+        .await
+        .map_err(|e| e.to_string())?;
+    let duration = playlist_duration(&media);
+
+    let mut variants: Vec<ProbeVariant> = master
+        .variants
+        .iter()
+        .filter(|v| !v.is_i_frame)
+        .map(|v| {
+            let traits = hls::variant_traits(v);
+            ProbeVariant {
+                id: v.uri.clone(),
+                width: v.resolution.map(|r| r.width),
+                height: v.resolution.map(|r| r.height),
+                codecs: v.codecs.clone(),
+                bandwidth: v.bandwidth,
+                frame_rate: v.frame_rate,
+                // BANDWIDTH is a peak rate; the average is closer to the real size
+                estimated_size: Some(
+                    (v.average_bandwidth.unwrap_or(v.bandwidth) as f64 / 8.0 * duration) as i64,
+                ),
+                hdr: traits.hdr,
+                playable: targets.can_decode(&traits),
+            }
+        })
+        .collect();
+    variants.sort_by_key(|v| std::cmp::Reverse(v.bandwidth));
+
+    let size = variants
+        .iter()
+        .find(|v| v.id == selected.uri)
+        .and_then(|v| v.estimated_size);
+    Ok(StreamProbe {
+        kind: "hls".into(),
+        container: Some(hls_container(&media).into()),
+        content_type: Some("application/vnd.apple.mpegurl".into()),
+        size,
+        size_exact: false,
+        accept_ranges: None,
+        resumable: true,
+        duration_secs: Some(duration),
+        variants,
+        selected_variant: Some(selected.uri.clone()),
+    })
+}
+
+fn playlist_duration(media: &MediaPlaylist) -> f64 {
+    media.segments.iter().map(|s| s.duration as f64).sum()
+}
+
+/// "fmp4" for playlists with an `EXT-X-MAP`, else the segment format.
+fn hls_container(media: &MediaPlaylist) -> &'static str {
+    if media.segments.iter().any(|s| s.map.is_some()) {
+        return "fmp4";
+    }
+    let first = media
+        .segments
+        .first()
+        .map(|s| s.uri.split('?').next().unwrap_or("").to_lowercase())
+        .unwrap_or_default();
+    if first.ends_with(".aac") {
+        "aac"
+    } else if first.ends_with(".mp4") || first.ends_with(".m4s") {
+        "fmp4"
+    } else {
+        "ts"
+    }
+}
+
+async fn probe_file(client: &Client, url: &str) -> StreamProbe {
+    let head = client
+        .head(url)
+        .timeout(std::time::Duration::from_secs(15))
+        .send()
+        .await
+        .ok()
+        .filter(|r| r.status().is_success());
+    let header = |name: &str| {
+        head.as_ref()
+            .and_then(|r| r.headers().get(name))
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+
+    let content_type = header("content-type");
+    let mut size = header("content-length")
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|s| *s > 0);
+    let mut accept_ranges = header("accept-ranges").is_some_and(|v| v.contains("bytes"));
+    // Some servers reject HEAD or omit the headers but still honour Range
+    if !accept_ranges || size.is_none() {
+        if let Some(total) = segmented::probe_range_support(client, url)
+            .await
+            .and_then(|remote| remote.total_size)
+        {
+            size = Some(total);
+            accept_ranges = true;
+        }
+    }
+
+    StreamProbe {
+        kind: "file".into(),
+        container: file_container(content_type.as_deref(), url),
+        content_type,
+        size,
+        size_exact: size.is_some(),
+        accept_ranges: Some(accept_ranges),
+        resumable: accept_ranges,
+        duration_secs: None,
+        variants: Vec::new(),
+        selected_variant: None,
+    }
+}
+
+/// Container of a direct file from its Content-Type, or the URL's extension.
+fn file_container(content_type: Option<&str>, url: &str) -> Option<String> {
+    let by_type = match content_type
+        .unwrap_or("")
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase()
+        .as_str()
+    {
+        "video/mp4" | "audio/mp4" => Some("mp4"),
+        "video/x-matroska" | "video/matroska" => Some("mkv"),
+        "video/webm" | "audio/webm" => Some("webm"),
+        "video/mp2t" => Some("ts"),
+        "video/quicktime" => Some("mov"),
+        "video/x-msvideo" => Some("avi"),
+        _ => None,
+    };
+    if let Some(container) = by_type {
+        return Some(container.to_string());
+    }
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    let ext = path.rsplit('/').next()?.rsplit_once('.')?.1.to_lowercase();
+    ["mp4", "m4v", "mkv", "webm", "ts", "mov", "avi"]
+        .contains(&ext.as_str())
+        .then_some(ext)
+}
@@ -0,0 +1,196 @@
+use std::time::{Duration, Instant};
+
+use reqwest::{Client, ClientBuilder, NoProxy, Proxy};
+use serde::{Deserialize, Serialize};
+
+/// Fetched by `test` when no URL is given; answers 204 with an empty body.
+const TEST_URL: &str = "https://www.gstatic.com/generate_204";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyMode {
+    /// HTTP(S)_PROXY / ALL_PROXY and the OS proxy settings
+    #[default]
+    System,
+    /// Always connect directly
+    None,
+    /// The configured `url`
+    Manual,
+}
+
+/// How download requests reach the network.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ProxySettings {
+    pub mode: ProxyMode,
+    /// http://, https://, socks5:// or socks5h:// (remote DNS) proxy URL
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Hosts reached directly, comma separated: "localhost, .lan, 10.0.0.0/8"
+    pub no_proxy: String,
+}
+
+impl ProxySettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.mode == ProxyMode::Manual {
+            self.proxy()?;
+        }
+        Ok(())
+    }
+
+    /// Routes `builder` through the configured proxy.
+    pub fn apply(&self, builder: ClientBuilder) -> Result<ClientBuilder, String> {
+        match self.mode {
+            // reqwest reads the environment and OS settings unless told otherwise
+            ProxyMode::System => Ok(builder),
+            ProxyMode::None => Ok(builder.no_proxy()),
+            ProxyMode::Manual => Ok(builder.proxy(self.proxy()?)),
+        }
+    }
+
+    /// Fetches `url` through these settings — before saving them — and
+    /// returns the HTTP status and the round trip in milliseconds.
+    pub async fn test(&self, url: Option<&str>) -> Result<(u16, u64), String> {
+        self.validate()?;
+        let client = self
+            .apply(
+                Client::builder()
+                    .user_agent("Zentrio/1.0")
+                    .connect_timeout(Duration::from_secs(10))
+                    .timeout(Duration::from_secs(20)),
+            )?
+            .build()
+            .map_err(|e| e.to_string())?;
+        let started = Instant::now();
+        let resp = client
+            .get(url.unwrap_or(TEST_URL))
+            .send()
+            .await
+            .map_err(|e| format!("Proxy test failed: {e}"))?;
+        Ok((resp.status().as_u16(), started.elapsed().as_millis() as u64))
+    }
+
+    fn proxy(&self) -> Result<Proxy, String> {
+        let url = self.url.trim();
+        let scheme = url.split("://").next().unwrap_or_default();
+        if !url.contains("://")
+            || !matches!(
+                scheme.to_ascii_lowercase().as_str(),
+                "http" | "https" | "socks5" | "socks5h"
+            )
+        {
+            return Err(
+                "Proxy URL must start with http://, https://, socks5:// or socks5h://".into(),
+            );
+        }
+        let mut proxy = Proxy::all(url).map_err(|e| format!("Invalid proxy URL: {e}"))?;
+        if let Some(username) = self.username.as_deref().filter(|u| !u.is_empty()) {
+            proxy = proxy.basic_auth(username, self.password.as_deref().unwrap_or(""));
+        }
+        Ok(proxy.no_proxy(NoProxy::from_string(&self.no_proxy)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// A server on localhost that answers GET with `body` and refuses CONNECT.
+    /// Returns its address and the request lines it received.
+    async fn serve(body: &'static str) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut head = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => head.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let head = String::from_utf8_lossy(&head);
+                let line = head.lines().next().unwrap_or_default().to_string();
+                let response = if line.starts_with("CONNECT ") {
+                    "HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n".to_string()
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    )
+                };
+                log.lock().unwrap().push(line);
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        (addr, seen)
+    }
+
+    fn manual(proxy: SocketAddr, no_proxy: &str) -> Client {
+        ProxySettings {
+            mode: ProxyMode::Manual,
+            url: format!("http://{proxy}"),
+            no_proxy: no_proxy.into(),
+            ..Default::default()
+        }
+        .apply(Client::builder())
+        .unwrap()
+        .build()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn manual_mode_routes_through_the_proxy() {
+        let (proxy, seen) = serve("proxied").await;
+        let client = manual(proxy, "");
+
+        // The name never resolves: only the proxy can answer
+        let body = client
+            .get("http://zentrio.invalid/video.mp4")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "proxied");
+
+        // HTTPS tunnels through CONNECT; this proxy refuses it
+        assert!(client.get("https://zentrio.invalid/").send().await.is_err());
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                "GET http://zentrio.invalid/video.mp4 HTTP/1.1",
+                "CONNECT zentrio.invalid:443 HTTP/1.1",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn no_proxy_hosts_connect_directly() {
+        let (proxy, proxied) = serve("proxied").await;
+        let (origin, direct) = serve("direct").await;
+        let client = manual(proxy, "localhost, 127.0.0.1");
+
+        let body = client
+            .get(format!("http://{origin}/video.mp4"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "direct");
+        assert_eq!(*direct.lock().unwrap(), ["GET /video.mp4 HTTP/1.1"]);
+        assert!(proxied.lock().unwrap().is_empty());
+    }
+}
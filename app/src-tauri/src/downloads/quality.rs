@@ -0,0 +1,252 @@
+use serde::{Deserialize, Serialize};
+
+use super::db::DownloadQuality;
+
+/// Explicit variant selection targets for one download: the defaults of its
+/// quality preference, with per-download overrides applied.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct QualityTargets {
+    /// Tallest acceptable video in pixels (None = no limit)
+    pub max_height: Option<u64>,
+    /// Codec families in order of preference ("hevc", "h264", …)
+    pub preferred_codecs: Vec<String>,
+    /// Avoid HDR (PQ / HLG / Dolby Vision) variants when an SDR one exists
+    pub sdr_only: bool,
+    /// Codec families the device can decode (empty = any).
+    /// Filled from the device profile when the download starts.
+    pub supported_codecs: Vec<String>,
+}
+
+/// What this device can play. Stored in app settings and applied to every download.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DeviceProfile {
+    /// Codec families the device can decode (empty = any)
+    pub supported_codecs: Vec<String>,
+    /// Tallest video the device should download (None = no limit)
+    pub max_height: Option<u64>,
+    pub hdr_supported: bool,
+}
+
+impl Default for DeviceProfile {
+    fn default() -> Self {
+        DeviceProfile {
+            supported_codecs: Vec::new(),
+            max_height: None,
+            hdr_supported: true,
+        }
+    }
+}
+
+impl QualityTargets {
+    /// Targets for a quality preference, with optional overrides from the payload.
+    pub fn new(
+        quality: &DownloadQuality,
+        max_height: Option<u64>,
+        preferred_codecs: Option<Vec<String>>,
+        sdr_only: Option<bool>,
+    ) -> Self {
+        QualityTargets {
+            max_height: max_height.or_else(|| quality.max_height()),
+            preferred_codecs: preferred_codecs
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|c| codec_family(c))
+                .collect(),
+            sdr_only: sdr_only.unwrap_or(false),
+            supported_codecs: Vec::new(),
+        }
+    }
+
+    /// Narrows the targets to what the device can play.
+    pub fn with_device(mut self, device: &DeviceProfile) -> Self {
+        self.max_height = match (self.max_height, device.max_height) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.sdr_only |= !device.hdr_supported;
+        self.supported_codecs = device
+            .supported_codecs
+            .iter()
+            .filter_map(|c| codec_family(c))
+            .collect();
+        self
+    }
+
+    /// Whether the device can decode every video codec of a variant.
+    pub fn can_decode(&self, traits: &VariantTraits) -> bool {
+        self.supported_codecs.is_empty()
+            || traits
+                .codecs
+                .iter()
+                .all(|c| self.supported_codecs.contains(c))
+    }
+}
+
+/// What variant ranking needs to know about one variant.
+#[derive(Debug, Clone, Default)]
+pub struct VariantTraits {
+    pub height: Option<u64>,
+    /// Video codec families; audio codecs are left out
+    pub codecs: Vec<String>,
+    pub hdr: bool,
+    pub frame_rate: Option<f64>,
+    pub bandwidth: u64,
+}
+
+impl VariantTraits {
+    /// Traits from an HLS-style `CODECS` list and `VIDEO-RANGE`.
+    pub fn new(
+        height: Option<u64>,
+        codecs: Option<&str>,
+        video_range: Option<&str>,
+        frame_rate: Option<f64>,
+        bandwidth: u64,
+    ) -> Self {
+        let codecs: Vec<String> = codecs
+            .unwrap_or("")
+            .split(',')
+            .filter_map(codec_family)
+            .collect();
+        let hdr = matches!(video_range, Some("PQ") | Some("HLG"))
+            || codecs.iter().any(|c| c == "dolby-vision");
+        VariantTraits {
+            height,
+            codecs,
+            hdr,
+            frame_rate,
+            bandwidth,
+        }
+    }
+}
+
+/// Maps a codec string ("avc1.640028", "hvc1", "h265") to its family name.
+/// Returns `None` for audio and unrecognised codecs.
+pub fn codec_family(codec: &str) -> Option<String> {
+    let codec = codec.trim().to_ascii_lowercase();
+    let fourcc = codec.split('.').next().unwrap_or("");
+    let family = match fourcc {
+        "avc1" | "avc3" | "avc" | "h264" => "h264",
+        "hvc1" | "hev1" | "hevc" | "h265" => "hevc",
+        "dvh1" | "dvhe" | "dvav" | "dva1" | "dav1" | "dolby-vision" => "dolby-vision",
+        "av01" | "av1" => "av1",
+        "vp09" | "vp9" => "vp9",
+        "vp08" | "vp8" => "vp8",
+        _ => return None,
+    };
+    Some(family.to_string())
+}
+
+/// Picks a variant for the targets. Variants the device cannot decode are
+/// never picked, and HDR ones only when no SDR variant is left. The rest are
+/// ranked by height (the tallest within `max_height`, else the shortest above
+/// it), then codec preference and frame rate, with bandwidth as tiebreaker.
+/// Without any resolution information the bandwidth heuristic of
+/// `quality_pref` decides.
+pub fn pick_variant_for<'a, T>(
+    streams: &'a [T],
+    traits: impl Fn(&T) -> VariantTraits,
+    targets: &QualityTargets,
+    quality_pref: &str,
+) -> Result<&'a T, String> {
+    let described: Vec<(&T, VariantTraits)> = streams.iter().map(|s| (s, traits(s))).collect();
+
+    let mut candidates: Vec<&(&T, VariantTraits)> = described
+        .iter()
+        .filter(|(_, t)| targets.can_decode(t))
+        .collect();
+    if candidates.is_empty() {
+        return Err(format!(
+            "No variant uses a codec this device can play ({})",
+            targets.supported_codecs.join(", ")
+        ));
+    }
+    if targets.sdr_only && candidates.iter().any(|(_, t)| !t.hdr) {
+        candidates.retain(|(_, t)| !t.hdr);
+    }
+
+    if candidates.iter().all(|(_, t)| t.height.is_none()) {
+        return pick_by_bandwidth(&candidates, |(_, t)| t.bandwidth, quality_pref)
+            .map(|(s, _)| *s)
+            .ok_or_else(|| "No suitable variant found".into());
+    }
+
+    // Over-cap variants only count when nothing fits under the cap
+    let fits = |t: &VariantTraits| match (t.height, targets.max_height) {
+        (Some(h), Some(max)) => h <= max,
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+    let any_fit = candidates.iter().any(|(_, t)| fits(t));
+    if any_fit {
+        candidates.retain(|(_, t)| fits(t));
+    } else {
+        candidates.retain(|(_, t)| t.height.is_some());
+    }
+
+    let codec_rank = |t: &VariantTraits| {
+        t.codecs
+            .iter()
+            .filter_map(|c| targets.preferred_codecs.iter().position(|p| p == c))
+            .min()
+            .unwrap_or(targets.preferred_codecs.len())
+    };
+    candidates.sort_by(|(_, a), (_, b)| {
+        let height = |t: &VariantTraits| t.height.unwrap_or(0);
+        let by_height = if any_fit {
+            height(b).cmp(&height(a))
+        } else {
+            height(a).cmp(&height(b))
+        };
+        by_height
+            .then_with(|| codec_rank(a).cmp(&codec_rank(b)))
+            .then_with(|| {
+                b.frame_rate
+                    .unwrap_or(0.0)
+                    .total_cmp(&a.frame_rate.unwrap_or(0.0))
+            })
+            .then_with(|| b.bandwidth.cmp(&a.bandwidth))
+    });
+    candidates
+        .first()
+        .map(|(s, _)| *s)
+        .ok_or_else(|| "No suitable variant found".into())
+}
+
+/// Picks a stream for a quality preference ("standard" | "higher" | "best")
+/// from its bandwidth in bits per second.
+pub fn pick_by_bandwidth<'a, T>(
+    streams: &'a [T],
+    bandwidth: impl Fn(&T) -> u64,
+    quality_pref: &str,
+) -> Option<&'a T> {
+    // Sort by bandwidth descending
+    let mut variants: Vec<_> = streams.iter().collect();
+    variants.sort_by_key(|v| std::cmp::Reverse(bandwidth(v)));
+
+    // Map quality preference to a maximum bandwidth cap (rough heuristic)
+    let selected = match quality_pref {
+        "standard" => {
+            // Pick the lowest quality that is ≥ 720p or the first available
+            variants
+                .iter()
+                .rev()
+                .find(|v| bandwidth(v) >= 1_000_000)
+                .or_else(|| variants.last())
+        }
+        "higher" => {
+            // Pick the best quality ≤ 8 Mbps (≈1080p)
+            variants
+                .iter()
+                .find(|v| bandwidth(v) <= 8_000_000)
+                .or_else(|| variants.first())
+        }
+        _ => {
+            // "best" — pick the highest bandwidth
+            variants.first()
+        }
+    };
+
+    selected.copied()
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tauri::AppHandle;
+
+use super::db::{DownloadDb, DownloadRecord, DownloadStatus};
+use super::file_store;
+use super::state::Event;
+
+/// A file in the downloads directory that no download needs.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrphanedFile {
+    pub path: String,
+    pub size: u64,
+    /// The download that left it behind, if its record still exists
+    pub download_id: Option<String>,
+}
+
+/// A completed download whose file is gone or no longer its recorded size.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MissingFile {
+    pub id: String,
+    pub profile_id: String,
+    pub title: String,
+    pub file_path: String,
+    pub expected_size: i64,
+    /// None when the file does not exist
+    pub actual_size: Option<i64>,
+}
+
+/// What `reconcile` found, and whether it acted on it.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconcileReport {
+    pub orphaned_files: Vec<OrphanedFile>,
+    /// Total size of `orphaned_files`
+    pub orphaned_bytes: u64,
+    pub missing: Vec<MissingFile>,
+    /// Missing downloads whose file is back at its recorded size
+    pub restored: Vec<String>,
+    /// Orphans were deleted and the records updated
+    pub cleaned: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FileKind {
+    Part,
+    Media,
+    Subtitle,
+}
+
+/// Compares the downloads directory with the download records. With `clean`,
+/// deletes orphaned files, marks completed downloads whose file is gone as
+/// Missing and completes missing ones whose file is back.
+///
+/// Call with the DB lock held, so no download can start writing files the
+/// scan has no record for.
+pub fn reconcile(app: &AppHandle, db: &DownloadDb, clean: bool) -> Result<ReconcileReport, String> {
+    let records = db.get_all_profiles().map_err(|e| e.to_string())?;
+    let mut report = ReconcileReport::default();
+
+    for rec in &records {
+        check_file(rec, &mut report);
+    }
+
+    // Every file of a download starts with its id: `{id}.mp4`, `{id}_en.vtt`,
+    // `{id}_audio0.m4a`, `{id}.zentrio-part`
+    let mut profiles: HashMap<PathBuf, HashMap<String, &DownloadRecord>> = HashMap::new();
+    for rec in &records {
+        profiles
+            .entry(file_store::downloads_dir(app, &rec.profile_id))
+            .or_default()
+            .insert(file_store::safe_id(&rec.id), rec);
+    }
+    // Only the directories of profiles with downloads: anything else under
+    // the downloads root, a custom directory in particular, is not the app's
+    for (dir, owners) in &profiles {
+        scan_dir(dir, owners, &mut report);
+    }
+    report.orphaned_bytes = report.orphaned_files.iter().map(|f| f.size).sum();
+
+    if clean {
+        clean_up(db, &mut report);
+    }
+    Ok(report)
+}
+
+fn check_file(rec: &DownloadRecord, report: &mut ReconcileReport) {
+    if !matches!(
+        rec.status,
+        DownloadStatus::Completed | DownloadStatus::Missing
+    ) {
+        return;
+    }
+    // A custom directory on an unmounted drive is not a deleted file
+    let path = Path::new(&rec.file_path);
+    if !path.parent().is_some_and(|p| p.is_dir()) {
+        return;
+    }
+
+    let actual_size = std::fs::metadata(path)
+        .ok()
+        .filter(|m| m.is_file())
+        .map(|m| m.len() as i64);
+    // A size of 0 was never recorded
+    let intact = actual_size.is_some_and(|size| rec.file_size <= 0 || size == rec.file_size);
+
+    match rec.status {
+        DownloadStatus::Completed if !intact => report.missing.push(MissingFile {
+            id: rec.id.clone(),
+            profile_id: rec.profile_id.clone(),
+            title: rec.title.clone(),
+            file_path: rec.file_path.clone(),
+            expected_size: rec.file_size,
+            actual_size,
+        }),
+        DownloadStatus::Missing if intact => report.restored.push(rec.id.clone()),
+        _ => {}
+    }
+}
+
+fn scan_dir(dir: &Path, owners: &HashMap<String, &DownloadRecord>, report: &mut ReconcileReport) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let size = match entry.metadata() {
+            Ok(m) if m.is_file() => m.len(),
+            _ => continue,
+        };
+        let kind = match file_kind(&path) {
+            Some(k) => k,
+            None => continue,
+        };
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !is_download_name(&name) {
+            continue;
+        }
+        let rec = owner(&name, owners);
+        if rec.is_some_and(|r| needs(r, kind)) {
+            continue;
+        }
+        report.orphaned_files.push(OrphanedFile {
+            path: path.to_string_lossy().to_string(),
+            size,
+            download_id: rec.map(|r| r.id.clone()),
+        });
+    }
+}
+
+/// Only files the engines write are considered; anything else in the
+/// directory is left alone.
+fn file_kind(path: &Path) -> Option<FileKind> {
+    match path.extension()?.to_str()? {
+        "zentrio-part" => Some(FileKind::Part),
+        "vtt" => Some(FileKind::Subtitle),
+        "mp4" | "ts" | "m4a" | "aac" | "ac3" => Some(FileKind::Media),
+        _ => None,
+    }
+}
+
+/// Whether `name` is one the engines give: a download id, which is a UUID,
+/// followed by `.` or `_`. Other files are never orphans, whatever their
+/// extension.
+fn is_download_name(name: &str) -> bool {
+    name.get(..36)
+        .is_some_and(|id| uuid::Uuid::parse_str(id).is_ok())
+        && matches!(name.as_bytes().get(36), Some(b'.' | b'_'))
+}
+
+/// The download a file name belongs to: the longest `_`-separated prefix of
+/// its stem that is a download id.
+fn owner<'a>(name: &str, ids: &HashMap<String, &'a DownloadRecord>) -> Option<&'a DownloadRecord> {
+    let mut stem = name.split('.').next().unwrap_or(name);
+    loop {
+        if let Some(rec) = ids.get(stem) {
+            return Some(rec);
+        }
+        stem = &stem[..stem.rfind('_')?];
+    }
+}
+
+/// Part files matter while a download can still continue from them; finished
+/// files until it is cancelled.
+fn needs(rec: &DownloadRecord, kind: FileKind) -> bool {
+    match kind {
+        FileKind::Part => !matches!(
+            rec.status,
+            DownloadStatus::Completed | DownloadStatus::Cancelled | DownloadStatus::Missing
+        ),
+        FileKind::Media | FileKind::Subtitle => rec.status != DownloadStatus::Cancelled,
+    }
+}
+
+fn clean_up(db: &DownloadDb, report: &mut ReconcileReport) {
+    for file in &report.orphaned_files {
+        if let Err(e) = std::fs::remove_file(&file.path) {
+            log::warn!(
+                "[Downloads] Failed to delete orphaned file {}: {e}",
+                file.path
+            );
+        }
+    }
+    for missing in &report.missing {
+        if let Err(e) = db.transition(&missing.id, Event::FileMissing) {
+            log::warn!("[Downloads] Failed to mark {} missing: {e}", missing.id);
+        }
+    }
+    for id in &report.restored {
+        if let Err(e) = db.transition(id, Event::FileFound) {
+            log::warn!("[Downloads] Failed to restore {id}: {e}");
+        }
+    }
+    report.cleaned = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_download_names_can_be_orphans() {
+        let id = "0f8b7c1e-2d3a-4b5c-8d9e-0a1b2c3d4e5f";
+        for name in [
+            format!("{id}.mp4"),
+            format!("{id}.zentrio-part"),
+            format!("{id}_audio0.m4a"),
+            format!("{id}_hls0-en.vtt"),
+        ] {
+            assert!(is_download_name(&name), "{name}");
+        }
+        for name in [
+            "holiday.mp4",
+            "0f8b7c1e.mp4",
+            "0f8b7c1e-2d3a-4b5c-8d9e-0a1b2c3d4e5fX.mp4",
+            "zf8b7c1e-2d3a-4b5c-8d9e-0a1b2c3d4e5f.mp4",
+            "0f8b7c1e-2d3a-4b5c-8d9e-0a1b2c3d4e5f",
+        ] {
+            assert!(!is_download_name(name), "{name}");
+        }
+    }
+}
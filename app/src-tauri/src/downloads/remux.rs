@@ -0,0 +1,1727 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::ts;
+
+/// Clock of MPEG-TS timestamps and timescale of the video track.
+const TS_CLOCK: i64 = 90_000;
+/// Timescale of the movie header and edit lists.
+const MOVIE_TIMESCALE: i64 = 1000;
+/// Frame duration assumed for the last video sample (~30 fps).
+const DEFAULT_FRAME_TICKS: i64 = 3000;
+
+/// Elementary stream formats the remuxer can place in an MP4.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Codec {
+    H264,
+    H265,
+    Aac,
+    Ac3,
+    Eac3,
+}
+
+impl Codec {
+    /// `Ok(None)` for streams that are not carried over (ID3, SCTE-35, …);
+    /// an error for audio/video the MP4 would otherwise silently lose.
+    fn from_stream_type(stream_type: u8) -> Result<Option<Codec>, String> {
+        match stream_type {
+            0x1b => Ok(Some(Codec::H264)),
+            0x24 => Ok(Some(Codec::H265)),
+            0x0f => Ok(Some(Codec::Aac)),
+            0x81 => Ok(Some(Codec::Ac3)),
+            0x87 => Ok(Some(Codec::Eac3)),
+            0x01 | 0x02 | 0x03 | 0x04 | 0x10 | 0x11 => Err(format!(
+                "Unsupported stream type 0x{stream_type:02x} for MP4 remux"
+            )),
+            _ => Ok(None),
+        }
+    }
+
+    fn is_video(self) -> bool {
+        matches!(self, Codec::H264 | Codec::H265)
+    }
+}
+
+/// An alternate audio rendition downloaded next to the main stream.
+#[derive(Debug, Clone)]
+pub struct AudioInput {
+    pub path: PathBuf,
+    /// BCP-47 tag from `EXT-X-MEDIA LANGUAGE`
+    pub language: Option<String>,
+    pub name: String,
+}
+
+/// Sample entry details of an audio track, taken from its first frame.
+struct AudioInfo {
+    sample_rate: u32,
+    channels: u16,
+    frame_samples: u32,
+    entry_type: [u8; 4],
+    /// `esds` or `dac3` box placed inside the sample entry
+    config_box: Vec<u8>,
+}
+
+/// One access unit or audio frame, in stream order.
+struct Sample {
+    track: usize,
+    dts: i64,
+    pts: i64,
+    sync: bool,
+    data: Vec<u8>,
+}
+
+/// Demuxer state for one elementary stream.
+struct Track {
+    codec: Codec,
+    /// PES packet being reassembled
+    pes: Vec<u8>,
+    /// Audio bytes of a frame split across PES packets
+    carry: Vec<u8>,
+    last_ts: Option<i64>,
+    /// Distinct parameter sets (`(nal_type, nal)`) seen in a video stream
+    param_sets: Vec<(u8, Vec<u8>)>,
+    audio: Option<AudioInfo>,
+    /// Time of the last timestamped audio frame, and frames counted since
+    audio_start: Option<i64>,
+    audio_frames: i64,
+    /// PES timestamps waiting for the first frame that starts at or after
+    /// their offset into `carry`
+    pending_pts: Vec<(usize, i64)>,
+    language: Option<String>,
+    name: Option<String>,
+}
+
+impl Track {
+    fn new(codec: Codec) -> Self {
+        Track {
+            codec,
+            pes: Vec::new(),
+            carry: Vec::new(),
+            last_ts: None,
+            param_sets: Vec::new(),
+            audio: None,
+            audio_start: None,
+            audio_frames: 0,
+            pending_pts: Vec::new(),
+            language: None,
+            name: None,
+        }
+    }
+
+    /// Extends a 33-bit timestamp past wraparound, relative to the previous one.
+    fn unwrap_ts(&mut self, raw: i64) -> i64 {
+        const WRAP: i64 = 1 << 33;
+        let value = match self.last_ts {
+            Some(last) => {
+                let mut v = raw + (last - last.rem_euclid(WRAP));
+                if v - last > WRAP / 2 {
+                    v -= WRAP;
+                } else if last - v > WRAP / 2 {
+                    v += WRAP;
+                }
+                v
+            }
+            None => raw,
+        };
+        self.last_ts = Some(value);
+        value
+    }
+
+    fn flush_pes(
+        &mut self,
+        index: usize,
+        on_sample: &mut impl FnMut(Sample) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let pes = std::mem::take(&mut self.pes);
+        let header_len = match ts::pes_header_len(&pes) {
+            Some(len) => len,
+            None => return Ok(()),
+        };
+        let (pts, dts) = ts::pes_timestamps(&pes);
+        let pts = pts.map(|t| self.unwrap_ts(t));
+        let dts = dts.map(|t| self.unwrap_ts(t)).or(pts);
+        let payload = &pes[header_len..];
+
+        if self.codec.is_video() {
+            match (pts, dts) {
+                (Some(pts), Some(dts)) => self.video_sample(index, payload, pts, dts, on_sample),
+                // An access unit without timestamps cannot be placed
+                _ => Ok(()),
+            }
+        } else {
+            self.audio_samples(index, payload, pts, on_sample)
+        }
+    }
+
+    /// Converts an Annex B access unit into a length-prefixed MP4 sample,
+    /// moving parameter sets into the sample entry.
+    fn video_sample(
+        &mut self,
+        index: usize,
+        payload: &[u8],
+        pts: i64,
+        dts: i64,
+        on_sample: &mut impl FnMut(Sample) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let mut data = Vec::with_capacity(payload.len() + 16);
+        let mut sync = false;
+        for nal in nal_units(payload) {
+            let nal_type = match self.codec {
+                Codec::H264 => nal[0] & 0x1f,
+                _ => (nal[0] >> 1) & 0x3f,
+            };
+            match (self.codec, nal_type) {
+                (Codec::H264, 7 | 8) | (Codec::H265, 32..=34) => {
+                    if self.param_sets.len() < 32
+                        && !self.param_sets.iter().any(|(_, p)| p.as_slice() == nal)
+                    {
+                        self.param_sets.push((nal_type, nal.to_vec()));
+                    }
+                    continue;
+                }
+                // Access unit delimiters
+                (Codec::H264, 9) | (Codec::H265, 35) => continue,
+                (Codec::H264, 5) | (Codec::H265, 16..=21) => sync = true,
+                _ => {}
+            }
+            data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+            data.extend_from_slice(nal);
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        on_sample(Sample {
+            track: index,
+            dts,
+            pts,
+            sync,
+            data,
+        })
+    }
+
+    /// Splits an audio PES payload into ADTS / AC-3 frames. `pts` belongs to
+    /// the first frame that starts in `payload`; the frames after it are
+    /// timed by their fixed duration until the next timestamp, so the clock
+    /// follows the stream across discontinuities and lost packets.
+    fn audio_samples(
+        &mut self,
+        index: usize,
+        payload: &[u8],
+        pts: Option<i64>,
+        on_sample: &mut impl FnMut(Sample) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let mut buf = std::mem::take(&mut self.carry);
+        if let Some(pts) = pts {
+            self.pending_pts.push((buf.len(), pts));
+        }
+        buf.extend_from_slice(payload);
+
+        let min_header = if self.codec == Codec::Aac { 7 } else { 6 };
+        let mut pos = 0;
+        while buf.len() - pos >= min_header {
+            let frame = &buf[pos..];
+            let parsed = match self.codec {
+                Codec::Aac => ts::adts_frame(frame),
+                _ => ts::ac3_frame_len(frame).map(|len| (0, len)),
+            };
+            let (header_len, frame_len) = match parsed {
+                Some(f) => f,
+                None => {
+                    // Resynchronise on the next frame header
+                    pos += 1;
+                    continue;
+                }
+            };
+            if frame_len > frame.len() {
+                break;
+            }
+            // An MP4 sample would have to hold every substream of a frame
+            // period; such streams stay MPEG-TS
+            if self.codec == Codec::Eac3 && (frame[2] >> 6 == 1 || frame[2] & 0x38 != 0) {
+                return Err(
+                    "E-AC-3 with dependent or multiple substreams is not supported for MP4 remux"
+                        .into(),
+                );
+            }
+            let due = self
+                .pending_pts
+                .iter()
+                .take_while(|(at, _)| *at <= pos)
+                .count();
+            if let Some((_, pts)) = self.pending_pts.drain(..due).next_back() {
+                self.audio_start = Some(pts);
+                self.audio_frames = 0;
+            }
+            let start = match self.audio_start {
+                Some(start) => start,
+                // Frames before the first timestamp cannot be placed
+                None => {
+                    pos += frame_len;
+                    continue;
+                }
+            };
+            if self.audio.is_none() {
+                self.audio = Some(audio_info(self.codec, frame)?);
+            }
+            let (sample_rate, frame_samples) = match &self.audio {
+                Some(a) => (a.sample_rate as i64, a.frame_samples as i64),
+                None => break,
+            };
+
+            let time = start + self.audio_frames * frame_samples * TS_CLOCK / sample_rate;
+            self.audio_frames += 1;
+            on_sample(Sample {
+                track: index,
+                dts: time,
+                pts: time,
+                sync: true,
+                data: frame[header_len..frame_len].to_vec(),
+            })?;
+            pos += frame_len;
+        }
+        self.carry = buf[pos..].to_vec();
+        for (at, _) in &mut self.pending_pts {
+            *at = at.saturating_sub(pos);
+        }
+        Ok(())
+    }
+}
+
+/// Splits an Annex B byte stream into NAL units, without start codes.
+fn nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let starts = ts::start_code_ends(data);
+    let mut nals = Vec::with_capacity(starts.len());
+    for (i, &begin) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).map(|next| next - 3).unwrap_or(data.len());
+        let region = &data[begin..end];
+        // Zero bytes before the next start code belong to a 4-byte start code
+        let len = region
+            .iter()
+            .rposition(|b| *b != 0)
+            .map(|p| p + 1)
+            .unwrap_or(0);
+        if len > 0 {
+            nals.push(&region[..len]);
+        }
+    }
+    nals
+}
+
+/// Demuxes the main transport stream and then each audio rendition, calling
+/// `on_sample` in the order samples are laid out in `mdat`.
+fn demux_all(
+    input: &Path,
+    extra_audio: &[AudioInput],
+    on_sample: &mut impl FnMut(Sample) -> Result<(), String>,
+) -> Result<Vec<Track>, String> {
+    let mut tracks = demux(input, 0, false, on_sample)?;
+    for audio in extra_audio {
+        let first_track = tracks.len();
+        let mut added = if is_transport_stream_file(&audio.path) {
+            demux(&audio.path, first_track, true, on_sample)?
+        } else if is_fragmented_mp4_file(&audio.path) {
+            return Err(
+                "Fragmented MP4 audio renditions cannot be muxed into MPEG-TS video".into(),
+            );
+        } else {
+            vec![demux_packed_audio(&audio.path, first_track, on_sample)?]
+        };
+        for track in &mut added {
+            track.language = audio.language.clone();
+            track.name = Some(audio.name.clone());
+        }
+        tracks.extend(added);
+    }
+    Ok(tracks)
+}
+
+/// Reads the transport stream at `path` and calls `on_sample` for every
+/// access unit / audio frame. Track indices start at `first_track`; with
+/// `audio_only`, video streams are skipped. Returns the demuxed tracks.
+fn demux(
+    path: &Path,
+    first_track: usize,
+    audio_only: bool,
+    on_sample: &mut impl FnMut(Sample) -> Result<(), String>,
+) -> Result<Vec<Track>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut reader = BufReader::with_capacity(1 << 20, file);
+    let mut packet = [0u8; ts::PACKET_SIZE];
+
+    let mut pmt_pid: Option<u16> = None;
+    let mut tracks: Vec<Track> = Vec::new();
+    let mut by_pid: HashMap<u16, usize> = HashMap::new();
+
+    loop {
+        match reader.read_exact(&mut packet) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.to_string()),
+        }
+        let pkt = ts::parse_packet(&packet).ok_or("Lost MPEG-TS packet sync")?;
+
+        if pkt.pid == ts::PAT_PID {
+            if pmt_pid.is_none() {
+                pmt_pid = ts::parse_pat(&packet).first().copied();
+            }
+            continue;
+        }
+        if Some(pkt.pid) == pmt_pid {
+            // Segments repeat the PMT; the first one defines the tracks
+            if tracks.is_empty() {
+                let mut has_video = false;
+                for stream in ts::parse_pmt(&packet) {
+                    let codec = match Codec::from_stream_type(stream.stream_type)? {
+                        Some(c) => c,
+                        None => continue,
+                    };
+                    if codec.is_video() {
+                        if has_video || audio_only {
+                            continue;
+                        }
+                        has_video = true;
+                    }
+                    by_pid.insert(stream.pid, tracks.len());
+                    tracks.push(Track::new(codec));
+                }
+            }
+            continue;
+        }
+
+        let local = match by_pid.get(&pkt.pid) {
+            Some(i) => *i,
+            None => continue,
+        };
+        let track = &mut tracks[local];
+        if pkt.pusi {
+            if !track.pes.is_empty() {
+                track.flush_pes(first_track + local, on_sample)?;
+            }
+        } else if track.pes.is_empty() {
+            // Continuation of a PES packet whose start was not seen
+            continue;
+        }
+        track.pes.extend_from_slice(pkt.payload);
+    }
+
+    for (local, track) in tracks.iter_mut().enumerate() {
+        if !track.pes.is_empty() {
+            track.flush_pes(first_track + local, on_sample)?;
+        }
+    }
+    Ok(tracks)
+}
+
+/// Demuxes a packed audio rendition: raw ADTS or AC-3 frames, with an ID3 tag
+/// carrying the MPEG-TS timestamp at the start of each segment.
+fn demux_packed_audio(
+    path: &Path,
+    index: usize,
+    on_sample: &mut impl FnMut(Sample) -> Result<(), String>,
+) -> Result<Track, String> {
+    // Audio-only renditions are small (~1 MB per minute), so read them whole
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    let mut track: Option<Track> = None;
+    // Each segment starts with a tag timing its first frame
+    let mut tag_pts: Option<i64> = None;
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let rest = &data[pos..];
+        if let Some((tag_len, pts)) = id3_tag(rest) {
+            tag_pts = pts.or(tag_pts);
+            pos += tag_len;
+            continue;
+        }
+        let (codec, frame_len) = match (ts::adts_frame(rest), ts::ac3_frame_len(rest)) {
+            (Some((_, len)), _) => (Codec::Aac, len),
+            (None, Some(len)) if rest[5] >> 3 > 10 => (Codec::Eac3, len),
+            (None, Some(len)) => (Codec::Ac3, len),
+            _ => {
+                pos += 1;
+                continue;
+            }
+        };
+        let track = track.get_or_insert_with(|| {
+            let mut t = Track::new(codec);
+            t.audio_start = Some(0);
+            t
+        });
+        let pts = tag_pts.take().map(|p| track.unwrap_ts(p));
+        let end = (pos + frame_len).min(data.len());
+        track.audio_samples(index, &data[pos..end], pts, on_sample)?;
+        pos = end;
+    }
+    track.ok_or_else(|| "Audio rendition contains no AAC or AC-3 frames".to_string())
+}
+
+/// Returns the length of an ID3v2 tag at the start of `data`, and the
+/// `com.apple.streaming.transportStreamTimestamp` it carries, if any.
+fn id3_tag(data: &[u8]) -> Option<(usize, Option<i64>)> {
+    const OWNER: &[u8] = b"com.apple.streaming.transportStreamTimestamp\0";
+    if data.len() < 10 || &data[..3] != b"ID3" || data[6..10].iter().any(|b| *b >= 0x80) {
+        return None;
+    }
+    let size = data[6..10]
+        .iter()
+        .fold(0usize, |acc, b| (acc << 7) | *b as usize);
+    let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+    let body = &data[10..(10 + size).min(data.len())];
+    let pts = body
+        .windows(OWNER.len())
+        .position(|w| w == OWNER)
+        .and_then(|p| body.get(p + OWNER.len()..p + OWNER.len() + 8))
+        .and_then(|b| <[u8; 8]>::try_from(b).ok())
+        .map(|b| i64::from_be_bytes(b) & ((1 << 33) - 1));
+    Some((10 + size + footer, pts))
+}
+
+/// Returns true if the file at `path` starts like an MPEG transport stream.
+pub fn is_transport_stream_file(path: &Path) -> bool {
+    let mut head = vec![0u8; ts::PACKET_SIZE * 2];
+    let read = File::open(path)
+        .and_then(|mut f| {
+            let mut total = 0;
+            while total < head.len() {
+                match f.read(&mut head[total..])? {
+                    0 => break,
+                    n => total += n,
+                }
+            }
+            Ok(total)
+        })
+        .unwrap_or(0);
+    ts::is_transport_stream(&head[..read])
+}
+
+/// Returns the earliest PTS among the first audio / video PES packets of a
+/// transport stream — the 90 kHz time the remuxed MP4 starts at.
+pub fn start_timestamp(path: &Path) -> Option<i64> {
+    // Every stream has a timestamped PES packet within the first segment
+    const SCAN_PACKETS: usize = 16_384;
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let mut packet = [0u8; ts::PACKET_SIZE];
+    let mut earliest: HashMap<u16, i64> = HashMap::new();
+    for _ in 0..SCAN_PACKETS {
+        if reader.read_exact(&mut packet).is_err() {
+            break;
+        }
+        let pkt = match ts::parse_packet(&packet) {
+            Some(p) if p.pusi => p,
+            _ => continue,
+        };
+        // Audio (0xc0–0xdf), video (0xe0–0xef) and private stream 1 (AC-3)
+        let audio_video = matches!(pkt.payload.get(3), Some(0xbd | 0xc0..=0xef));
+        if !audio_video || earliest.contains_key(&pkt.pid) {
+            continue;
+        }
+        if let (Some(pts), _) = ts::pes_timestamps(pkt.payload) {
+            earliest.insert(pkt.pid, pts);
+        }
+    }
+    earliest.into_values().min()
+}
+
+fn is_fragmented_mp4_file(path: &Path) -> bool {
+    let mut head = [0u8; 8];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut head))
+        .map(|_| matches!(&head[4..8], b"ftyp" | b"styp" | b"moov" | b"moof"))
+        .unwrap_or(false)
+}
+
+/// File extension for an audio rendition kept as a separate file.
+pub fn audio_file_extension(path: &Path) -> &'static str {
+    if is_transport_stream_file(path) {
+        return "ts";
+    }
+    if is_fragmented_mp4_file(path) {
+        return "m4a";
+    }
+    let mut head = vec![0u8; 4096];
+    let len = File::open(path)
+        .and_then(|mut f| f.read(&mut head))
+        .unwrap_or(0);
+    let mut pos = 0;
+    if let Some((tag_len, _)) = id3_tag(&head[..len]) {
+        pos = tag_len;
+    }
+    match head.get(pos..len) {
+        Some(rest) if ts::ac3_frame_len(rest).is_some() => "ac3",
+        _ => "aac",
+    }
+}
+
+// ─── MP4 writer ──────────────────────────────────────────────────────────────
+
+/// Sample table of one track, collected during the first pass.
+#[derive(Default)]
+struct Table {
+    dts: Vec<i64>,
+    cts_offsets: Vec<i64>,
+    sizes: Vec<u32>,
+    /// 1-based numbers of sync samples
+    sync: Vec<u32>,
+    min_pts: Option<i64>,
+    /// `(offset into the mdat payload, sample count)` per chunk
+    chunks: Vec<(u64, u32)>,
+}
+
+/// A track ready to be described in the `moov` box.
+struct OutTrack<'a> {
+    id: u32,
+    codec: Codec,
+    table: &'a Table,
+    timescale: i64,
+    durations: Vec<i64>,
+    /// Empty edit before the track starts, in movie timescale
+    delay: i64,
+    /// First presented media time, in track timescale
+    media_start: i64,
+    sample_entry: Vec<u8>,
+    width: u16,
+    height: u16,
+    /// Packed ISO 639-2/T code for `mdhd`
+    language: u16,
+    name: String,
+    enabled: bool,
+}
+
+impl OutTrack<'_> {
+    fn media_duration(&self) -> i64 {
+        self.durations.iter().sum()
+    }
+
+    fn movie_duration(&self) -> i64 {
+        self.delay + (self.media_duration() - self.media_start) * MOVIE_TIMESCALE / self.timescale
+    }
+}
+
+/// Remuxes the MPEG-TS file at `input`, plus any alternate audio renditions,
+/// into a faststart MP4 at `output`. Runs two passes over the inputs: one to
+/// build the sample tables, then one to write the samples after the `moov` box.
+pub fn remux_ts_to_mp4(
+    input: &Path,
+    extra_audio: &[AudioInput],
+    output: &Path,
+) -> Result<(), String> {
+    // ── Pass 1: sample tables ────────────────────────────────────────────────
+    let mut tables: Vec<Table> = Vec::new();
+    let mut mdat_size: u64 = 0;
+    let mut last_track: Option<usize> = None;
+    let tracks = demux_all(input, extra_audio, &mut |sample| {
+        if tables.len() <= sample.track {
+            tables.resize_with(sample.track + 1, Table::default);
+        }
+        let table = &mut tables[sample.track];
+        // Consecutive samples of one track share a chunk
+        if last_track != Some(sample.track) {
+            table.chunks.push((mdat_size, 0));
+            last_track = Some(sample.track);
+        }
+        if let Some(chunk) = table.chunks.last_mut() {
+            chunk.1 += 1;
+        }
+        table.dts.push(sample.dts);
+        table.cts_offsets.push((sample.pts - sample.dts).max(0));
+        table.sizes.push(sample.data.len() as u32);
+        if sample.sync {
+            table.sync.push(table.sizes.len() as u32);
+        }
+        table.min_pts = Some(table.min_pts.map_or(sample.pts, |m| m.min(sample.pts)));
+        mdat_size += sample.data.len() as u64;
+        Ok(())
+    })?;
+
+    let global_start = tables
+        .iter()
+        .filter_map(|t| t.min_pts)
+        .min()
+        .ok_or("No audio or video samples found in transport stream")?;
+
+    let mut out_tracks: Vec<OutTrack> = Vec::new();
+    let mut has_audio = false;
+    for (track, table) in tracks.iter().zip(tables.iter()) {
+        if table.sizes.is_empty() {
+            continue;
+        }
+        let min_pts = table.min_pts.unwrap_or(global_start);
+        let delay = (min_pts - global_start) * MOVIE_TIMESCALE / TS_CLOCK;
+        let id = out_tracks.len() as u32 + 1;
+        let language = pack_language(track.language.as_deref());
+
+        if track.codec.is_video() {
+            let (config, width, height) = video_config(track)?;
+            let mut durations: Vec<i64> =
+                table.dts.windows(2).map(|w| (w[1] - w[0]).max(0)).collect();
+            durations.push(durations.last().copied().unwrap_or(DEFAULT_FRAME_TICKS));
+            let entry_type = if track.codec == Codec::H264 {
+                b"avc1"
+            } else {
+                b"hvc1"
+            };
+            out_tracks.push(OutTrack {
+                id,
+                codec: track.codec,
+                table,
+                timescale: TS_CLOCK,
+                durations,
+                delay,
+                media_start: min_pts - table.dts[0],
+                sample_entry: video_sample_entry(entry_type, width, height, &config),
+                width,
+                height,
+                language,
+                name: track.name.clone().unwrap_or_else(|| "VideoHandler".into()),
+                enabled: true,
+            });
+        } else {
+            let audio = track
+                .audio
+                .as_ref()
+                .ok_or("Audio track has no decodable frames")?;
+            out_tracks.push(OutTrack {
+                id,
+                codec: track.codec,
+                table,
+                timescale: audio.sample_rate as i64,
+                durations: audio_durations(&table.dts, audio),
+                delay,
+                media_start: 0,
+                sample_entry: audio_sample_entry(audio),
+                width: 0,
+                height: 0,
+                language,
+                name: track.name.clone().unwrap_or_else(|| "SoundHandler".into()),
+                // Audio tracks form one alternate group; only the first plays by default
+                enabled: !has_audio,
+            });
+            has_audio = true;
+        }
+    }
+
+    // ── Layout: ftyp, moov, mdat ─────────────────────────────────────────────
+    let ftyp = mp4_box(
+        b"ftyp",
+        [&b"isom"[..], &0x200u32.to_be_bytes(), b"isomiso2avc1mp41"].concat(),
+    );
+    let large_mdat = mdat_size + 8 > u32::MAX as u64;
+    let mdat_header_len: u64 = if large_mdat { 16 } else { 8 };
+
+    // Chunk offsets depend on the size of moov itself, so lay it out twice
+    let mut use_co64 = false;
+    let moov = loop {
+        let probe = build_moov(&out_tracks, 0, use_co64);
+        let base = ftyp.len() as u64 + probe.len() as u64 + mdat_header_len;
+        if !use_co64 && base + mdat_size > u32::MAX as u64 {
+            use_co64 = true;
+            continue;
+        }
+        break build_moov(&out_tracks, base, use_co64);
+    };
+
+    // ── Pass 2: sample data ──────────────────────────────────────────────────
+    let file = File::create(output).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::with_capacity(1 << 20, file);
+    writer.write_all(&ftyp).map_err(|e| e.to_string())?;
+    writer.write_all(&moov).map_err(|e| e.to_string())?;
+    if large_mdat {
+        writer
+            .write_all(&1u32.to_be_bytes())
+            .and_then(|_| writer.write_all(b"mdat"))
+            .and_then(|_| writer.write_all(&(mdat_size + 16).to_be_bytes()))
+            .map_err(|e| e.to_string())?;
+    } else {
+        writer
+            .write_all(&((mdat_size + 8) as u32).to_be_bytes())
+            .and_then(|_| writer.write_all(b"mdat"))
+            .map_err(|e| e.to_string())?;
+    }
+
+    let mut written: u64 = 0;
+    demux_all(input, extra_audio, &mut |sample| {
+        written += sample.data.len() as u64;
+        writer.write_all(&sample.data).map_err(|e| e.to_string())
+    })?;
+    if written != mdat_size {
+        return Err("Transport stream changed between remux passes".into());
+    }
+    writer.flush().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Durations of audio frames in the track's sample rate, from their decode
+/// times. Gaps come from resynced timestamps; a frame that overlaps the next
+/// is cut short.
+fn audio_durations(dts: &[i64], audio: &AudioInfo) -> Vec<i64> {
+    let rate = audio.sample_rate as i64;
+    let mut durations: Vec<i64> = dts
+        .windows(2)
+        .map(|w| ((w[1] - w[0]).max(0) * rate + TS_CLOCK / 2) / TS_CLOCK)
+        .collect();
+    durations.push(audio.frame_samples as i64);
+    durations
+}
+
+// ─── Codec configuration ─────────────────────────────────────────────────────
+
+/// Builds the `avcC` / `hvcC` box of a video track and reads its dimensions.
+fn video_config(track: &Track) -> Result<(Vec<u8>, u16, u16), String> {
+    let sets = |kind: u8| -> Vec<&Vec<u8>> {
+        track
+            .param_sets
+            .iter()
+            .filter(|(t, _)| *t == kind)
+            .map(|(_, nal)| nal)
+            .collect()
+    };
+
+    if track.codec == Codec::H264 {
+        let (sps, pps) = (sets(7), sets(8));
+        let first = sps
+            .first()
+            .filter(|s| s.len() >= 4)
+            .ok_or("H.264 stream has no SPS")?;
+        if pps.is_empty() {
+            return Err("H.264 stream has no PPS".into());
+        }
+        let (width, height) = h264_dimensions(first).ok_or("Could not parse H.264 SPS")?;
+
+        let mut body = vec![
+            1,
+            first[1],
+            first[2],
+            first[3],
+            0xff,
+            0xe0 | sps.len() as u8,
+        ];
+        for nal in &sps {
+            body.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            body.extend_from_slice(nal);
+        }
+        body.push(pps.len() as u8);
+        for nal in &pps {
+            body.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            body.extend_from_slice(nal);
+        }
+        return Ok((mp4_box(b"avcC", body), width, height));
+    }
+
+    let (vps, sps, pps) = (sets(32), sets(33), sets(34));
+    let first = sps.first().ok_or("H.265 stream has no SPS")?;
+    if vps.is_empty() || pps.is_empty() {
+        return Err("H.265 stream is missing its VPS or PPS".into());
+    }
+    let info = parse_hevc_sps(first).ok_or("Could not parse H.265 SPS")?;
+
+    let mut body = vec![1];
+    body.extend_from_slice(&info.profile_tier_level);
+    body.extend_from_slice(&0xf000u16.to_be_bytes()); // min_spatial_segmentation_idc
+    body.push(0xfc); // parallelismType
+    body.push(0xfc | info.chroma_format_idc);
+    body.push(0xf8 | info.bit_depth_luma_minus8);
+    body.push(0xf8 | info.bit_depth_chroma_minus8);
+    body.extend_from_slice(&0u16.to_be_bytes()); // avgFrameRate
+    body.push((info.max_sub_layers << 3) | ((info.temporal_id_nested as u8) << 2) | 0x03);
+    body.push(3);
+    for (kind, nals) in [(32u8, &vps), (33, &sps), (34, &pps)] {
+        body.push(0x80 | kind);
+        body.extend_from_slice(&(nals.len() as u16).to_be_bytes());
+        for nal in nals.iter() {
+            body.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            body.extend_from_slice(nal);
+        }
+    }
+    Ok((mp4_box(b"hvcC", body), info.width, info.height))
+}
+
+/// Reads the sample entry details of an audio track from its first frame.
+fn audio_info(codec: Codec, frame: &[u8]) -> Result<AudioInfo, String> {
+    const AAC_RATES: [u32; 13] = [
+        96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+    ];
+
+    if codec == Codec::Aac {
+        let object_type = (frame[2] >> 6) + 1;
+        let freq_index = (frame[2] >> 2) & 0x0f;
+        let channels = ((frame[2] & 0x01) << 2) | (frame[3] >> 6);
+        let sample_rate = *AAC_RATES
+            .get(freq_index as usize)
+            .ok_or("Invalid AAC sample rate")?;
+        if channels == 0 {
+            return Err("AAC streams with in-band channel configuration are not supported".into());
+        }
+
+        // AudioSpecificConfig inside an ES_Descriptor
+        let asc =
+            ((object_type as u16) << 11) | ((freq_index as u16) << 7) | ((channels as u16) << 3);
+        let decoder_specific = descriptor(0x05, asc.to_be_bytes().to_vec());
+        let mut decoder_config = vec![0x40, 0x15, 0, 0, 0];
+        decoder_config.extend_from_slice(&[0; 8]); // max / avg bitrate
+        decoder_config.extend_from_slice(&decoder_specific);
+        let mut es = vec![0, 0, 0]; // ES_ID, flags
+        es.extend_from_slice(&descriptor(0x04, decoder_config));
+        es.extend_from_slice(&descriptor(0x06, vec![0x02]));
+        return Ok(AudioInfo {
+            sample_rate,
+            channels: channels as u16,
+            frame_samples: 1024,
+            entry_type: *b"mp4a",
+            config_box: full_box(b"esds", 0, 0, descriptor(0x03, es)),
+        });
+    }
+
+    if codec == Codec::Eac3 {
+        return eac3_info(frame);
+    }
+
+    let (fscod, frmsizecod, bsid, bsmod, acmod, lfeon) =
+        ac3_header(frame).ok_or("Truncated AC-3 frame")?;
+    if bsid > 10 {
+        return Err("E-AC-3 audio is not supported for MP4 remux".into());
+    }
+    let sample_rate = match fscod {
+        0 => 48000,
+        1 => 44100,
+        2 => 32000,
+        _ => return Err("Invalid AC-3 sample rate".into()),
+    };
+    let channels = [2u16, 1, 2, 3, 3, 4, 4, 5][acmod as usize] + lfeon as u16;
+    let dac3 = (fscod << 22)
+        | (bsid << 17)
+        | (bsmod << 14)
+        | (acmod << 11)
+        | (lfeon << 10)
+        | ((frmsizecod >> 1) << 5);
+    Ok(AudioInfo {
+        sample_rate,
+        channels,
+        frame_samples: 1536,
+        entry_type: *b"ac-3",
+        config_box: mp4_box(b"dac3", dac3.to_be_bytes()[1..].to_vec()),
+    })
+}
+
+/// Reads the sample entry details of an E-AC-3 stream with a single
+/// independent substream, building its `dec3` box.
+fn eac3_info(frame: &[u8]) -> Result<AudioInfo, String> {
+    let mut r = BitReader::new(frame.get(2..).ok_or("Truncated E-AC-3 frame")?);
+    let header = (|| {
+        r.skip(5)?; // strmtyp, substreamid
+        let frmsiz = r.bits(11)?;
+        let fscod = r.bits(2)?;
+        let (sample_rate, blocks) = match fscod {
+            3 => ([24000, 22050, 16000].get(r.bits(2)? as usize).copied()?, 6),
+            _ => (
+                [48000, 44100, 32000][fscod as usize],
+                [1, 2, 3, 6][r.bits(2)? as usize],
+            ),
+        };
+        let acmod = r.bits(3)?;
+        let lfeon = r.bits(1)?;
+        let bsid = r.bits(5)?;
+        Some((frmsiz, fscod, sample_rate, blocks, acmod, lfeon, bsid))
+    })();
+    let (frmsiz, fscod, sample_rate, blocks, acmod, lfeon, bsid) =
+        header.ok_or("Invalid E-AC-3 frame header")?;
+
+    let frame_samples = blocks * 256;
+    let data_rate = (frmsiz + 1) * 16 * sample_rate / frame_samples / 1000;
+    // data_rate, num_ind_sub = 0; then fscod, bsid, asvc, bsmod = 0, acmod,
+    // lfeon, num_dep_sub = 0
+    let mut dec3 = ((data_rate.min(0x1fff) << 3) as u16).to_be_bytes().to_vec();
+    let sub = (fscod.min(3) << 22) | (bsid << 17) | (acmod << 9) | (lfeon << 8);
+    dec3.extend_from_slice(&sub.to_be_bytes()[1..]);
+    Ok(AudioInfo {
+        sample_rate,
+        channels: [2u16, 1, 2, 3, 3, 4, 4, 5][acmod as usize] + lfeon as u16,
+        frame_samples,
+        entry_type: *b"ec-3",
+        config_box: mp4_box(b"dec3", dec3),
+    })
+}
+
+/// Reads `(fscod, frmsizecod, bsid, bsmod, acmod, lfeon)` from an AC-3 syncframe.
+fn ac3_header(frame: &[u8]) -> Option<(u32, u32, u32, u32, u32, u32)> {
+    let mut r = BitReader::new(frame.get(4..)?);
+    let fscod = r.bits(2)?;
+    let frmsizecod = r.bits(6)?;
+    let bsid = r.bits(5)?;
+    let bsmod = r.bits(3)?;
+    let acmod = r.bits(3)?;
+    if acmod & 0x01 != 0 && acmod != 0x01 {
+        r.skip(2)?; // cmixlev
+    }
+    if acmod & 0x04 != 0 {
+        r.skip(2)?; // surmixlev
+    }
+    if acmod == 0x02 {
+        r.skip(2)?; // dsurmod
+    }
+    let lfeon = r.bit()?;
+    Some((fscod, frmsizecod, bsid, bsmod, acmod, lfeon))
+}
+
+/// An MPEG-4 descriptor with a single-byte length (all ours are < 128 bytes).
+fn descriptor(tag: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut out = vec![tag, body.len() as u8];
+    out.extend_from_slice(&body);
+    out
+}
+
+/// Reads the display size from an H.264 SPS NAL unit.
+fn h264_dimensions(sps: &[u8]) -> Option<(u16, u16)> {
+    let rbsp = ts::remove_emulation_prevention(sps);
+    let mut r = BitReader::new(rbsp.get(1..)?);
+    let profile_idc = r.bits(8)?;
+    r.skip(16)?; // constraint flags, level_idc
+    r.ue()?; // seq_parameter_set_id
+
+    let mut chroma_format_idc = 1;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = r.ue()?;
+        if chroma_format_idc == 3 {
+            r.skip(1)?; // separate_colour_plane_flag
+        }
+        r.ue()?; // bit_depth_luma_minus8
+        r.ue()?; // bit_depth_chroma_minus8
+        r.skip(1)?; // qpprime_y_zero_transform_bypass_flag
+        if r.bit()? == 1 {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if r.bit()? == 1 {
+                    skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    r.ue()?; // log2_max_frame_num_minus4
+    match r.ue()? {
+        0 => {
+            r.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            r.skip(1)?;
+            r.se()?;
+            r.se()?;
+            for _ in 0..r.ue()? {
+                r.se()?;
+            }
+        }
+        _ => {}
+    }
+    r.ue()?; // max_num_ref_frames
+    r.skip(1)?; // gaps_in_frame_num_value_allowed_flag
+    let width_mbs = r.ue()? + 1;
+    let height_units = r.ue()? + 1;
+    let frame_mbs_only = r.bit()?;
+    if frame_mbs_only == 0 {
+        r.skip(1)?; // mb_adaptive_frame_field_flag
+    }
+    r.skip(1)?; // direct_8x8_inference_flag
+
+    let (mut crop_x, mut crop_y) = (0, 0);
+    if r.bit()? == 1 {
+        crop_x = r.ue()? + r.ue()?;
+        crop_y = r.ue()? + r.ue()?;
+    }
+    let field_factor = 2 - frame_mbs_only;
+    let (unit_x, unit_y) = match chroma_format_idc {
+        0 => (1, field_factor),
+        1 => (2, 2 * field_factor),
+        2 => (2, field_factor),
+        _ => (1, field_factor),
+    };
+    let width = (width_mbs * 16).saturating_sub(unit_x * crop_x);
+    let height = (field_factor * height_units * 16).saturating_sub(unit_y * crop_y);
+    Some((u16::try_from(width).ok()?, u16::try_from(height).ok()?))
+}
+
+fn skip_scaling_list(r: &mut BitReader, size: usize) -> Option<()> {
+    let (mut last, mut next) = (8i32, 8i32);
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + r.se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+/// Fields of an H.265 SPS needed for the `hvcC` box.
+struct HevcSps {
+    profile_tier_level: [u8; 12],
+    max_sub_layers: u8,
+    temporal_id_nested: bool,
+    chroma_format_idc: u8,
+    bit_depth_luma_minus8: u8,
+    bit_depth_chroma_minus8: u8,
+    width: u16,
+    height: u16,
+}
+
+fn parse_hevc_sps(sps: &[u8]) -> Option<HevcSps> {
+    let rbsp = ts::remove_emulation_prevention(sps);
+    let profile_tier_level: [u8; 12] = rbsp.get(3..15)?.try_into().ok()?;
+    let mut r = BitReader::new(rbsp.get(2..)?);
+    r.skip(4)?; // sps_video_parameter_set_id
+    let max_sub_layers_minus1 = r.bits(3)? as usize;
+    let temporal_id_nested = r.bit()? == 1;
+    r.skip(96)?; // general profile_tier_level
+
+    let mut present = Vec::with_capacity(max_sub_layers_minus1);
+    for _ in 0..max_sub_layers_minus1 {
+        present.push((r.bit()?, r.bit()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        r.skip(2 * (8 - max_sub_layers_minus1))?;
+    }
+    for (profile_present, level_present) in present {
+        if profile_present == 1 {
+            r.skip(88)?;
+        }
+        if level_present == 1 {
+            r.skip(8)?;
+        }
+    }
+
+    r.ue()?; // sps_seq_parameter_set_id
+    let chroma_format_idc = r.ue()?;
+    if chroma_format_idc == 3 {
+        r.skip(1)?; // separate_colour_plane_flag
+    }
+    let mut width = r.ue()?;
+    let mut height = r.ue()?;
+    if r.bit()? == 1 {
+        let (sub_w, sub_h) = match chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        width = width.saturating_sub(sub_w * (r.ue()? + r.ue()?));
+        height = height.saturating_sub(sub_h * (r.ue()? + r.ue()?));
+    }
+    let bit_depth_luma_minus8 = r.ue()? as u8;
+    let bit_depth_chroma_minus8 = r.ue()? as u8;
+
+    Some(HevcSps {
+        profile_tier_level,
+        max_sub_layers: max_sub_layers_minus1 as u8 + 1,
+        temporal_id_nested,
+        chroma_format_idc: chroma_format_idc as u8,
+        bit_depth_luma_minus8,
+        bit_depth_chroma_minus8,
+        width: u16::try_from(width).ok()?,
+        height: u16::try_from(height).ok()?,
+    })
+}
+
+/// MSB-first bit reader with Exp-Golomb support, for parameter sets.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0 }
+    }
+
+    fn bit(&mut self) -> Option<u32> {
+        let byte = *self.data.get(self.pos / 8)?;
+        self.pos += 1;
+        Some(((byte >> (7 - (self.pos - 1) % 8)) & 0x01) as u32)
+    }
+
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..n {
+            value = (value << 1) | self.bit()?;
+        }
+        Some(value)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.pos += n;
+        (self.pos <= self.data.len() * 8).then_some(())
+    }
+
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        let value = (1u64 << zeros) - 1 + self.bits(zeros)? as u64;
+        u32::try_from(value).ok()
+    }
+
+    fn se(&mut self) -> Option<i32> {
+        let v = self.ue()? as i64;
+        Some(if v % 2 == 1 { (v + 1) / 2 } else { -(v / 2) } as i32)
+    }
+}
+
+// ─── Box layout ──────────────────────────────────────────────────────────────
+
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Wraps `body` in a box of type `kind`.
+pub fn mp4_box(kind: &[u8; 4], body: Vec<u8>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 8);
+    out.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(&body);
+    out
+}
+
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, body: Vec<u8>) -> Vec<u8> {
+    let mut full = Vec::with_capacity(body.len() + 4);
+    full.extend_from_slice(&((version as u32) << 24 | (flags & 0x00ff_ffff)).to_be_bytes());
+    full.extend_from_slice(&body);
+    mp4_box(kind, full)
+}
+
+fn put_u16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_be_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_be_bytes());
+}
+
+/// Packs a BCP-47 language tag into the ISO 639-2/T form used by `mdhd`.
+fn pack_language(tag: Option<&str>) -> u16 {
+    const ISO_639_2: [(&str, &str); 30] = [
+        ("ar", "ara"),
+        ("cs", "ces"),
+        ("da", "dan"),
+        ("de", "deu"),
+        ("el", "ell"),
+        ("en", "eng"),
+        ("es", "spa"),
+        ("fi", "fin"),
+        ("fr", "fra"),
+        ("he", "heb"),
+        ("hi", "hin"),
+        ("hu", "hun"),
+        ("id", "ind"),
+        ("it", "ita"),
+        ("ja", "jpn"),
+        ("ko", "kor"),
+        ("nb", "nob"),
+        ("nl", "nld"),
+        ("no", "nor"),
+        ("pl", "pol"),
+        ("pt", "por"),
+        ("ro", "ron"),
+        ("ru", "rus"),
+        ("sv", "swe"),
+        ("th", "tha"),
+        ("tr", "tur"),
+        ("uk", "ukr"),
+        ("vi", "vie"),
+        ("zh", "zho"),
+        ("nn", "nno"),
+    ];
+    let primary = tag
+        .and_then(|t| t.split(['-', '_']).next())
+        .map(|p| p.to_ascii_lowercase())
+        .unwrap_or_default();
+    let code = match primary.len() {
+        2 => ISO_639_2
+            .iter()
+            .find(|(short, _)| *short == primary)
+            .map(|(_, long)| long.to_string()),
+        3 if primary.bytes().all(|b| b.is_ascii_lowercase()) => Some(primary),
+        _ => None,
+    }
+    .unwrap_or_else(|| "und".into());
+    code.bytes()
+        .fold(0u16, |acc, b| (acc << 5) | (b - 0x60) as u16)
+}
+
+/// Clamps a duration to the 32-bit fields of version-0 boxes.
+fn clamp_u32(v: i64) -> u32 {
+    v.clamp(0, u32::MAX as i64) as u32
+}
+
+fn build_moov(tracks: &[OutTrack], mdat_base: u64, use_co64: bool) -> Vec<u8> {
+    let duration = tracks.iter().map(|t| t.movie_duration()).max().unwrap_or(0);
+
+    let mut mvhd = Vec::new();
+    put_u32(&mut mvhd, 0); // creation_time
+    put_u32(&mut mvhd, 0); // modification_time
+    put_u32(&mut mvhd, MOVIE_TIMESCALE as u32);
+    put_u32(&mut mvhd, clamp_u32(duration));
+    put_u32(&mut mvhd, 0x0001_0000); // rate
+    put_u16(&mut mvhd, 0x0100); // volume
+    mvhd.extend_from_slice(&[0; 10]);
+    for v in MATRIX {
+        put_u32(&mut mvhd, v);
+    }
+    mvhd.extend_from_slice(&[0; 24]);
+    put_u32(&mut mvhd, tracks.len() as u32 + 1);
+
+    let mut body = full_box(b"mvhd", 0, 0, mvhd);
+    for track in tracks {
+        body.extend_from_slice(&build_trak(track, mdat_base, use_co64));
+    }
+    mp4_box(b"moov", body)
+}
+
+fn build_trak(track: &OutTrack, mdat_base: u64, use_co64: bool) -> Vec<u8> {
+    let is_video = track.codec.is_video();
+    let movie_duration = track.movie_duration();
+    let media_duration = track.media_duration();
+
+    let mut tkhd = Vec::new();
+    put_u32(&mut tkhd, 0); // creation_time
+    put_u32(&mut tkhd, 0); // modification_time
+    put_u32(&mut tkhd, track.id);
+    put_u32(&mut tkhd, 0);
+    put_u32(&mut tkhd, clamp_u32(movie_duration));
+    tkhd.extend_from_slice(&[0; 8]);
+    put_u16(&mut tkhd, 0); // layer
+    put_u16(&mut tkhd, if is_video { 0 } else { 1 }); // alternate_group
+    put_u16(&mut tkhd, if is_video { 0 } else { 0x0100 }); // volume
+    put_u16(&mut tkhd, 0);
+    for v in MATRIX {
+        put_u32(&mut tkhd, v);
+    }
+    put_u32(&mut tkhd, (track.width as u32) << 16);
+    put_u32(&mut tkhd, (track.height as u32) << 16);
+
+    // Edit list: optional empty edit for a late start, then the media from its first presented sample
+    let mut elst = Vec::new();
+    let mut entries: Vec<(u32, i32)> = Vec::new();
+    if track.delay > 0 {
+        entries.push((clamp_u32(track.delay), -1));
+    }
+    entries.push((
+        clamp_u32(movie_duration - track.delay),
+        track.media_start.clamp(0, i32::MAX as i64) as i32,
+    ));
+    put_u32(&mut elst, entries.len() as u32);
+    for (segment_duration, media_time) in entries {
+        put_u32(&mut elst, segment_duration);
+        elst.extend_from_slice(&media_time.to_be_bytes());
+        put_u32(&mut elst, 0x0001_0000); // media_rate 1.0
+    }
+    let edts = mp4_box(b"edts", full_box(b"elst", 0, 0, elst));
+
+    let mut mdhd = Vec::new();
+    put_u32(&mut mdhd, 0);
+    put_u32(&mut mdhd, 0);
+    put_u32(&mut mdhd, track.timescale as u32);
+    put_u32(&mut mdhd, clamp_u32(media_duration));
+    put_u16(&mut mdhd, track.language);
+    put_u16(&mut mdhd, 0);
+
+    let mut hdlr = vec![0; 4];
+    hdlr.extend_from_slice(if is_video { b"vide" } else { b"soun" });
+    hdlr.extend_from_slice(&[0; 12]);
+    hdlr.extend_from_slice(track.name.as_bytes());
+    hdlr.push(0);
+
+    let media_header = if is_video {
+        full_box(b"vmhd", 0, 1, vec![0; 8])
+    } else {
+        full_box(b"smhd", 0, 0, vec![0; 4])
+    };
+    let mut dref = Vec::new();
+    put_u32(&mut dref, 1);
+    dref.extend_from_slice(&full_box(b"url ", 0, 1, Vec::new()));
+    let dinf = mp4_box(b"dinf", full_box(b"dref", 0, 0, dref));
+
+    let minf = mp4_box(
+        b"minf",
+        [media_header, dinf, build_stbl(track, mdat_base, use_co64)].concat(),
+    );
+    let mdia = mp4_box(
+        b"mdia",
+        [
+            full_box(b"mdhd", 0, 0, mdhd),
+            full_box(b"hdlr", 0, 0, hdlr),
+            minf,
+        ]
+        .concat(),
+    );
+    mp4_box(
+        b"trak",
+        [
+            full_box(b"tkhd", 0, if track.enabled { 0x03 } else { 0x02 }, tkhd),
+            edts,
+            mdia,
+        ]
+        .concat(),
+    )
+}
+
+fn build_stbl(track: &OutTrack, mdat_base: u64, use_co64: bool) -> Vec<u8> {
+    let table = track.table;
+
+    let mut stsd = Vec::new();
+    put_u32(&mut stsd, 1);
+    stsd.extend_from_slice(&track.sample_entry);
+    let mut boxes = vec![full_box(b"stsd", 0, 0, stsd)];
+
+    let stts = run_lengths(&track.durations);
+    boxes.push(full_box(b"stts", 0, 0, run_length_body(&stts)));
+
+    if table.cts_offsets.iter().any(|o| *o != 0) {
+        let ctts = run_lengths(&table.cts_offsets);
+        boxes.push(full_box(b"ctts", 0, 0, run_length_body(&ctts)));
+    }
+
+    if track.codec.is_video() && table.sync.len() < table.sizes.len() {
+        let mut stss = Vec::with_capacity(4 + table.sync.len() * 4);
+        put_u32(&mut stss, table.sync.len() as u32);
+        for n in &table.sync {
+            put_u32(&mut stss, *n);
+        }
+        boxes.push(full_box(b"stss", 0, 0, stss));
+    }
+
+    // One stsc entry each time the samples-per-chunk count changes
+    let mut stsc_entries: Vec<(u32, u32)> = Vec::new();
+    for (i, (_, count)) in table.chunks.iter().enumerate() {
+        if stsc_entries.last().map(|(_, c)| c) != Some(count) {
+            stsc_entries.push((i as u32 + 1, *count));
+        }
+    }
+    let mut stsc = Vec::new();
+    put_u32(&mut stsc, stsc_entries.len() as u32);
+    for (first_chunk, count) in stsc_entries {
+        put_u32(&mut stsc, first_chunk);
+        put_u32(&mut stsc, count);
+        put_u32(&mut stsc, 1);
+    }
+    boxes.push(full_box(b"stsc", 0, 0, stsc));
+
+    let mut stsz = Vec::with_capacity(8 + table.sizes.len() * 4);
+    put_u32(&mut stsz, 0);
+    put_u32(&mut stsz, table.sizes.len() as u32);
+    for size in &table.sizes {
+        put_u32(&mut stsz, *size);
+    }
+    boxes.push(full_box(b"stsz", 0, 0, stsz));
+
+    let mut offsets = Vec::new();
+    put_u32(&mut offsets, table.chunks.len() as u32);
+    for (offset, _) in &table.chunks {
+        if use_co64 {
+            offsets.extend_from_slice(&(mdat_base + offset).to_be_bytes());
+        } else {
+            put_u32(&mut offsets, (mdat_base + offset) as u32);
+        }
+    }
+    boxes.push(full_box(
+        if use_co64 { b"co64" } else { b"stco" },
+        0,
+        0,
+        offsets,
+    ));
+
+    mp4_box(b"stbl", boxes.concat())
+}
+
+/// Collapses values into `(count, value)` runs for `stts` / `ctts`.
+fn run_lengths(values: &[i64]) -> Vec<(u32, i64)> {
+    let mut runs: Vec<(u32, i64)> = Vec::new();
+    for v in values {
+        match runs.last_mut() {
+            Some((count, last)) if last == v => *count += 1,
+            _ => runs.push((1, *v)),
+        }
+    }
+    runs
+}
+
+fn run_length_body(runs: &[(u32, i64)]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(4 + runs.len() * 8);
+    put_u32(&mut body, runs.len() as u32);
+    for (count, value) in runs {
+        put_u32(&mut body, *count);
+        put_u32(&mut body, clamp_u32(*value));
+    }
+    body
+}
+
+fn video_sample_entry(kind: &[u8; 4], width: u16, height: u16, config: &[u8]) -> Vec<u8> {
+    let mut body = vec![0; 6];
+    put_u16(&mut body, 1); // data_reference_index
+    body.extend_from_slice(&[0; 16]);
+    put_u16(&mut body, width);
+    put_u16(&mut body, height);
+    put_u32(&mut body, 0x0048_0000); // 72 dpi
+    put_u32(&mut body, 0x0048_0000);
+    put_u32(&mut body, 0);
+    put_u16(&mut body, 1); // frame_count
+    body.extend_from_slice(&[0; 32]); // compressorname
+    put_u16(&mut body, 0x0018); // depth
+    put_u16(&mut body, 0xffff);
+    body.extend_from_slice(config);
+    mp4_box(kind, body)
+}
+
+fn audio_sample_entry(audio: &AudioInfo) -> Vec<u8> {
+    let mut body = vec![0; 6];
+    put_u16(&mut body, 1); // data_reference_index
+    body.extend_from_slice(&[0; 8]);
+    // AC-3 sample entries always declare stereo; dac3 / dec3 carry the layout
+    put_u16(
+        &mut body,
+        if audio.entry_type == *b"ac-3" || audio.entry_type == *b"ec-3" {
+            2
+        } else {
+            audio.channels
+        },
+    );
+    put_u16(&mut body, 16); // samplesize
+    put_u32(&mut body, 0);
+    put_u32(&mut body, audio.sample_rate.min(0xffff) << 16);
+    body.extend_from_slice(&audio.config_box);
+    mp4_box(&audio.entry_type, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An AAC-LC ADTS frame at 48 kHz stereo carrying `payload` bytes.
+    fn adts(payload: usize) -> Vec<u8> {
+        let len = payload + 7;
+        let mut frame = vec![
+            0xff,
+            0xf1,
+            (1 << 6) | (3 << 2),
+            (2 << 6) | (len >> 11) as u8,
+            (len >> 3) as u8,
+            ((len & 0x07) << 5) as u8 | 0x1f,
+            0xfc,
+        ];
+        frame.resize(len, 0xaa);
+        frame
+    }
+
+    /// Runs `payloads` through an AAC track and returns the sample times.
+    fn audio_times(payloads: &[(Vec<u8>, Option<i64>)]) -> Vec<i64> {
+        let mut track = Track::new(Codec::Aac);
+        let mut times = Vec::new();
+        for (payload, pts) in payloads {
+            track
+                .audio_samples(0, payload, *pts, &mut |s| {
+                    times.push(s.dts);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        times
+    }
+
+    /// The body of the box at `path`, descending through container boxes.
+    fn find_box<'a>(mut data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+        while data.len() >= 8 {
+            let size = u32::from_be_bytes(data[..4].try_into().ok()?) as usize;
+            let body = data.get(8..size)?;
+            if &data[4..8] == path[0] {
+                return match path.len() {
+                    1 => Some(body),
+                    _ => find_box(body, &path[1..]),
+                };
+            }
+            data = &data[size..];
+        }
+        None
+    }
+
+    fn read_u32(data: &[u8], at: usize) -> u32 {
+        u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn nal_units_strip_start_codes() {
+        let stream = [
+            0, 0, 0, 1, 0x67, 1, 2, // 4-byte start code
+            0, 0, 1, 0x68, 3, // 3-byte start code
+            0, 0, 0, 1, 0x65, 4, 0, 5, 0, 0, // trailing zeros
+        ];
+        assert_eq!(
+            nal_units(&stream),
+            vec![&[0x67, 1, 2][..], &[0x68, 3][..], &[0x65, 4, 0, 5][..]]
+        );
+        assert!(nal_units(&[1, 2, 3]).is_empty());
+    }
+
+    #[test]
+    fn adts_header() {
+        let frame = adts(100);
+        assert_eq!(ts::adts_frame(&frame), Some((7, 107)));
+        // With a CRC the header is two bytes longer
+        let mut protected = frame.clone();
+        protected[1] = 0xf0;
+        assert_eq!(ts::adts_frame(&protected), Some((9, 107)));
+        assert_eq!(ts::adts_frame(&frame[1..]), None);
+
+        let info = audio_info(Codec::Aac, &frame).unwrap();
+        assert_eq!(info.sample_rate, 48000);
+        assert_eq!(info.channels, 2);
+        assert_eq!(info.frame_samples, 1024);
+        assert_eq!(&info.entry_type, b"mp4a");
+        // AudioSpecificConfig: AAC-LC, 48 kHz, 2 channels
+        assert!(info
+            .config_box
+            .windows(4)
+            .any(|w| w == [0x05, 0x02, 0x11, 0x90]));
+    }
+
+    #[test]
+    fn audio_frames_are_split_and_timed() {
+        let frame = adts(50);
+        // 1024 samples at 48 kHz are 1920 ticks of the 90 kHz clock
+        let times = audio_times(&[([frame.clone(), frame.clone()].concat(), Some(9000))]);
+        assert_eq!(times, vec![9000, 10920]);
+        // Frames before the first timestamp are dropped
+        let times = audio_times(&[(frame.clone(), None), (frame.clone(), Some(500))]);
+        assert_eq!(times, vec![500]);
+    }
+
+    #[test]
+    fn audio_clock_resyncs_on_each_pts() {
+        let frame = adts(50);
+        // A gap of ten frames, as after a lost packet or a discontinuity
+        let times = audio_times(&[
+            ([frame.clone(), frame.clone()].concat(), Some(0)),
+            (frame.clone(), Some(10 * 1920)),
+        ]);
+        assert_eq!(times, vec![0, 1920, 19200]);
+
+        // A frame split across PES packets keeps the first packet's clock;
+        // the second packet's timestamp belongs to the frame after it
+        let (head, tail) = frame.split_at(20);
+        let times = audio_times(&[
+            ([&frame[..], head].concat(), Some(0)),
+            ([tail, &frame[..]].concat(), Some(50_000)),
+        ]);
+        assert_eq!(times, vec![0, 1920, 50_000]);
+
+        // A timestamp that arrives before any complete frame is kept
+        let times = audio_times(&[
+            (head.to_vec(), Some(3000)),
+            ([tail, &frame[..]].concat(), Some(8000)),
+        ]);
+        assert_eq!(times, vec![3000, 8000]);
+    }
+
+    /// An E-AC-3 syncframe header: 48 kHz, six blocks, 5.1, 768 bytes.
+    fn eac3(strmtyp: u8) -> Vec<u8> {
+        let frmsiz = 383u16;
+        let mut frame = vec![
+            0x0b,
+            0x77,
+            (strmtyp << 6) | (frmsiz >> 8) as u8,
+            frmsiz as u8,
+            (3 << 4) | (7 << 1) | 1, // fscod 0, numblkscod 3, acmod 7, lfeon
+            16 << 3,                 // bsid 16
+        ];
+        frame.resize(768, 0);
+        frame
+    }
+
+    #[test]
+    fn eac3_stream() {
+        assert_eq!(Codec::from_stream_type(0x87), Ok(Some(Codec::Eac3)));
+        let frame = eac3(0);
+        assert_eq!(ts::ac3_frame_len(&frame), Some(768));
+
+        let info = audio_info(Codec::Eac3, &frame).unwrap();
+        assert_eq!(info.sample_rate, 48000);
+        assert_eq!(info.channels, 6);
+        assert_eq!(info.frame_samples, 1536);
+        assert_eq!(&info.entry_type, b"ec-3");
+        // 768 bytes every 32 ms: 192 kbps, one independent substream
+        assert_eq!(&info.config_box[8..10], &((192u16 << 3).to_be_bytes()));
+        assert_eq!(info.config_box[10..], [16 << 1, 7 << 1 | 1, 0]);
+
+        let mut track = Track::new(Codec::Eac3);
+        let frames = [frame, eac3(1)].concat();
+        let result = track.audio_samples(0, &frames, Some(0), &mut |_| Ok(()));
+        assert!(result.is_err(), "dependent substreams stay MPEG-TS");
+    }
+
+    #[test]
+    fn moov_chunk_offsets() {
+        let audio = audio_info(Codec::Aac, &adts(10)).unwrap();
+        let table = Table {
+            dts: vec![0, 1920, 3840],
+            cts_offsets: vec![0; 3],
+            sizes: vec![10, 10, 10],
+            sync: vec![1, 2, 3],
+            min_pts: Some(0),
+            chunks: vec![(0, 2), (500, 1)],
+        };
+        let track = OutTrack {
+            id: 1,
+            codec: Codec::Aac,
+            table: &table,
+            timescale: 48000,
+            durations: vec![1024; 3],
+            delay: 0,
+            media_start: 0,
+            sample_entry: audio_sample_entry(&audio),
+            width: 0,
+            height: 0,
+            language: pack_language(Some("en")),
+            name: "SoundHandler".into(),
+            enabled: true,
+        };
+        let stbl: [&[u8; 4]; 5] = [b"moov", b"trak", b"mdia", b"minf", b"stbl"];
+
+        let moov = build_moov(std::slice::from_ref(&track), 1000, false);
+        assert_eq!(read_u32(&moov, 0) as usize, moov.len());
+        let stco = find_box(&moov, &[&stbl[..], &[b"stco"]].concat()).unwrap();
+        assert_eq!(read_u32(stco, 4), 2);
+        assert_eq!((read_u32(stco, 8), read_u32(stco, 12)), (1000, 1500));
+        assert!(find_box(&moov, &[&stbl[..], &[b"co64"]].concat()).is_none());
+
+        // stsc: chunk 1 holds two samples, chunk 2 onwards one
+        let stsc = find_box(&moov, &[&stbl[..], &[b"stsc"]].concat()).unwrap();
+        assert_eq!(read_u32(stsc, 4), 2);
+        assert_eq!((read_u32(stsc, 8), read_u32(stsc, 12)), (1, 2));
+        assert_eq!((read_u32(stsc, 20), read_u32(stsc, 24)), (2, 1));
+
+        // Past 4 GiB the offsets need 64 bits
+        let base = 5_000_000_000u64;
+        let moov = build_moov(std::slice::from_ref(&track), base, true);
+        assert!(find_box(&moov, &[&stbl[..], &[b"stco"]].concat()).is_none());
+        let co64 = find_box(&moov, &[&stbl[..], &[b"co64"]].concat()).unwrap();
+        assert_eq!(read_u32(co64, 4), 2);
+        let offset = |at: usize| u64::from_be_bytes(co64[at..at + 8].try_into().unwrap());
+        assert_eq!((offset(8), offset(16)), (base, base + 500));
+    }
+
+    #[test]
+    fn audio_durations_follow_timestamps() {
+        let audio = audio_info(Codec::Aac, &adts(10)).unwrap();
+        let dts = [0, 1920, 3840, 3840 + 10 * 1920, 3840];
+        assert_eq!(
+            audio_durations(&dts, &audio),
+            vec![1024, 1024, 10240, 0, 1024]
+        );
+        // 44.1 kHz frames do not land on whole 90 kHz ticks
+        let mut audio = audio;
+        audio.sample_rate = 44100;
+        assert_eq!(audio_durations(&[0, 2089, 4179], &audio), vec![1024; 3]);
+    }
+}
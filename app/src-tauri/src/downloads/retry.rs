@@ -0,0 +1,65 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::error::DownloadError;
+
+/// How often, and how patiently, a failed request is tried again.
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled for each one after it
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+/// Whole downloads: re-queued after a transient failure. Eight retries from
+/// 10s doubling upwards ride out about 45 minutes of outage.
+pub const DOWNLOAD_RETRY: RetryPolicy = RetryPolicy {
+    max_retries: 8,
+    base_delay: Duration::from_secs(10),
+    max_delay: Duration::from_secs(30 * 60),
+};
+
+/// Single HLS / DASH segments: retried in place before the download fails.
+pub const SEGMENT_RETRY: RetryPolicy = RetryPolicy {
+    max_retries: 3,
+    base_delay: Duration::from_millis(500),
+    max_delay: Duration::from_secs(8),
+};
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (1-based): exponential backoff,
+    /// capped, with ±25% jitter so that downloads failing together do not
+    /// hammer the server together.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(1u32 << retry.saturating_sub(1).min(16))
+            .min(self.max_delay);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        // 0.75 ..= 1.25
+        let factor = 0.75 + (nanos % 1_000) as f64 / 1_998.0;
+        exp.mul_f64(factor)
+    }
+}
+
+/// Whether a download error is worth retrying: network failures, timeouts,
+/// dropped connections, 5xx / 408 / 429 responses and a remote file that
+/// changed mid-download. Everything else — 4xx, unparsable playlists, DRM,
+/// local I/O — fails for good.
+pub fn is_transient(error: &DownloadError) -> bool {
+    match error {
+        DownloadError::Http(status) => *status >= 500 || *status == 408 || *status == 429,
+        DownloadError::Network(_) | DownloadError::RemoteChanged => true,
+        _ => false,
+    }
+}
+
+/// Whether a download error means its stream URL has expired (403 / 410, as
+/// debrid and signed CDN links answer once their token runs out). Such
+/// downloads wait for the frontend to resolve a fresh URL.
+pub fn is_url_expired(error: &DownloadError) -> bool {
+    matches!(error, DownloadError::Http(403) | DownloadError::Http(410))
+}
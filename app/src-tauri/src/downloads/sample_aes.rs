@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+
+use super::ts;
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+/// Elementary stream formats that SAMPLE-AES can encrypt inside MPEG-TS.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EncryptedCodec {
+    H264,
+    Aac,
+    Ac3,
+}
+
+/// Maps a SAMPLE-AES stream type to its codec and the clear stream type that
+/// replaces it in the PMT once the samples are decrypted.
+fn encrypted_stream_type(stream_type: u8) -> Option<(EncryptedCodec, u8)> {
+    match stream_type {
+        0xdb => Some((EncryptedCodec::H264, 0x1b)),
+        0xcf => Some((EncryptedCodec::Aac, 0x0f)),
+        0xc1 => Some((EncryptedCodec::Ac3, 0x81)),
+        0xc2 => Some((EncryptedCodec::Ac3, 0x87)),
+        _ => None,
+    }
+}
+
+/// A PES packet being reassembled from TS packets of one PID.
+struct PendingPes {
+    adaptation: Option<Vec<u8>>,
+    data: Vec<u8>,
+    slot: usize,
+}
+
+/// Output pieces in original packet order: untouched packets, or the slot a
+/// re-packetized PES is written into once it is complete.
+enum Piece {
+    Raw(Vec<u8>),
+    Pes(usize),
+}
+
+/// Decrypts a SAMPLE-AES encrypted MPEG-TS segment (H.264, AAC and AC-3/E-AC-3).
+/// Encrypted PES packets are decrypted and re-packetized; every other packet is
+/// passed through, and the PMT is rewritten to announce the clear stream types.
+pub fn decrypt_ts(data: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Result<Vec<u8>, String> {
+    if !ts::is_transport_stream(data) {
+        return Err("SAMPLE-AES is only supported for MPEG-TS segments".into());
+    }
+
+    // Find the PMT(s) and the encrypted elementary streams they describe
+    let mut pmt_pids: Vec<u16> = Vec::new();
+    let mut codecs: HashMap<u16, EncryptedCodec> = HashMap::new();
+    for packet in data.chunks_exact(ts::PACKET_SIZE) {
+        let pid = match ts::parse_packet(packet) {
+            Some(p) => p.pid,
+            None => continue,
+        };
+        if pid == ts::PAT_PID {
+            for pmt in ts::parse_pat(packet) {
+                if !pmt_pids.contains(&pmt) {
+                    pmt_pids.push(pmt);
+                }
+            }
+        } else if pmt_pids.contains(&pid) {
+            for stream in ts::parse_pmt(packet) {
+                if let Some((codec, _)) = encrypted_stream_type(stream.stream_type) {
+                    codecs.insert(stream.pid, codec);
+                }
+            }
+        }
+    }
+    if codecs.is_empty() {
+        return Err("SAMPLE-AES segment has no supported encrypted streams".into());
+    }
+
+    let mut pieces: Vec<Piece> = Vec::new();
+    let mut slots: Vec<Vec<u8>> = Vec::new();
+    let mut pending: HashMap<u16, PendingPes> = HashMap::new();
+    let mut continuity: HashMap<u16, u8> = HashMap::new();
+
+    for packet in data.chunks_exact(ts::PACKET_SIZE) {
+        let pkt = match ts::parse_packet(packet) {
+            Some(p) => p,
+            None => return Err("Corrupt MPEG-TS packet in SAMPLE-AES segment".into()),
+        };
+
+        if pmt_pids.contains(&pkt.pid) && pkt.pusi {
+            let mut rewritten = packet.to_vec();
+            for stream in ts::parse_pmt(packet) {
+                if let Some((_, clear_type)) = encrypted_stream_type(stream.stream_type) {
+                    rewritten[stream.type_offset] = clear_type;
+                }
+            }
+            ts::fix_psi_crc(&mut rewritten);
+            pieces.push(Piece::Raw(rewritten));
+            continue;
+        }
+
+        let codec = match codecs.get(&pkt.pid) {
+            Some(c) => *c,
+            None => {
+                pieces.push(Piece::Raw(packet.to_vec()));
+                continue;
+            }
+        };
+
+        if pkt.pusi {
+            if let Some(done) = pending.remove(&pkt.pid) {
+                let cc = continuity.entry(pkt.pid).or_insert(pkt.continuity);
+                finish_pes(pkt.pid, cc, codec, done, &mut slots, key, iv);
+            }
+            continuity.entry(pkt.pid).or_insert(pkt.continuity);
+            slots.push(Vec::new());
+            pieces.push(Piece::Pes(slots.len() - 1));
+            pending.insert(
+                pkt.pid,
+                PendingPes {
+                    adaptation: pkt.adaptation.map(|a| a.to_vec()),
+                    data: pkt.payload.to_vec(),
+                    slot: slots.len() - 1,
+                },
+            );
+        } else if let Some(pes) = pending.get_mut(&pkt.pid) {
+            pes.data.extend_from_slice(pkt.payload);
+        } else {
+            // Continuation of a PES that started before this segment
+            pieces.push(Piece::Raw(packet.to_vec()));
+        }
+    }
+
+    for (pid, done) in pending.drain() {
+        let codec = codecs[&pid];
+        let cc = continuity.entry(pid).or_insert(0);
+        finish_pes(pid, cc, codec, done, &mut slots, key, iv);
+    }
+
+    let mut out = Vec::with_capacity(data.len() + data.len() / 16);
+    for piece in pieces {
+        match piece {
+            Piece::Raw(bytes) => out.extend_from_slice(&bytes),
+            Piece::Pes(slot) => out.extend_from_slice(&slots[slot]),
+        }
+    }
+    Ok(out)
+}
+
+/// Decrypts one reassembled PES packet and writes its TS packets into its slot.
+fn finish_pes(
+    pid: u16,
+    continuity: &mut u8,
+    codec: EncryptedCodec,
+    pes: PendingPes,
+    slots: &mut [Vec<u8>],
+    key: &[u8; 16],
+    iv: &[u8; 16],
+) {
+    let mut data = pes.data;
+    if let Some(header_len) = ts::pes_header_len(&data) {
+        let payload = &data[header_len..];
+        let clear = match codec {
+            EncryptedCodec::H264 => decrypt_h264(payload, key, iv),
+            EncryptedCodec::Aac | EncryptedCodec::Ac3 => {
+                decrypt_audio_frames(payload, key, iv, codec)
+            }
+        };
+        let mut rebuilt = data[..header_len].to_vec();
+        rebuilt.extend_from_slice(&clear);
+
+        // PES_packet_length counts the bytes after the length field; 0 means unbounded
+        if rebuilt[4] != 0 || rebuilt[5] != 0 {
+            let len = rebuilt.len() - 6;
+            let len = if len > 0xffff { 0 } else { len as u16 };
+            rebuilt[4..6].copy_from_slice(&len.to_be_bytes());
+        }
+        data = rebuilt;
+    }
+    ts::packetize_pes(
+        pid,
+        continuity,
+        pes.adaptation.as_deref(),
+        &data,
+        &mut slots[pes.slot],
+    );
+}
+
+/// CBC-decrypts `blocks` (a whole number of 16-byte blocks) in place.
+fn cbc_decrypt(blocks: &mut [u8], key: &[u8; 16], iv: &[u8; 16]) {
+    let len = blocks.len() - blocks.len() % 16;
+    if len == 0 {
+        return;
+    }
+    let _ = Aes128CbcDec::new(key.into(), iv.into())
+        .decrypt_padded_mut::<aes::cipher::block_padding::NoPadding>(&mut blocks[..len]);
+}
+
+/// Decrypts the ADTS (AAC) or AC-3 frames of an audio PES payload.
+/// Each frame keeps its header and a 16-byte leader in the clear, followed by
+/// encrypted whole blocks; any trailing partial block is clear.
+fn decrypt_audio_frames(
+    payload: &[u8],
+    key: &[u8; 16],
+    iv: &[u8; 16],
+    codec: EncryptedCodec,
+) -> Vec<u8> {
+    let mut out = payload.to_vec();
+    let mut pos = 0;
+    while pos < out.len() {
+        let (header_len, frame_len) = match codec {
+            EncryptedCodec::Aac => match ts::adts_frame(&out[pos..]) {
+                Some(f) => f,
+                None => break,
+            },
+            _ => match ts::ac3_frame_len(&out[pos..]) {
+                Some(len) => (0, len),
+                None => break,
+            },
+        };
+        if pos + frame_len > out.len() {
+            log::warn!("[SAMPLE-AES] Audio frame crosses PES boundary; leaving it as-is");
+            break;
+        }
+        let body_start = pos + header_len + 16;
+        let frame_end = pos + frame_len;
+        if body_start < frame_end {
+            cbc_decrypt(&mut out[body_start..frame_end], key, iv);
+        }
+        pos = frame_end;
+    }
+    out
+}
+
+/// Decrypts the protected NAL units (types 1 and 5) of an H.264 PES payload.
+fn decrypt_h264(payload: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    let starts = ts::start_code_ends(payload);
+    let first = match starts.first() {
+        Some(s) => *s,
+        None => return payload.to_vec(),
+    };
+
+    let mut out = Vec::with_capacity(payload.len());
+    out.extend_from_slice(&payload[..first]);
+    for (i, &begin) in starts.iter().enumerate() {
+        let end = starts
+            .get(i + 1)
+            .map(|next| next - 3)
+            .unwrap_or(payload.len());
+        let region = &payload[begin..end];
+        // Zero bytes before the next start code belong to the stream, not the NAL
+        let nal_len = region
+            .iter()
+            .rposition(|b| *b != 0)
+            .map(|p| p + 1)
+            .unwrap_or(0);
+        let (nal, zeros) = region.split_at(nal_len);
+
+        let nal_type = nal.first().map(|b| b & 0x1f).unwrap_or(0);
+        if nal.len() > 48 && (nal_type == 1 || nal_type == 5) {
+            let mut rbsp = ts::remove_emulation_prevention(nal);
+            decrypt_nal(&mut rbsp, key, iv);
+            out.extend_from_slice(&add_emulation_prevention(&rbsp));
+        } else {
+            out.extend_from_slice(nal);
+        }
+        out.extend_from_slice(zeros);
+        if i + 1 < starts.len() {
+            out.extend_from_slice(&[0x00, 0x00, 0x01]);
+        }
+    }
+    out
+}
+
+/// Decrypts an unescaped NAL unit: 32 clear bytes, then one encrypted block
+/// followed by up to nine clear blocks, repeated. The encrypted blocks form a
+/// single CBC chain; a final block of 16 bytes or less stays clear.
+fn decrypt_nal(nal: &mut [u8], key: &[u8; 16], iv: &[u8; 16]) {
+    let mut offsets = Vec::new();
+    let mut pos = 32;
+    while nal.len() > pos + 16 {
+        offsets.push(pos);
+        pos += 16 * 10;
+    }
+    if offsets.is_empty() {
+        return;
+    }
+
+    let mut blocks: Vec<u8> = Vec::with_capacity(offsets.len() * 16);
+    for &o in &offsets {
+        blocks.extend_from_slice(&nal[o..o + 16]);
+    }
+    cbc_decrypt(&mut blocks, key, iv);
+    for (i, &o) in offsets.iter().enumerate() {
+        nal[o..o + 16].copy_from_slice(&blocks[i * 16..i * 16 + 16]);
+    }
+}
+
+fn add_emulation_prevention(rbsp: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(rbsp.len() + rbsp.len() / 64);
+    let mut zeros = 0;
+    for &b in rbsp {
+        if zeros >= 2 && b <= 0x03 {
+            out.push(0x03);
+            zeros = 0;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+    if out.last() == Some(&0x00) {
+        out.push(0x03);
+    }
+    out
+}
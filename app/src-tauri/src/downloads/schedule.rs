@@ -0,0 +1,52 @@
+use chrono::Timelike;
+use serde::{Deserialize, Serialize};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// Daily window, in local time, in which downloads may run. Outside it queued
+/// downloads wait and running ones are paused until it opens again.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DownloadSchedule {
+    /// When false, downloads run at any time
+    pub enabled: bool,
+    /// Minutes after local midnight when the window opens
+    pub start_minute: u32,
+    /// Minutes after local midnight when it closes. Earlier than
+    /// `start_minute` spans midnight; equal means all day.
+    pub end_minute: u32,
+}
+
+impl DownloadSchedule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.start_minute >= MINUTES_PER_DAY || self.end_minute >= MINUTES_PER_DAY {
+            return Err("Schedule times must be between 00:00 and 23:59".into());
+        }
+        Ok(())
+    }
+
+    /// Whether downloads may run right now.
+    pub fn is_open(&self) -> bool {
+        let now = chrono::Local::now();
+        self.is_open_at(now.hour() * 60 + now.minute())
+    }
+
+    fn is_open_at(&self, minute: u32) -> bool {
+        if !self.enabled || self.start_minute == self.end_minute {
+            return true;
+        }
+        if self.start_minute < self.end_minute {
+            (self.start_minute..self.end_minute).contains(&minute)
+        } else {
+            minute >= self.start_minute || minute < self.end_minute
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch, the unit of `not_before` times.
+pub fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
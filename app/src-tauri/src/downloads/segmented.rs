@@ -0,0 +1,423 @@
+use std::io::SeekFrom;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use futures_util::future::try_join_all;
+use futures_util::StreamExt;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, StatusCode};
+use tauri::AppHandle;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use super::db::{DownloadDb, RangeState, RemoteValidators};
+use super::error::DownloadError;
+use super::events::{emit_progress, ProgressPayload};
+use super::file_store;
+use super::http::HttpClient;
+use super::manager::commit_complete;
+use super::notifier;
+use super::tasks::TaskHandle;
+use super::throttle::Throttle;
+
+/// Number of parallel connections used for a single direct download.
+const CONNECTIONS: i64 = 4;
+/// Files smaller than this are fetched over a single stream.
+pub const MIN_SEGMENTED_SIZE: i64 = 16 * 1024 * 1024;
+/// Smallest byte range handed to one connection.
+const MIN_RANGE_SIZE: i64 = 8 * 1024 * 1024;
+/// How often (in bytes) a worker persists its range offset.
+const PERSIST_EVERY: i64 = 4 * 1024 * 1024;
+
+/// Checks whether the server honours byte-range requests.
+/// Returns the file's validators, with its total size, if a `bytes=0-0` probe
+/// answers `206` with a complete `Content-Range`, otherwise `None`.
+pub async fn probe_range_support(client: &Client, url: &str) -> Option<RemoteValidators> {
+    let resp = client
+        .get(url)
+        .header("Range", "bytes=0-0")
+        .timeout(std::time::Duration::from_secs(15))
+        .send()
+        .await
+        .ok()?;
+    if resp.status() != StatusCode::PARTIAL_CONTENT {
+        return None;
+    }
+    let total = resp
+        .headers()
+        .get("content-range")
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_content_range)
+        .and_then(|(_, _, total)| total)?;
+    Some(validators_from_headers(resp.headers(), Some(total)))
+}
+
+/// Reads the ETag and Last-Modified of a response.
+pub fn validators_from_headers(headers: &HeaderMap, total_size: Option<i64>) -> RemoteValidators {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    RemoteValidators {
+        etag: header("etag"),
+        last_modified: header("last-modified"),
+        total_size,
+    }
+}
+
+/// Parses `Content-Range: bytes 100-199/12345` into `(first, last, total)`.
+/// The total is `None` when the server sends `*`.
+pub fn parse_content_range(value: &str) -> Option<(i64, i64, Option<i64>)> {
+    let (range, total) = value.trim().strip_prefix("bytes")?.trim().split_once('/')?;
+    let (first, last) = range.trim().split_once('-')?;
+    let total = match total.trim() {
+        "*" => None,
+        t => Some(t.parse::<i64>().ok().filter(|t| *t > 0)?),
+    };
+    Some((first.trim().parse().ok()?, last.trim().parse().ok()?, total))
+}
+
+/// Splits `[resume_from, total)` into evenly sized ranges, one per connection.
+/// Bytes before `resume_from` (left by an earlier single-stream attempt) are
+/// recorded as an already-completed leading range.
+fn plan_ranges(total: i64, resume_from: i64) -> Vec<RangeState> {
+    let mut ranges = Vec::new();
+    if resume_from > 0 {
+        ranges.push(RangeState {
+            index: 0,
+            start_byte: 0,
+            end_byte: resume_from - 1,
+            downloaded: resume_from,
+        });
+    }
+
+    let remaining = total - resume_from;
+    let count = (remaining / MIN_RANGE_SIZE).clamp(1, CONNECTIONS);
+    let chunk = remaining / count;
+    let mut start = resume_from;
+    for i in 0..count {
+        let end = if i == count - 1 {
+            total - 1
+        } else {
+            start + chunk - 1
+        };
+        ranges.push(RangeState {
+            index: ranges.len() as i64,
+            start_byte: start,
+            end_byte: end,
+            downloaded: 0,
+        });
+        start = end + 1;
+    }
+    ranges
+}
+
+/// Bytes of an interrupted segmented download that a restart will keep:
+/// `(downloaded, total_size)`. None without a range plan. A plan that no
+/// longer fits its part file or the recorded remote file counts as nothing
+/// downloaded, like `download_segmented` would treat it.
+pub fn saved_progress(db: &DownloadDb, part_path: &Path, id: &str) -> Option<(i64, i64)> {
+    let ranges = db.get_ranges(id).ok()?;
+    let total_size = ranges.last()?.end_byte + 1;
+    let stored = db.get_validators(id).unwrap_or_default();
+    let usable = part_path.exists()
+        && stored.if_range().is_some()
+        && stored.total_size.map_or(true, |s| s == total_size);
+    if !usable {
+        return Some((0, total_size));
+    }
+    let downloaded = ranges.iter().map(|r| r.downloaded.min(r.size())).sum();
+    Some((downloaded, total_size))
+}
+
+/// Aggregated progress across all range workers of one download.
+struct SharedProgress {
+    total_size: i64,
+    downloaded: i64,
+    last_progress: f64,
+    last_notif_progress: u8,
+    last_notif_time: Instant,
+    started: Instant,
+    bytes_this_session: i64,
+}
+
+/// Downloads a direct file over several connections, one per byte range,
+/// writing each range into its slot of the preallocated `.zentrio-part` file.
+#[allow(clippy::too_many_arguments)]
+pub async fn download_segmented(
+    app: AppHandle,
+    db: Arc<Mutex<DownloadDb>>,
+    task: &TaskHandle,
+    client: &HttpClient,
+    throttle: &Throttle,
+    id: &str,
+    profile_id: &str,
+    title: &str,
+    stream_url: &str,
+    total_size: i64,
+    remote: &RemoteValidators,
+) -> Result<(), DownloadError> {
+    let part_path = file_store::part_file_path(&app, profile_id, id);
+    let final_path = file_store::download_file_path(&app, profile_id, id);
+
+    let (mut saved, stored) = {
+        let d = db.lock().map_err(|_| "DB lock poisoned".to_string())?;
+        (
+            d.get_ranges(id).map_err(|e| e.to_string())?,
+            d.get_validators(id).map_err(|e| e.to_string())?,
+        )
+    };
+
+    // Bytes on disk from a different file behind the same URL are garbage
+    if !stored.same_file(remote) && (part_path.exists() || !saved.is_empty()) {
+        log::warn!(
+            "[Downloads] Remote file for {} changed since the last attempt. Restarting.",
+            id
+        );
+        let _ = tokio::fs::remove_file(&part_path).await;
+        saved.clear();
+    }
+    db.lock()
+        .map_err(|_| "DB lock poisoned".to_string())?
+        .update_validators(id, remote)
+        .map_err(|e| e.to_string())?;
+
+    // Reuse the saved plan only if it still describes a file of the same size
+    let plan_matches = !saved.is_empty()
+        && saved.last().map(|r| r.end_byte + 1) == Some(total_size)
+        && part_path.exists();
+    let ranges = if plan_matches {
+        saved
+    } else {
+        // A part file without a range plan comes from a single-stream attempt;
+        // keep its prefix as long as it fits inside the remote file.
+        let existing = if saved.is_empty() && part_path.exists() {
+            file_store::file_size(&part_path)
+        } else {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            0
+        };
+        let resume_from = if existing < total_size { existing } else { 0 };
+        let ranges = plan_ranges(total_size, resume_from);
+        db.lock()
+            .map_err(|_| "DB lock poisoned".to_string())?
+            .save_ranges(id, &ranges)
+            .map_err(|e| e.to_string())?;
+        ranges
+    };
+
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&part_path)
+        .await?;
+    file.set_len(total_size as u64).await?;
+    drop(file);
+
+    let already_done: i64 = ranges.iter().map(|r| r.downloaded.min(r.size())).sum();
+    let progress = Arc::new(Mutex::new(SharedProgress {
+        total_size,
+        downloaded: already_done,
+        last_progress: -10.0,
+        last_notif_progress: 0,
+        last_notif_time: Instant::now(),
+        started: Instant::now(),
+        bytes_this_session: 0,
+    }));
+
+    let workers = ranges.iter().filter(|r| !r.is_complete()).map(|range| {
+        download_range(
+            &app,
+            &db,
+            task,
+            client,
+            throttle,
+            &progress,
+            id,
+            title,
+            stream_url,
+            remote.if_range(),
+            &part_path,
+            range.clone(),
+        )
+    });
+
+    try_join_all(workers).await?;
+
+    // Workers return early when stopped — leave the part file and range plan in place
+    if task.is_stopped() {
+        return Ok(());
+    }
+
+    commit_complete(
+        &app,
+        &db,
+        task,
+        id,
+        title,
+        &[(part_path, final_path.clone())],
+        &final_path,
+        |d| {
+            d.clear_ranges(id).ok();
+        },
+    )?;
+    Ok(())
+}
+
+/// Fetches the missing tail of one byte range and writes it at its offset.
+#[allow(clippy::too_many_arguments)]
+async fn download_range(
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    task: &TaskHandle,
+    client: &HttpClient,
+    throttle: &Throttle,
+    progress: &Arc<Mutex<SharedProgress>>,
+    id: &str,
+    title: &str,
+    stream_url: &str,
+    if_range: Option<&str>,
+    part_path: &Path,
+    mut range: RangeState,
+) -> Result<(), DownloadError> {
+    let from = range.start_byte + range.downloaded;
+    let _permit = client.host_permit(stream_url).await;
+    let mut req = client
+        .get(stream_url)
+        .header("Range", format!("bytes={}-{}", from, range.end_byte));
+    if let Some(validator) = if_range {
+        req = req.header("If-Range", validator);
+    }
+    let response = req.send().await?;
+
+    // With If-Range, a full 200 response means the file changed underneath us
+    if response.status() == StatusCode::OK && if_range.is_some() {
+        return Err(DownloadError::RemoteChanged);
+    }
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(DownloadError::Http(response.status().as_u16()));
+    }
+    let served_from = response
+        .headers()
+        .get("content-range")
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_content_range)
+        .map(|(first, _, _)| first);
+    if served_from != Some(from) {
+        return Err(format!(
+            "Server answered bytes {}-{} with a different range",
+            from, range.end_byte
+        )
+        .into());
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(part_path)
+        .await?;
+    file.seek(SeekFrom::Start(from as u64)).await?;
+
+    let mut unpersisted: i64 = 0;
+    let mut stream = response.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        if task.is_stopped() {
+            break;
+        }
+
+        let chunk = chunk?;
+
+        // Never write past the end of this range, even if the server overshoots
+        let room = (range.size() - range.downloaded).max(0) as usize;
+        let chunk = &chunk[..chunk.len().min(room)];
+        file.write_all(chunk).await?;
+        throttle.consume(chunk.len()).await;
+        range.downloaded += chunk.len() as i64;
+        unpersisted += chunk.len() as i64;
+
+        if unpersisted >= PERSIST_EVERY {
+            unpersisted = 0;
+            file.flush().await?;
+            if let Ok(d) = db.lock() {
+                d.update_range_progress(id, range.index, range.downloaded)
+                    .ok();
+            }
+        }
+
+        report_progress(app, db, progress, id, title, chunk.len() as i64);
+
+        if range.is_complete() {
+            break;
+        }
+    }
+
+    file.flush().await?;
+    if let Ok(d) = db.lock() {
+        d.update_range_progress(id, range.index, range.downloaded)
+            .ok();
+    }
+
+    if !task.is_stopped() && !range.is_complete() {
+        return Err(DownloadError::Network(format!(
+            "Connection closed after {} of {} bytes in range {}",
+            range.downloaded,
+            range.size(),
+            range.index
+        )));
+    }
+    Ok(())
+}
+
+/// Adds `bytes` to the shared counter and emits progress ~every 1%.
+fn report_progress(
+    app: &AppHandle,
+    db: &Arc<Mutex<DownloadDb>>,
+    progress: &Arc<Mutex<SharedProgress>>,
+    id: &str,
+    title: &str,
+    bytes: i64,
+) {
+    let mut state = match progress.lock() {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    state.downloaded += bytes;
+    state.bytes_this_session += bytes;
+
+    let pct = (state.downloaded as f64 / state.total_size as f64 * 100.0).min(100.0);
+    if (pct - state.last_progress) < 1.0 {
+        return;
+    }
+    state.last_progress = pct;
+
+    if let Ok(d) = db.lock() {
+        d.update_progress(id, pct, state.downloaded).ok();
+    }
+
+    let elapsed_secs = state.started.elapsed().as_secs_f64().max(0.001);
+    let speed = state.bytes_this_session as f64 / elapsed_secs;
+
+    emit_progress(
+        app,
+        ProgressPayload {
+            id: id.to_string(),
+            progress: pct,
+            downloaded_bytes: state.downloaded,
+            speed,
+        },
+    );
+
+    // OS notification every 10% or every 30 seconds
+    let pct_u8 = pct as u8;
+    if pct_u8 / 10 > state.last_notif_progress / 10
+        || state.last_notif_time.elapsed().as_secs() >= 30
+    {
+        state.last_notif_progress = pct_u8;
+        state.last_notif_time = Instant::now();
+        notifier::notify_progress(app, id, title, pct_u8, speed / 1024.0);
+    }
+}
@@ -0,0 +1,316 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::db::DownloadStatus;
+
+/// Why a download is paused.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseReason {
+    /// Paused from the UI; resumes only on request
+    User,
+    /// Outside the download window; resumes when it opens
+    Schedule,
+    DiskFull,
+    Network,
+    /// The profile's storage quota is used up
+    Quota,
+}
+
+impl PauseReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Schedule => "schedule",
+            Self::DiskFull => "disk_full",
+            Self::Network => "network",
+            Self::Quota => "quota",
+        }
+    }
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "user" => Some(Self::User),
+            "schedule" => Some(Self::Schedule),
+            "disk_full" => Some(Self::DiskFull),
+            "network" => Some(Self::Network),
+            "quota" => Some(Self::Quota),
+            _ => None,
+        }
+    }
+}
+
+/// Something that happens to a download. Every status change goes through
+/// `next`, which knows the legal ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// The first bytes arrived
+    Start,
+    Pause(PauseReason),
+    /// Back into the queue on request — after a pause, a failure or a
+    /// cancellation, or to download a missing file again
+    Resume,
+    /// A fresh stream URL arrived for a download whose URL expired
+    NewUrl,
+    /// Interrupted by shutdown; starts again from the queue
+    Interrupt,
+    Complete,
+    Fail,
+    /// Failed transiently; retried after a backoff delay
+    Retry,
+    /// The backoff delay of a retry elapsed
+    Requeue,
+    /// The stream URL expired
+    AwaitUrl,
+    Cancel,
+    /// The finished file was deleted or changed outside the app
+    FileMissing,
+    /// The file of a missing download is back at its recorded size
+    FileFound,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    /// `event` is not allowed while the download is `from`
+    IllegalTransition {
+        from: DownloadStatus,
+        event: Event,
+    },
+    /// A stored status string this version does not know
+    UnknownStatus(String),
+    NotFound(String),
+    Db(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IllegalTransition { from, event } => {
+                write!(
+                    f,
+                    "Cannot {} a download that is {}",
+                    verb(event),
+                    from.as_str()
+                )
+            }
+            Self::UnknownStatus(status) => write!(f, "Unknown download status: {status}"),
+            Self::NotFound(id) => write!(f, "Download not found: {id}"),
+            Self::Db(e) => write!(f, "Database error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<rusqlite::Error> for StateError {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::Db(e.to_string())
+    }
+}
+
+fn verb(event: &Event) -> &'static str {
+    match event {
+        Event::Start => "start",
+        Event::Pause(_) => "pause",
+        Event::Resume => "resume",
+        Event::NewUrl => "update the URL of",
+        Event::Interrupt => "interrupt",
+        Event::Complete => "complete",
+        Event::Fail => "fail",
+        Event::Retry => "retry",
+        Event::Requeue => "re-queue",
+        Event::AwaitUrl => "park",
+        Event::Cancel => "cancel",
+        Event::FileMissing => "mark missing",
+        Event::FileFound => "restore",
+    }
+}
+
+/// The status a download moves to when `event` happens while it is `from`.
+pub fn next(from: &DownloadStatus, event: Event) -> Result<DownloadStatus, StateError> {
+    use DownloadStatus::*;
+
+    let to = match (from, event) {
+        (Queued, Event::Start) => Downloading,
+
+        // Pausing again only updates the reason
+        (Queued | Downloading | Retrying | AwaitingUrl | Paused, Event::Pause(_)) => Paused,
+
+        (Paused | Failed | Cancelled | Retrying | AwaitingUrl | Missing, Event::Resume) => Queued,
+        (AwaitingUrl, Event::NewUrl) => Queued,
+        (Downloading | Queued, Event::Interrupt) => Queued,
+
+        // A small file can finish before its first progress report
+        (Queued | Downloading, Event::Complete) => Completed,
+        (Queued | Downloading, Event::Fail) => Failed,
+        (Queued | Downloading, Event::Retry) => Retrying,
+        (Queued | Downloading, Event::AwaitUrl) => AwaitingUrl,
+        (Retrying, Event::Requeue) => Queued,
+        (Completed, Event::FileMissing) => Missing,
+        (Missing, Event::FileFound) => Completed,
+
+        // Completed downloads are deleted, not cancelled
+        (
+            Queued | Downloading | Paused | Failed | Retrying | AwaitingUrl | Cancelled,
+            Event::Cancel,
+        ) => Cancelled,
+
+        (from, event) => {
+            return Err(StateError::IllegalTransition {
+                from: from.clone(),
+                event,
+            })
+        }
+    };
+    Ok(to)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DownloadStatus::*;
+
+    const ALL: [DownloadStatus; 9] = [
+        Queued,
+        Downloading,
+        Paused,
+        Completed,
+        Failed,
+        Cancelled,
+        Retrying,
+        AwaitingUrl,
+        Missing,
+    ];
+
+    /// Every status an event is legal from, and where it leads.
+    fn table(event: Event) -> Vec<(DownloadStatus, DownloadStatus)> {
+        match event {
+            Event::Start => vec![(Queued, Downloading)],
+            Event::Pause(_) => vec![
+                (Queued, Paused),
+                (Downloading, Paused),
+                (Paused, Paused),
+                (Retrying, Paused),
+                (AwaitingUrl, Paused),
+            ],
+            Event::Resume => vec![
+                (Paused, Queued),
+                (Failed, Queued),
+                (Cancelled, Queued),
+                (Retrying, Queued),
+                (AwaitingUrl, Queued),
+                (Missing, Queued),
+            ],
+            Event::NewUrl => vec![(AwaitingUrl, Queued)],
+            Event::Interrupt => vec![(Queued, Queued), (Downloading, Queued)],
+            Event::Complete => vec![(Queued, Completed), (Downloading, Completed)],
+            Event::Fail => vec![(Queued, Failed), (Downloading, Failed)],
+            Event::Retry => vec![(Queued, Retrying), (Downloading, Retrying)],
+            Event::Requeue => vec![(Retrying, Queued)],
+            Event::AwaitUrl => vec![(Queued, AwaitingUrl), (Downloading, AwaitingUrl)],
+            Event::Cancel => vec![
+                (Queued, Cancelled),
+                (Downloading, Cancelled),
+                (Paused, Cancelled),
+                (Failed, Cancelled),
+                (Cancelled, Cancelled),
+                (Retrying, Cancelled),
+                (AwaitingUrl, Cancelled),
+            ],
+            Event::FileMissing => vec![(Completed, Missing)],
+            Event::FileFound => vec![(Missing, Completed)],
+        }
+    }
+
+    #[test]
+    fn transition_table() {
+        let events = [
+            Event::Start,
+            Event::Pause(PauseReason::User),
+            Event::Pause(PauseReason::DiskFull),
+            Event::Resume,
+            Event::NewUrl,
+            Event::Interrupt,
+            Event::Complete,
+            Event::Fail,
+            Event::Retry,
+            Event::Requeue,
+            Event::AwaitUrl,
+            Event::Cancel,
+            Event::FileMissing,
+            Event::FileFound,
+        ];
+        for event in events {
+            let legal = table(event);
+            for from in ALL {
+                let expected = legal
+                    .iter()
+                    .find(|(f, _)| *f == from)
+                    .map(|(_, to)| to.clone());
+                match (next(&from, event), expected) {
+                    (Ok(to), Some(expected)) => assert_eq!(to, expected, "{from:?} + {event:?}"),
+                    (Err(e), None) => assert_eq!(
+                        e,
+                        StateError::IllegalTransition {
+                            from: from.clone(),
+                            event
+                        }
+                    ),
+                    (got, expected) => {
+                        panic!("{from:?} + {event:?}: got {got:?}, expected {expected:?}")
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn completed_cannot_restart() {
+        for event in [
+            Event::Start,
+            Event::Pause(PauseReason::User),
+            Event::Resume,
+            Event::Fail,
+            Event::Cancel,
+        ] {
+            assert!(next(&Completed, event).is_err());
+        }
+    }
+
+    #[test]
+    fn illegal_transition_message() {
+        let err = next(&Completed, Event::Resume).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cannot resume a download that is completed"
+        );
+    }
+
+    #[test]
+    fn status_strings_round_trip() {
+        for status in ALL {
+            assert_eq!(
+                DownloadStatus::from_str(status.as_str()),
+                Ok(status.clone())
+            );
+        }
+        assert_eq!(
+            DownloadStatus::from_str("exploded"),
+            Err(StateError::UnknownStatus("exploded".into()))
+        );
+    }
+
+    #[test]
+    fn pause_reason_strings_round_trip() {
+        for reason in [
+            PauseReason::User,
+            PauseReason::Schedule,
+            PauseReason::DiskFull,
+            PauseReason::Network,
+            PauseReason::Quota,
+        ] {
+            assert_eq!(PauseReason::from_str(reason.as_str()), Some(reason));
+        }
+        assert_eq!(PauseReason::from_str(""), None);
+    }
+}
@@ -1,8 +1,11 @@
-use reqwest::Client;
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 use tauri::AppHandle;
 
 use super::file_store;
+use super::http::HttpClient;
+use super::throttle::Throttle;
 
 /// A subtitle track entry, matching the frontend Stream.subtitles format.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,19 +31,14 @@ pub async fn download_subtitles(
     profile_id: &str,
     download_id: &str,
     subtitle_urls_json: &str,
+    client: &HttpClient,
+    throttle: &Throttle,
 ) -> Option<String> {
     let entries: Vec<SubtitleEntry> = serde_json::from_str(subtitle_urls_json).ok()?;
     if entries.is_empty() {
         return None;
     }
 
-    let client = Client::builder()
-        .user_agent("Zentrio/1.0")
-        .connect_timeout(std::time::Duration::from_secs(10))
-        .read_timeout(std::time::Duration::from_secs(30))
-        .build()
-        .ok()?;
-
     let mut downloaded: Vec<SubtitlePathEntry> = Vec::new();
 
     for entry in &entries {
@@ -58,6 +56,7 @@ pub async fn download_subtitles(
         match client.get(&entry.url).send().await {
             Ok(resp) if resp.status().is_success() => {
                 if let Ok(bytes) = resp.bytes().await {
+                    throttle.consume(bytes.len()).await;
                     if tokio::fs::write(&path, &bytes).await.is_ok() {
                         downloaded.push(SubtitlePathEntry {
                             lang: entry.lang.clone(),
@@ -94,3 +93,190 @@ pub async fn download_subtitles(
 
     serde_json::to_string(&downloaded).ok()
 }
+
+/// Adds newly downloaded subtitle files to a stored `subtitle_paths` JSON
+/// array, skipping paths it already lists.
+pub fn merge_subtitle_paths(existing: Option<&str>, added: &[SubtitlePathEntry]) -> Option<String> {
+    let mut entries: Vec<SubtitlePathEntry> = existing
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default();
+    for entry in added {
+        if !entries.iter().any(|e| e.path == entry.path) {
+            entries.push(entry.clone());
+        }
+    }
+    if entries.is_empty() {
+        return None;
+    }
+    serde_json::to_string(&entries).ok()
+}
+
+// ─── Segmented WebVTT ─────────────────────────────────────────────────────────
+
+/// A WebVTT cue with its times in milliseconds.
+struct Cue {
+    start: i64,
+    end: i64,
+    settings: String,
+    text: String,
+}
+
+/// Stitches the WebVTT segments of an HLS subtitle rendition into one file.
+///
+/// Each segment's cues are moved onto the video timeline using its
+/// `X-TIMESTAMP-MAP`, relative to `reference_pts` (the first 90 kHz timestamp
+/// of the video). Without one, the first segment's mapping is taken as the
+/// start. Cues repeated across segment boundaries are merged.
+pub fn stitch_webvtt(segments: &[String], reference_pts: Option<i64>) -> String {
+    let mut reference = reference_pts;
+    let mut header_blocks: Vec<String> = Vec::new();
+    let mut cues: Vec<Cue> = Vec::new();
+    // Last cue index per (settings, text), to join cues split at segment boundaries
+    let mut last_by_text: HashMap<(String, String), usize> = HashMap::new();
+
+    for (n, segment) in segments.iter().enumerate() {
+        let normalized = segment
+            .trim_start_matches('\u{feff}')
+            .replace("\r\n", "\n")
+            .replace('\r', "\n");
+        let mut blocks = normalized
+            .split("\n\n")
+            .map(|b| b.trim_matches('\n'))
+            .filter(|b| !b.is_empty());
+        let header = match blocks.next() {
+            Some(h) if h.starts_with("WEBVTT") => h,
+            _ => {
+                log::warn!("[Subtitles] Skipping WebVTT segment {n} without a header");
+                continue;
+            }
+        };
+
+        let offset = match timestamp_map(header) {
+            Some((mpegts, local)) => {
+                let base = *reference.get_or_insert(mpegts - local * 90);
+                pts_diff(mpegts, base) / 90 - local
+            }
+            None => 0,
+        };
+
+        for block in blocks {
+            if block.starts_with("STYLE") || block.starts_with("REGION") {
+                if !header_blocks.iter().any(|b| b == block) {
+                    header_blocks.push(block.to_string());
+                }
+                continue;
+            }
+            let mut lines = block.lines();
+            let timing = match lines.by_ref().find(|l| l.contains("-->")) {
+                Some(t) => t,
+                // NOTE blocks, or a cue without timings
+                None => continue,
+            };
+            let (start, end, settings) = match parse_timing(timing) {
+                Some(t) => t,
+                None => continue,
+            };
+            let (start, end) = ((start + offset).max(0), end + offset);
+            if end <= start {
+                continue;
+            }
+            let text = lines.collect::<Vec<_>>().join("\n");
+
+            let key = (settings.clone(), text.clone());
+            if let Some(&i) = last_by_text.get(&key) {
+                let prev = &mut cues[i];
+                if start <= prev.end + 1 {
+                    prev.start = prev.start.min(start);
+                    prev.end = prev.end.max(end);
+                    continue;
+                }
+            }
+            last_by_text.insert(key, cues.len());
+            cues.push(Cue {
+                start,
+                end,
+                settings,
+                text,
+            });
+        }
+    }
+
+    cues.sort_by_key(|c| c.start);
+    let mut out = String::from("WEBVTT\n\n");
+    for block in header_blocks {
+        out.push_str(&block);
+        out.push_str("\n\n");
+    }
+    for cue in cues {
+        out.push_str(&format_timestamp(cue.start));
+        out.push_str(" --> ");
+        out.push_str(&format_timestamp(cue.end));
+        if !cue.settings.is_empty() {
+            out.push(' ');
+            out.push_str(&cue.settings);
+        }
+        out.push('\n');
+        out.push_str(&cue.text);
+        out.push_str("\n\n");
+    }
+    out
+}
+
+/// Parses `X-TIMESTAMP-MAP=MPEGTS:<90 kHz>,LOCAL:<cue time>` from a segment
+/// header. Returns `(mpegts, local_ms)`.
+fn timestamp_map(header: &str) -> Option<(i64, i64)> {
+    let value = header
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("X-TIMESTAMP-MAP="))?;
+    let mut mpegts = None;
+    let mut local = None;
+    for part in value.split(',') {
+        if let Some(v) = part.trim().strip_prefix("MPEGTS:") {
+            mpegts = v.trim().parse::<i64>().ok();
+        } else if let Some(v) = part.trim().strip_prefix("LOCAL:") {
+            local = parse_timestamp(v.trim());
+        }
+    }
+    Some((mpegts?, local.unwrap_or(0)))
+}
+
+/// Difference between two 33-bit MPEG-TS timestamps, across wraparound.
+fn pts_diff(pts: i64, base: i64) -> i64 {
+    const WRAP: i64 = 1 << 33;
+    let diff = (pts - base).rem_euclid(WRAP);
+    if diff > WRAP / 2 {
+        diff - WRAP
+    } else {
+        diff
+    }
+}
+
+/// Parses a cue timing line into `(start_ms, end_ms, settings)`.
+fn parse_timing(line: &str) -> Option<(i64, i64, String)> {
+    let (start, rest) = line.split_once("-->")?;
+    let mut rest = rest.split_whitespace();
+    let end = parse_timestamp(rest.next()?)?;
+    let settings = rest.collect::<Vec<_>>().join(" ");
+    Some((parse_timestamp(start.trim())?, end, settings))
+}
+
+/// Parses a WebVTT timestamp (`hh:mm:ss.ttt` or `mm:ss.ttt`) into milliseconds.
+fn parse_timestamp(ts: &str) -> Option<i64> {
+    let (clock, millis) = ts.split_once('.')?;
+    let millis: i64 = millis.parse().ok()?;
+    let mut seconds = 0i64;
+    for part in clock.split(':') {
+        seconds = seconds * 60 + part.parse::<i64>().ok()?;
+    }
+    Some(seconds * 1000 + millis)
+}
+
+fn format_timestamp(ms: i64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio_util::sync::CancellationToken;
+
+/// Why a running download was told to stop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    /// Paused by the user or the download window; the record has the reason
+    Pause,
+    /// Cancelled or deleted
+    Cancel,
+}
+
+/// One running download. Engines check it between chunks; the manager also
+/// races the whole engine against it, so a stop drops in-flight requests.
+#[derive(Clone)]
+pub struct TaskHandle {
+    token: CancellationToken,
+    reason: Arc<Mutex<Option<StopReason>>>,
+}
+
+impl TaskHandle {
+    fn new() -> Self {
+        TaskHandle {
+            token: CancellationToken::new(),
+            reason: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Resolves once the download is told to stop.
+    pub async fn stopped(&self) {
+        self.token.cancelled().await
+    }
+
+    pub fn reason(&self) -> Option<StopReason> {
+        self.reason.lock().ok().and_then(|r| *r)
+    }
+
+    /// A cancel overrides an earlier pause; a pause never replaces a cancel.
+    fn stop(&self, reason: StopReason) {
+        if let Ok(mut current) = self.reason.lock() {
+            if *current != Some(StopReason::Cancel) {
+                *current = Some(reason);
+            }
+        }
+        self.token.cancel();
+    }
+}
+
+/// The downloads currently running, by id.
+#[derive(Default)]
+pub struct TaskRegistry {
+    tasks: Mutex<HashMap<String, TaskHandle>>,
+}
+
+impl TaskRegistry {
+    /// Registers a download as running and returns its handle.
+    pub fn start(&self, id: &str) -> TaskHandle {
+        let handle = TaskHandle::new();
+        if let Ok(mut tasks) = self.tasks.lock() {
+            tasks.insert(id.to_string(), handle.clone());
+        }
+        handle
+    }
+
+    /// Removes a download once its task has returned. A newer task started
+    /// under the same id in the meantime stays registered.
+    pub fn finish(&self, id: &str, handle: &TaskHandle) {
+        if let Ok(mut tasks) = self.tasks.lock() {
+            if tasks
+                .get(id)
+                .is_some_and(|t| Arc::ptr_eq(&t.reason, &handle.reason))
+            {
+                tasks.remove(id);
+            }
+        }
+    }
+
+    /// Tells a running download to stop. Returns false if it is not running.
+    pub fn stop(&self, id: &str, reason: StopReason) -> bool {
+        match self.tasks.lock().ok().and_then(|t| t.get(id).cloned()) {
+            Some(handle) => {
+                handle.stop(reason);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_running(&self, id: &str) -> bool {
+        self.tasks
+            .lock()
+            .map(|t| t.contains_key(id))
+            .unwrap_or(false)
+    }
+
+    /// Ids of running downloads, including ones still winding down after a stop.
+    pub fn running(&self) -> Vec<String> {
+        self.tasks
+            .lock()
+            .map(|t| t.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Ids of running downloads that have not been told to stop.
+    pub fn active(&self) -> Vec<String> {
+        self.tasks
+            .lock()
+            .map(|t| {
+                t.iter()
+                    .filter(|(_, h)| !h.is_stopped())
+                    .map(|(id, _)| id.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_overrides_pause() {
+        let tasks = TaskRegistry::default();
+        let handle = tasks.start("a");
+        assert!(tasks.stop("a", StopReason::Pause));
+        assert!(tasks.stop("a", StopReason::Cancel));
+        assert_eq!(handle.reason(), Some(StopReason::Cancel));
+        assert!(tasks.stop("a", StopReason::Pause));
+        assert_eq!(handle.reason(), Some(StopReason::Cancel));
+    }
+}
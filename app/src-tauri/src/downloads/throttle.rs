@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Smallest bucket size, so low limits still admit a whole network chunk.
+const MIN_BURST_BYTES: f64 = 64.0 * 1024.0;
+
+/// Token bucket holding up to one second of transfer. Bytes are charged after
+/// they arrive, so the balance may go negative; the caller then waits until
+/// the debt is repaid.
+struct Bucket {
+    /// Bytes per second; 0 = unlimited
+    rate: u64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Self {
+        let mut bucket = Bucket {
+            rate,
+            tokens: 0.0,
+            refilled: Instant::now(),
+        };
+        bucket.tokens = bucket.capacity();
+        bucket
+    }
+
+    fn capacity(&self) -> f64 {
+        (self.rate as f64).max(MIN_BURST_BYTES)
+    }
+
+    /// A new limit starts without debt run up under the old one.
+    fn set_rate(&mut self, rate: u64) {
+        self.refill();
+        self.rate = rate;
+        self.tokens = self.tokens.clamp(0.0, self.capacity());
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.capacity());
+        self.refilled = now;
+    }
+
+    /// Charges `bytes` and returns how long to wait before taking more.
+    fn take(&mut self, bytes: usize) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+        self.refill();
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
+}
+
+/// Bandwidth limits shared by every download engine: one global cap plus
+/// optional per-download caps. Limits can change at any time and apply from
+/// the next chunk of in-flight downloads.
+pub struct RateLimiter {
+    global: Mutex<Bucket>,
+    downloads: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// `global_limit` in bytes per second; 0 = unlimited.
+    pub fn new(global_limit: u64) -> Self {
+        RateLimiter {
+            global: Mutex::new(Bucket::new(global_limit)),
+            downloads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn global_limit(&self) -> u64 {
+        self.global.lock().map(|b| b.rate).unwrap_or(0)
+    }
+
+    pub fn set_global_limit(&self, bytes_per_sec: u64) {
+        if let Ok(mut bucket) = self.global.lock() {
+            bucket.set_rate(bytes_per_sec);
+        }
+    }
+
+    /// Caps one download; 0 removes its cap.
+    pub fn set_download_limit(&self, id: &str, bytes_per_sec: u64) {
+        let mut downloads = match self.downloads.lock() {
+            Ok(d) => d,
+            Err(_) => return,
+        };
+        if bytes_per_sec == 0 {
+            downloads.remove(id);
+        } else if let Some(bucket) = downloads.get_mut(id) {
+            bucket.set_rate(bytes_per_sec);
+        } else {
+            downloads.insert(id.to_string(), Bucket::new(bytes_per_sec));
+        }
+    }
+
+    /// Forgets a download's cap once its task has ended.
+    pub fn remove(&self, id: &str) {
+        if let Ok(mut downloads) = self.downloads.lock() {
+            downloads.remove(id);
+        }
+    }
+
+    /// Handle through which one download's engine reports transferred bytes.
+    pub fn for_download(self: &Arc<Self>, id: &str) -> Throttle {
+        Throttle {
+            limiter: Arc::clone(self),
+            id: id.to_string(),
+        }
+    }
+
+    async fn consume(&self, id: &str, bytes: usize) {
+        let global = self
+            .global
+            .lock()
+            .map(|mut b| b.take(bytes))
+            .unwrap_or_default();
+        let own = self
+            .downloads
+            .lock()
+            .ok()
+            .and_then(|mut d| d.get_mut(id).map(|b| b.take(bytes)))
+            .unwrap_or_default();
+        let wait = global.max(own);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// One download's share of the `RateLimiter`.
+#[derive(Clone)]
+pub struct Throttle {
+    limiter: Arc<RateLimiter>,
+    id: String,
+}
+
+impl Throttle {
+    /// Accounts for `bytes` just received, sleeping while over a limit.
+    pub async fn consume(&self, bytes: usize) {
+        self.limiter.consume(&self.id, bytes).await;
+    }
+}
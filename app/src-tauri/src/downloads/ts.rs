@@ -0,0 +1,346 @@
+pub const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+
+pub const PAT_PID: u16 = 0x0000;
+
+/// Header fields and body slices of one 188-byte TS packet.
+pub struct Packet<'a> {
+    pub pid: u16,
+    /// payload_unit_start_indicator — a new PES packet or PSI section starts here
+    pub pusi: bool,
+    pub continuity: u8,
+    /// Adaptation field contents, without its length byte.
+    pub adaptation: Option<&'a [u8]>,
+    pub payload: &'a [u8],
+}
+
+/// Returns true if `data` looks like a packet-aligned transport stream.
+pub fn is_transport_stream(data: &[u8]) -> bool {
+    data.len() >= PACKET_SIZE
+        && data[0] == SYNC_BYTE
+        && (data.len() < PACKET_SIZE * 2 || data[PACKET_SIZE] == SYNC_BYTE)
+}
+
+pub fn parse_packet(buf: &[u8]) -> Option<Packet<'_>> {
+    if buf.len() < PACKET_SIZE || buf[0] != SYNC_BYTE {
+        return None;
+    }
+    let pusi = buf[1] & 0x40 != 0;
+    let pid = ((buf[1] as u16 & 0x1f) << 8) | buf[2] as u16;
+    let afc = (buf[3] >> 4) & 0x03;
+    let continuity = buf[3] & 0x0f;
+
+    let mut pos = 4;
+    let mut adaptation = None;
+    if afc & 0x02 != 0 {
+        let len = buf[4] as usize;
+        if 5 + len > PACKET_SIZE {
+            return None;
+        }
+        adaptation = Some(&buf[5..5 + len]);
+        pos = 5 + len;
+    }
+    let payload = if afc & 0x01 != 0 {
+        &buf[pos..PACKET_SIZE]
+    } else {
+        &[]
+    };
+    Some(Packet {
+        pid,
+        pusi,
+        continuity,
+        adaptation,
+        payload,
+    })
+}
+
+/// Byte offset of a PSI section inside a TS packet, and the section's end.
+fn psi_section_bounds(packet: &[u8]) -> Option<(usize, usize)> {
+    let pkt = parse_packet(packet)?;
+    if !pkt.pusi || pkt.payload.is_empty() {
+        return None;
+    }
+    let payload_offset = PACKET_SIZE - pkt.payload.len();
+    let start = payload_offset + 1 + pkt.payload[0] as usize;
+    if start + 3 > PACKET_SIZE {
+        return None;
+    }
+    let section_length = (((packet[start + 1] & 0x0f) as usize) << 8) | packet[start + 2] as usize;
+    let end = start + 3 + section_length;
+    if end > PACKET_SIZE || section_length < 9 {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// Returns the PMT PIDs announced by a PAT packet.
+pub fn parse_pat(packet: &[u8]) -> Vec<u16> {
+    let (start, end) = match psi_section_bounds(packet) {
+        Some(b) if packet[b.0] == 0x00 => b,
+        _ => return Vec::new(),
+    };
+    let mut pids = Vec::new();
+    let mut pos = start + 8;
+    while pos + 4 <= end - 4 {
+        let program_number = ((packet[pos] as u16) << 8) | packet[pos + 1] as u16;
+        let pid = ((packet[pos + 2] as u16 & 0x1f) << 8) | packet[pos + 3] as u16;
+        if program_number != 0 {
+            pids.push(pid);
+        }
+        pos += 4;
+    }
+    pids
+}
+
+/// An elementary stream listed in a PMT.
+pub struct PmtStream {
+    pub stream_type: u8,
+    pub pid: u16,
+    /// Offset of the `stream_type` byte within the packet
+    pub type_offset: usize,
+}
+
+/// Returns the elementary streams listed in a PMT packet.
+pub fn parse_pmt(packet: &[u8]) -> Vec<PmtStream> {
+    let (start, end) = match psi_section_bounds(packet) {
+        Some(b) if packet[b.0] == 0x02 => b,
+        _ => return Vec::new(),
+    };
+    let program_info_length =
+        (((packet[start + 10] & 0x0f) as usize) << 8) | packet[start + 11] as usize;
+    let mut streams = Vec::new();
+    let mut pos = start + 12 + program_info_length;
+    while pos + 5 <= end - 4 {
+        let stream_type = packet[pos];
+        let pid = ((packet[pos + 1] as u16 & 0x1f) << 8) | packet[pos + 2] as u16;
+        let es_info_length = (((packet[pos + 3] & 0x0f) as usize) << 8) | packet[pos + 4] as usize;
+        streams.push(PmtStream {
+            stream_type,
+            pid,
+            type_offset: pos,
+        });
+        pos += 5 + es_info_length;
+    }
+    streams
+}
+
+/// Recomputes the CRC of the PSI section carried in `packet` after it was edited.
+pub fn fix_psi_crc(packet: &mut [u8]) {
+    if let Some((start, end)) = psi_section_bounds(packet) {
+        let crc = crc32_mpeg2(&packet[start..end - 4]);
+        packet[end - 4..end].copy_from_slice(&crc.to_be_bytes());
+    }
+}
+
+/// CRC-32/MPEG-2 as used by PSI sections.
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Splits a PES packet into TS packets for `pid`, appending them to `out`.
+/// The first packet carries `adaptation` (e.g. the PCR of the original packet);
+/// the last one is padded with adaptation-field stuffing.
+pub fn packetize_pes(
+    pid: u16,
+    continuity: &mut u8,
+    adaptation: Option<&[u8]>,
+    pes: &[u8],
+    out: &mut Vec<u8>,
+) {
+    let mut rest = pes;
+    let mut first = true;
+    while first || !rest.is_empty() {
+        let mut af: Option<Vec<u8>> = if first {
+            adaptation.map(|a| a.to_vec())
+        } else {
+            None
+        };
+        let af_overhead = af.as_ref().map(|a| 1 + a.len()).unwrap_or(0);
+        let room = (PACKET_SIZE - 4).saturating_sub(af_overhead);
+        let take = room.min(rest.len());
+
+        // Pad short packets through the adaptation field
+        let stuffing = room - take;
+        if stuffing > 0 {
+            match af.as_mut() {
+                Some(a) if a.is_empty() => {
+                    a.push(0x00);
+                    a.resize(stuffing, 0xff);
+                }
+                Some(a) => a.resize(a.len() + stuffing, 0xff),
+                None if stuffing == 1 => af = Some(Vec::new()),
+                None => {
+                    let mut a = vec![0x00];
+                    a.resize(stuffing - 1, 0xff);
+                    af = Some(a);
+                }
+            }
+        }
+
+        let afc = if af.is_some() { 0x20 } else { 0x00 } | if take > 0 { 0x10 } else { 0x00 };
+        out.push(SYNC_BYTE);
+        out.push(if first { 0x40 } else { 0x00 } | ((pid >> 8) as u8 & 0x1f));
+        out.push(pid as u8);
+        out.push(afc | (*continuity & 0x0f));
+        if let Some(a) = af {
+            out.push(a.len() as u8);
+            out.extend_from_slice(&a);
+        }
+        out.extend_from_slice(&rest[..take]);
+
+        if take > 0 {
+            *continuity = (*continuity + 1) & 0x0f;
+        }
+        rest = &rest[take..];
+        first = false;
+    }
+}
+
+/// Length of the PES header (up to the start of the payload), if `pes` starts
+/// with a PES packet that carries the optional header used by audio/video streams.
+pub fn pes_header_len(pes: &[u8]) -> Option<usize> {
+    if pes.len() < 9 || pes[0..3] != [0x00, 0x00, 0x01] {
+        return None;
+    }
+    let len = 9 + pes[8] as usize;
+    (len <= pes.len()).then_some(len)
+}
+
+/// Returns the `(pts, dts)` of a PES packet header, as raw 33-bit 90 kHz values.
+pub fn pes_timestamps(pes: &[u8]) -> (Option<i64>, Option<i64>) {
+    if pes_header_len(pes).is_none() {
+        return (None, None);
+    }
+    let flags = pes[7] >> 6;
+    let pts = if flags & 0x02 != 0 {
+        pes.get(9..14).map(read_timestamp)
+    } else {
+        None
+    };
+    let dts = if flags == 0x03 {
+        pes.get(14..19).map(read_timestamp)
+    } else {
+        None
+    };
+    (pts, dts)
+}
+
+fn read_timestamp(b: &[u8]) -> i64 {
+    (((b[0] as i64 >> 1) & 0x07) << 30)
+        | ((b[1] as i64) << 22)
+        | ((b[2] as i64 >> 1) << 15)
+        | ((b[3] as i64) << 7)
+        | (b[4] as i64 >> 1)
+}
+
+/// Positions just past each `00 00 01` start code in an Annex B byte stream.
+pub fn start_code_ends(data: &[u8]) -> Vec<usize> {
+    let mut ends = Vec::new();
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            ends.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    ends
+}
+
+pub fn remove_emulation_prevention(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+    out
+}
+
+/// Returns `(header_len, frame_len)` for an ADTS (AAC) frame at the start of `data`.
+pub fn adts_frame(data: &[u8]) -> Option<(usize, usize)> {
+    if data.len() < 7 || data[0] != 0xff || data[1] & 0xf0 != 0xf0 {
+        return None;
+    }
+    let header_len = if data[1] & 0x01 != 0 { 7 } else { 9 };
+    let frame_len =
+        (((data[3] & 0x03) as usize) << 11) | ((data[4] as usize) << 3) | (data[5] as usize >> 5);
+    (frame_len >= header_len).then_some((header_len, frame_len))
+}
+
+/// AC-3 frame sizes in 16-bit words, indexed by `[frmsizecod][fscod]`.
+const AC3_FRAME_WORDS: [[u16; 3]; 38] = [
+    [64, 69, 96],
+    [64, 70, 96],
+    [80, 87, 120],
+    [80, 88, 120],
+    [96, 104, 144],
+    [96, 105, 144],
+    [112, 121, 168],
+    [112, 122, 168],
+    [128, 139, 192],
+    [128, 140, 192],
+    [160, 174, 240],
+    [160, 175, 240],
+    [192, 208, 288],
+    [192, 209, 288],
+    [224, 243, 336],
+    [224, 244, 336],
+    [256, 278, 384],
+    [256, 279, 384],
+    [320, 348, 480],
+    [320, 349, 480],
+    [384, 417, 576],
+    [384, 418, 576],
+    [448, 487, 672],
+    [448, 488, 672],
+    [512, 557, 768],
+    [512, 558, 768],
+    [640, 696, 960],
+    [640, 697, 960],
+    [768, 835, 1152],
+    [768, 836, 1152],
+    [896, 975, 1344],
+    [896, 976, 1344],
+    [1024, 1114, 1536],
+    [1024, 1115, 1536],
+    [1152, 1253, 1728],
+    [1152, 1254, 1728],
+    [1280, 1393, 1920],
+    [1280, 1394, 1920],
+];
+
+/// Returns the byte length of an AC-3 or E-AC-3 sync frame at the start of `data`.
+pub fn ac3_frame_len(data: &[u8]) -> Option<usize> {
+    if data.len() < 6 || data[0] != 0x0b || data[1] != 0x77 {
+        return None;
+    }
+    let bsid = data[5] >> 3;
+    if bsid > 10 {
+        // E-AC-3: frmsiz is the frame size in words minus one
+        let frmsiz = (((data[2] & 0x07) as usize) << 8) | data[3] as usize;
+        return Some((frmsiz + 1) * 2);
+    }
+    let fscod = (data[4] >> 6) as usize;
+    let frmsizecod = (data[4] & 0x3f) as usize;
+    if fscod > 2 || frmsizecod >= AC3_FRAME_WORDS.len() {
+        return None;
+    }
+    Some(AC3_FRAME_WORDS[frmsizecod][fscod] as usize * 2)
+}
@@ -11,9 +11,15 @@ mod downloads;
 mod plugins;
 
 use downloads::{
-    db::DownloadDb,
+    db::{DownloadDb, DownloadQuality},
     file_store,
+    headers::{self, RequestHeaders},
     manager::{DownloadManager, StartDownloadPayload},
+    probe::{self, StreamProbe},
+    proxy::ProxySettings,
+    quality::{DeviceProfile, QualityTargets},
+    reconcile::ReconcileReport,
+    schedule::DownloadSchedule,
 };
 
 struct ServerPort(Mutex<u16>);
@@ -34,6 +40,24 @@ fn download_start(
     state.enqueue(app, payload)
 }
 
+#[tauri::command]
+async fn download_probe(
+    state: tauri::State<'_, Arc<DownloadManager>>,
+    url: String,
+    quality: Option<String>,
+    max_height: Option<u64>,
+    preferred_codecs: Option<Vec<String>>,
+    sdr_only: Option<bool>,
+    request_headers: Option<RequestHeaders>,
+) -> Result<StreamProbe, String> {
+    let quality = DownloadQuality::from_str(quality.as_deref().unwrap_or("standard"));
+    let targets = QualityTargets::new(&quality, max_height, preferred_codecs, sdr_only)
+        .with_device(&state.get_device_profile()?);
+    let headers = headers::header_map(&request_headers.unwrap_or_default())?;
+    let client = state.client(&headers)?;
+    probe::probe_stream(&url, &client, &targets, quality.as_str()).await
+}
+
 #[tauri::command]
 fn download_pause(
     app: tauri::AppHandle,
@@ -61,6 +85,16 @@ fn download_cancel(
     state.cancel(app, &id)
 }
 
+#[tauri::command]
+fn download_update_url(
+    app: tauri::AppHandle,
+    state: tauri::State<Arc<DownloadManager>>,
+    id: String,
+    stream_url: String,
+) -> Result<(), String> {
+    state.update_url(app, &id, &stream_url)
+}
+
 #[tauri::command]
 fn download_delete(
     app: tauri::AppHandle,
@@ -121,6 +155,15 @@ fn download_purge_profile(
     state.delete_all_for_profile(app, &profile_id)
 }
 
+#[tauri::command]
+fn download_reconcile(
+    app: tauri::AppHandle,
+    state: tauri::State<Arc<DownloadManager>>,
+    clean: Option<bool>,
+) -> Result<ReconcileReport, String> {
+    state.reconcile(app, clean.unwrap_or(false))
+}
+
 #[tauri::command]
 fn download_set_directory(app: tauri::AppHandle, path: String) -> Result<(), String> {
     let requested_path = std::path::Path::new(&path);
@@ -213,6 +256,152 @@ fn download_set_smart_defaults(
     state.set_smart_defaults(&profile_id, smart_download, auto_delete)
 }
 
+#[tauri::command]
+fn download_get_global_rate_limit(state: tauri::State<Arc<DownloadManager>>) -> u64 {
+    state.get_global_rate_limit()
+}
+
+#[tauri::command]
+fn download_set_global_rate_limit(
+    state: tauri::State<Arc<DownloadManager>>,
+    bytes_per_sec: u64,
+) -> Result<(), String> {
+    state.set_global_rate_limit(bytes_per_sec)
+}
+
+#[tauri::command]
+fn download_set_rate_limit(
+    state: tauri::State<Arc<DownloadManager>>,
+    id: String,
+    bytes_per_sec: u64,
+) -> Result<(), String> {
+    state.set_rate_limit(&id, bytes_per_sec)
+}
+
+#[tauri::command]
+fn download_get_schedule(
+    state: tauri::State<Arc<DownloadManager>>,
+) -> Result<DownloadSchedule, String> {
+    state.get_schedule()
+}
+
+#[tauri::command]
+fn download_set_schedule(
+    app: tauri::AppHandle,
+    state: tauri::State<Arc<DownloadManager>>,
+    schedule: DownloadSchedule,
+) -> Result<(), String> {
+    state.set_schedule(app, schedule)
+}
+
+#[tauri::command]
+fn download_set_start_after(
+    app: tauri::AppHandle,
+    state: tauri::State<Arc<DownloadManager>>,
+    id: String,
+    not_before: Option<i64>,
+) -> Result<(), String> {
+    state.set_start_after(app, &id, not_before)
+}
+
+#[tauri::command]
+fn download_get_profile_start_after(
+    state: tauri::State<Arc<DownloadManager>>,
+    profile_id: String,
+) -> Result<Option<i64>, String> {
+    state.get_profile_start_after(&profile_id)
+}
+
+#[tauri::command]
+fn download_set_profile_start_after(
+    app: tauri::AppHandle,
+    state: tauri::State<Arc<DownloadManager>>,
+    profile_id: String,
+    start_after: Option<i64>,
+) -> Result<(), String> {
+    state.set_profile_start_after(app, &profile_id, start_after)
+}
+
+#[tauri::command]
+fn download_move(
+    app: tauri::AppHandle,
+    state: tauri::State<Arc<DownloadManager>>,
+    id: String,
+    position: usize,
+) -> Result<(), String> {
+    state.move_in_queue(app, &id, position)
+}
+
+#[tauri::command]
+fn download_set_priority(
+    app: tauri::AppHandle,
+    state: tauri::State<Arc<DownloadManager>>,
+    id: String,
+    priority: String,
+) -> Result<(), String> {
+    state.set_priority(app, &id, &priority)
+}
+
+#[tauri::command]
+fn download_get_max_concurrent(state: tauri::State<Arc<DownloadManager>>) -> usize {
+    state.get_max_concurrent()
+}
+
+#[tauri::command]
+fn download_set_max_concurrent(
+    app: tauri::AppHandle,
+    state: tauri::State<Arc<DownloadManager>>,
+    max_concurrent: usize,
+) -> Result<(), String> {
+    state.set_max_concurrent(app, max_concurrent)
+}
+
+#[tauri::command]
+fn download_get_proxy(state: tauri::State<Arc<DownloadManager>>) -> Result<ProxySettings, String> {
+    state.get_proxy()
+}
+
+#[tauri::command]
+fn download_set_proxy(
+    state: tauri::State<Arc<DownloadManager>>,
+    proxy: ProxySettings,
+) -> Result<(), String> {
+    state.set_proxy(proxy)
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProxyTestResult {
+    pub status: u16,
+    pub elapsed_ms: u64,
+}
+
+/// Checks proxy settings before they are saved by fetching `url` (or a
+/// connectivity-check URL) through them.
+#[tauri::command]
+async fn download_test_proxy(
+    proxy: ProxySettings,
+    url: Option<String>,
+) -> Result<ProxyTestResult, String> {
+    let (status, elapsed_ms) = proxy.test(url.as_deref()).await?;
+    Ok(ProxyTestResult { status, elapsed_ms })
+}
+
+#[tauri::command]
+fn download_get_device_profile(
+    state: tauri::State<Arc<DownloadManager>>,
+) -> Result<DeviceProfile, String> {
+    state.get_device_profile()
+}
+
+#[tauri::command]
+fn download_set_device_profile(
+    state: tauri::State<Arc<DownloadManager>>,
+    profile: DeviceProfile,
+) -> Result<(), String> {
+    state.set_device_profile(profile)
+}
+
 // ─────────────────────────────────────────────────────────────────────────────
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -338,19 +527,39 @@ pub fn run() {
             plugins::mobile_bridge::command_immersive_mode_set_player_mode,
             // Download commands
             download_start,
+            download_probe,
             download_pause,
             download_resume,
             download_cancel,
+            download_update_url,
             download_delete,
             download_list,
             download_storage_stats,
             download_purge_profile,
+            download_reconcile,
             download_set_directory,
             download_get_directory,
             download_get_quota,
             download_set_quota,
             download_get_smart_defaults,
             download_set_smart_defaults,
+            download_get_global_rate_limit,
+            download_set_global_rate_limit,
+            download_set_rate_limit,
+            download_get_schedule,
+            download_set_schedule,
+            download_set_start_after,
+            download_get_profile_start_after,
+            download_set_profile_start_after,
+            download_move,
+            download_set_priority,
+            download_get_max_concurrent,
+            download_set_max_concurrent,
+            download_get_proxy,
+            download_set_proxy,
+            download_test_proxy,
+            download_get_device_profile,
+            download_set_device_profile,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
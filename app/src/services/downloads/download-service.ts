@@ -1,7 +1,9 @@
 import { invoke } from '@tauri-apps/api/core'
 
-export type DownloadStatus = 'queued' | 'downloading' | 'paused' | 'completed' | 'failed' | 'cancelled'
+export type DownloadStatus = 'queued' | 'downloading' | 'paused' | 'completed' | 'failed' | 'cancelled' | 'retrying' | 'awaiting_url' | 'missing'
 export type DownloadQuality = 'standard' | 'higher' | 'best'
+export type DownloadPriority = 'high' | 'normal' | 'low'
+export type PauseReason = 'user' | 'schedule' | 'disk_full' | 'network' | 'quota'
 
 export interface DownloadRecord {
   id: string
@@ -17,6 +19,8 @@ export interface DownloadRecord {
   /** Local path to downloaded episode thumbnail/still image */
   thumbnailPath?: string
   status: DownloadStatus
+  /** Why the download is paused; set only while `status` is 'paused' */
+  pauseReason?: PauseReason
   progress: number
   quality: DownloadQuality
   filePath: string
@@ -31,6 +35,11 @@ export interface DownloadRecord {
   streamUrl: string
   addonId: string
   errorMessage?: string
+  /** Automatic retries since the download last started or was resumed */
+  retryCount?: number
+  priority?: DownloadPriority
+  /** Index in the download queue; 0 starts next */
+  queuePosition?: number
   smartDownload: boolean
   autoDelete: boolean
   /** Original subtitle URLs from the stream response (stored for re-download on resume) */
@@ -60,6 +69,172 @@ export interface StartDownloadPayload {
   autoDelete?: boolean
   /** Subtitle tracks from the stream — downloaded alongside the video for offline use */
   subtitleUrls?: Array<{ url: string; lang: string }>
+  /** HLS segments fetched in parallel (undefined = engine default) */
+  segmentConcurrency?: number
+  /** Preferred HLS audio languages (BCP-47), most preferred first (undefined = the stream's default) */
+  audioLanguages?: string[]
+  /** Override the tallest video `quality` allows */
+  maxHeight?: number
+  /** Codec families in order of preference ('hevc', 'h264', …) */
+  preferredCodecs?: string[]
+  /** Avoid HDR variants when an SDR one exists */
+  sdrOnly?: boolean
+  /** Bandwidth cap for this download in bytes/s (undefined = only the global cap applies) */
+  rateLimit?: number
+  /** Do not start before this time, in ms since the epoch */
+  notBefore?: number
+  /** Where it joins the queue (undefined = 'normal') */
+  priority?: DownloadPriority
+  /** `id` of a variant from `probe` — downloads exactly that one (undefined = pick by `quality`) */
+  variantId?: string
+  /** Extra HTTP headers for every request, e.g. the stream's `behaviorHints.proxyHeaders.request` */
+  requestHeaders?: Record<string, string>
+  /** `size` from `download_probe` — checked against free disk space before the download starts */
+  expectedSize?: number
+}
+
+/** One selectable variant: an HLS variant stream or a DASH representation */
+export interface ProbeVariant {
+  /** Passed back as `variantId` in `start` */
+  id: string
+  width?: number
+  height?: number
+  codecs?: string
+  /** Bits per second */
+  bandwidth: number
+  frameRate?: number
+  /** Bandwidth × duration, when the duration is known */
+  estimatedSize?: number
+  hdr: boolean
+  /** Whether the device profile can play it */
+  playable: boolean
+}
+
+/** What `probe` learned about a stream URL before enqueueing it */
+export interface StreamProbe {
+  kind: 'hls' | 'dash' | 'file'
+  /** 'ts', 'fmp4', 'mp4', 'mkv', 'webm' … when known */
+  container?: string
+  contentType?: string
+  /** Size in bytes: exact for files, estimated for the selected variant */
+  size?: number
+  sizeExact: boolean
+  /** Whether the server honours byte ranges; unset for HLS / DASH, which resume per segment */
+  acceptRanges?: boolean
+  /** An interrupted download continues instead of starting over */
+  resumable: boolean
+  durationSecs?: number
+  /** Highest bandwidth first */
+  variants: ProbeVariant[]
+  /** `id` of the variant `start` picks for the probed quality */
+  selectedVariant?: string
+}
+
+export interface ProbeOptions {
+  /** Quality the selected variant is picked for (default 'standard') */
+  quality?: DownloadQuality
+  maxHeight?: number
+  preferredCodecs?: string[]
+  sdrOnly?: boolean
+  /** Headers the download will send, e.g. `requestHeaders` of the start payload */
+  requestHeaders?: Record<string, string>
+}
+
+/** What this device can play; applied to every download */
+export interface DeviceProfile {
+  /** Codec families the device can decode (empty = any) */
+  supportedCodecs: string[]
+  /** Tallest video the device should download (undefined = no limit) */
+  maxHeight?: number
+  hdrSupported: boolean
+}
+
+/**
+ * Payload of `download:url_expired`: the stream URL stopped working (HTTP 403 / 410).
+ * Re-resolve the stream through the addon and pass it to `updateUrl`.
+ */
+export interface UrlExpiredEvent {
+  id: string
+  profileId: string
+  mediaType: 'movie' | 'series'
+  mediaId: string
+  episodeId?: string
+  title: string
+  episodeTitle?: string
+  season?: number
+  episode?: number
+  addonId: string
+  quality: DownloadQuality
+}
+
+/** Daily window in which downloads may run; outside it they wait or pause */
+export interface DownloadSchedule {
+  /** When false, downloads run at any time */
+  enabled: boolean
+  /** Minutes after local midnight when the window opens */
+  startMinute: number
+  /** Minutes after local midnight when it closes; earlier than `startMinute` spans midnight, equal means all day */
+  endMinute: number
+}
+
+/** Payload of `download:queue`, sent whenever the queue order changes */
+export interface QueueEvent {
+  items: Array<{
+    id: string
+    /** 0 = next to start */
+    position: number
+    priority: DownloadPriority
+  }>
+}
+
+/** 'system' = environment / OS proxy settings, 'none' = direct, 'manual' = `url` */
+export type ProxyMode = 'system' | 'none' | 'manual'
+
+/** How download requests reach the network */
+export interface ProxySettings {
+  mode: ProxyMode
+  /** http://, https://, socks5:// or socks5h:// (remote DNS) proxy URL */
+  url: string
+  username?: string
+  password?: string
+  /** Hosts reached directly, comma separated: 'localhost, .lan, 10.0.0.0/8' */
+  noProxy: string
+}
+
+export interface ProxyTestResult {
+  /** HTTP status of the test request */
+  status: number
+  elapsedMs: number
+}
+
+/** A file in the downloads directory that no download needs */
+export interface OrphanedFile {
+  path: string
+  size: number
+  /** The download that left it behind, if its record still exists */
+  downloadId?: string
+}
+
+/** A completed download whose file is gone or no longer its recorded size */
+export interface MissingFile {
+  id: string
+  profileId: string
+  title: string
+  filePath: string
+  expectedSize: number
+  /** Unset when the file does not exist */
+  actualSize?: number
+}
+
+export interface ReconcileReport {
+  orphanedFiles: OrphanedFile[]
+  /** Total size of `orphanedFiles` */
+  orphanedBytes: number
+  missing: MissingFile[]
+  /** Ids of missing downloads whose file is back at its recorded size */
+  restored: string[]
+  /** Orphans were deleted and the records updated */
+  cleaned: boolean
 }
 
 export interface StorageStats {
@@ -78,6 +253,10 @@ export const downloadService = {
     return invoke<string>('download_start', { payload })
   },
 
+  probe(url: string, options: ProbeOptions = {}): Promise<StreamProbe> {
+    return invoke<StreamProbe>('download_probe', { url, ...options })
+  },
+
   pause(id: string): Promise<void> {
     return invoke('download_pause', { id })
   },
@@ -90,6 +269,10 @@ export const downloadService = {
     return invoke('download_cancel', { id })
   },
 
+  updateUrl(id: string, streamUrl: string): Promise<void> {
+    return invoke('download_update_url', { id, streamUrl })
+  },
+
   delete(id: string): Promise<void> {
     return invoke('download_delete', { id })
   },
@@ -106,6 +289,11 @@ export const downloadService = {
     return invoke('download_purge_profile', { profileId })
   },
 
+  /** Check the downloads directory against the records; with `clean`, delete orphans and mark missing files */
+  reconcile(clean = false): Promise<ReconcileReport> {
+    return invoke<ReconcileReport>('download_reconcile', { clean })
+  },
+
   setDirectory(path: string): Promise<void> {
     return invoke('download_set_directory', { path })
   },
@@ -129,4 +317,78 @@ export const downloadService = {
   setSmartDefaults(profileId: string, smartDownload: boolean, autoDelete: boolean): Promise<void> {
     return invoke('download_set_smart_defaults', { profileId, smartDownload, autoDelete })
   },
+
+  /** Device-wide bandwidth cap in bytes/s (0 = unlimited) */
+  getGlobalRateLimit(): Promise<number> {
+    return invoke<number>('download_get_global_rate_limit')
+  },
+
+  setGlobalRateLimit(bytesPerSec: number): Promise<void> {
+    return invoke('download_set_global_rate_limit', { bytesPerSec })
+  },
+
+  /** Per-download cap in bytes/s (0 = only the global cap applies); also applies while running */
+  setRateLimit(id: string, bytesPerSec: number): Promise<void> {
+    return invoke('download_set_rate_limit', { id, bytesPerSec })
+  },
+
+  /** Move a queued download to `position` (0 = next to start), clamped to the end of the queue */
+  move(id: string, position: number): Promise<void> {
+    return invoke('download_move', { id, position })
+  },
+
+  setPriority(id: string, priority: DownloadPriority): Promise<void> {
+    return invoke('download_set_priority', { id, priority })
+  },
+
+  getMaxConcurrent(): Promise<number> {
+    return invoke<number>('download_get_max_concurrent')
+  },
+
+  setMaxConcurrent(maxConcurrent: number): Promise<void> {
+    return invoke('download_set_max_concurrent', { maxConcurrent })
+  },
+
+  getSchedule(): Promise<DownloadSchedule> {
+    return invoke<DownloadSchedule>('download_get_schedule')
+  },
+
+  setSchedule(schedule: DownloadSchedule): Promise<void> {
+    return invoke('download_set_schedule', { schedule })
+  },
+
+  /** Hold a queued download until `notBefore` (ms since the epoch; null = no hold) */
+  setStartAfter(id: string, notBefore: number | null): Promise<void> {
+    return invoke('download_set_start_after', { id, notBefore })
+  },
+
+  getProfileStartAfter(profileId: string): Promise<number | null> {
+    return invoke<number | null>('download_get_profile_start_after', { profileId })
+  },
+
+  /** Hold every queued download of a profile until `startAfter` (null = no hold) */
+  setProfileStartAfter(profileId: string, startAfter: number | null): Promise<void> {
+    return invoke('download_set_profile_start_after', { profileId, startAfter })
+  },
+
+  getProxy(): Promise<ProxySettings> {
+    return invoke<ProxySettings>('download_get_proxy')
+  },
+
+  setProxy(proxy: ProxySettings): Promise<void> {
+    return invoke('download_set_proxy', { proxy })
+  },
+
+  /** Fetch `url` (or a connectivity-check URL) through `proxy` before saving it */
+  testProxy(proxy: ProxySettings, url?: string): Promise<ProxyTestResult> {
+    return invoke<ProxyTestResult>('download_test_proxy', { proxy, url })
+  },
+
+  getDeviceProfile(): Promise<DeviceProfile> {
+    return invoke<DeviceProfile>('download_get_device_profile')
+  },
+
+  setDeviceProfile(profile: DeviceProfile): Promise<void> {
+    return invoke('download_set_device_profile', { profile })
+  },
 }